@@ -1,3 +1,18 @@
 fn main() {
     cc::Build::new().file("c-src/logging.c").compile("shims");
+    #[cfg(feature = "use_capi")]
+    {
+        println!("cargo::rerun-if-changed=src/capi.rs");
+        println!("cargo::rerun-if-changed=cbindgen.toml");
+        // The committed include/retro_rs.h is checked against this copy by the capi tests
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml")).unwrap();
+        cbindgen::Builder::new()
+            .with_src(format!("{crate_dir}/src/capi.rs"))
+            .with_config(config)
+            .generate()
+            .expect("Unable to generate C header")
+            .write_to_file(format!("{out_dir}/retro_rs.h"));
+    }
 }
@@ -1,21 +1,34 @@
 #include <stdio.h>
 #include <stdarg.h>
+#include <stdlib.h>
 
+/* Implemented in Rust (src/emulator.rs) */
+void retrors_log_message(int level, const char *msg);
 
 void retrors_log_print(int level, const char *fmt, ...) {
+    char stack_buf[1024];
+    char *heap_buf;
     va_list va_args;
-    if (level == 0) {
-      printf("[DBG]: ");
-    } else if(level == 1) {
-      printf("[INF]: ");
-    } else if(level == 2) {
-      printf("[WRN]: ");
-    } else if(level == 3) {
-      printf("[ERR]: ");
-    } else {
-      printf("[%03d]: ", level);
+    int len;
+    va_start(va_args, fmt);
+    len = vsnprintf(stack_buf, sizeof(stack_buf), fmt, va_args);
+    va_end(va_args);
+    if (len < 0) {
+      return;
+    }
+    if ((size_t)len < sizeof(stack_buf)) {
+      retrors_log_message(level, stack_buf);
+      return;
+    }
+    heap_buf = malloc((size_t)len + 1);
+    if (heap_buf == NULL) {
+      /* Better a truncated message than none */
+      retrors_log_message(level, stack_buf);
+      return;
     }
     va_start(va_args, fmt);
-    vprintf(fmt, va_args);
+    vsnprintf(heap_buf, (size_t)len + 1, fmt, va_args);
     va_end(va_args);
+    retrors_log_message(level, heap_buf);
+    free(heap_buf);
 }
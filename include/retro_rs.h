@@ -0,0 +1,165 @@
+/* Generated by cbindgen from src/capi.rs with the use_capi feature; do not edit. */
+
+#ifndef RETRO_RS_H
+#define RETRO_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Result codes; every failure has a message available from [`retro_rs_last_error`].
+typedef enum RetroRsStatus {
+  RETRO_RS_STATUS_OK = 0,
+  // A pointer argument was null or a string wasn't UTF-8
+  RETRO_RS_STATUS_INVALID_ARGUMENT,
+  // The core couldn't be loaded or is missing libretro functions
+  RETRO_RS_STATUS_CORE_LOAD_FAILED,
+  // The ROM couldn't be read or the core rejected it
+  RETRO_RS_STATUS_CONTENT_FAILED,
+  RETRO_RS_STATUS_NO_FRAMEBUFFER,
+  // A caller's buffer is too small for the requested data
+  RETRO_RS_STATUS_BUFFER_TOO_SMALL,
+  // A memory address isn't mapped or a region index is out of range
+  RETRO_RS_STATUS_MEMORY_ACCESS_FAILED,
+  RETRO_RS_STATUS_STATE_SAVE_FAILED,
+  RETRO_RS_STATUS_STATE_LOAD_FAILED,
+  RETRO_RS_STATUS_CORE_OPTION_FAILED,
+  RETRO_RS_STATUS_OTHER,
+} RetroRsStatus;
+
+// An emulator created by [`retro_rs_emulator_create`].
+typedef struct RetroRsEmulator RetroRsEmulator;
+
+// A region of the core's memory map, see [`crate::MemoryRegion`].
+typedef struct RetroRsMemoryRegion {
+  uint64_t flags;
+  size_t start;
+  size_t len;
+  size_t offset;
+  size_t select;
+  size_t disconnect;
+} RetroRsMemoryRegion;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Copies the message for the last failure on this thread into `buf` as a NUL-terminated string,
+// truncating it to fit `len` bytes. Returns the message's full length, not counting the NUL.
+// # Safety
+// `buf` must be null or valid for writes of `len` bytes.
+size_t retro_rs_last_error(char *buf, size_t len);
+
+// Loads a core and ROM, storing the new emulator in `*out`.
+// # Safety
+// The paths must be null or NUL-terminated strings, and `out` must be null or valid for writes.
+enum RetroRsStatus retro_rs_emulator_create(const char *core_path,
+                                            const char *rom_path,
+                                            struct RetroRsEmulator **out);
+
+// # Safety
+// `emu` must be null or come from [`retro_rs_emulator_create`], and not be used afterwards.
+void retro_rs_emulator_destroy(struct RetroRsEmulator *emu);
+
+// Runs one frame with the given joypad button masks held on ports 0 and 1.
+// Bit `n` of a mask is libretro's `RETRO_DEVICE_ID_JOYPAD` button `n`.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`].
+void retro_rs_emulator_run(struct RetroRsEmulator *emu, int16_t port0, int16_t port1);
+
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`].
+void retro_rs_emulator_reset(struct RetroRsEmulator *emu);
+
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`].
+uint64_t retro_rs_emulator_frame_count(const struct RetroRsEmulator *emu);
+
+// The buffer size [`retro_rs_emulator_save`] needs.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`].
+size_t retro_rs_emulator_save_size(const struct RetroRsEmulator *emu);
+
+// Serializes the core's state into `buf`.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+enum RetroRsStatus retro_rs_emulator_save(const struct RetroRsEmulator *emu,
+                                          uint8_t *buf,
+                                          size_t len);
+
+// Restores a state produced by [`retro_rs_emulator_save`].
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for reads of `len` bytes.
+enum RetroRsStatus retro_rs_emulator_load(struct RetroRsEmulator *emu,
+                                          const uint8_t *buf,
+                                          size_t len);
+
+// Stores the last frame's size in `*width` and `*height`.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `width` and `height` must be valid for writes.
+void retro_rs_emulator_framebuffer_size(const struct RetroRsEmulator *emu,
+                                        size_t *width,
+                                        size_t *height);
+
+// Copies the last frame into `buf` with 3 bytes per pixel.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+enum RetroRsStatus retro_rs_emulator_copy_framebuffer_rgb888(const struct RetroRsEmulator *emu,
+                                                             uint8_t *buf,
+                                                             size_t len);
+
+// Copies the last frame into `buf` with 4 bytes per pixel.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+enum RetroRsStatus retro_rs_emulator_copy_framebuffer_rgba8888(const struct RetroRsEmulator *emu,
+                                                               uint8_t *buf,
+                                                               size_t len);
+
+// Copies the last frame into `buf` with 1 byte per pixel.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+enum RetroRsStatus retro_rs_emulator_copy_framebuffer_rgb332(const struct RetroRsEmulator *emu,
+                                                             uint8_t *buf,
+                                                             size_t len);
+
+// Points `*data` at system RAM and stores its length in `*len`; the pointer is valid until the emulator is destroyed.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `data` and `len` must be valid for writes.
+void retro_rs_emulator_system_ram(struct RetroRsEmulator *emu,
+                                  uint8_t **data,
+                                  size_t *len);
+
+// How many regions the core's memory map has.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`].
+size_t retro_rs_emulator_memory_region_count(const struct RetroRsEmulator *emu);
+
+// Stores memory region `index` in `*out`, and copies its name into `name` as
+// [`retro_rs_last_error`] copies messages if `name` isn't null.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], `out` must be valid for writes,
+// and `name` must be null or valid for writes of `name_len` bytes.
+enum RetroRsStatus retro_rs_emulator_memory_region(const struct RetroRsEmulator *emu,
+                                                   size_t index,
+                                                   struct RetroRsMemoryRegion *out,
+                                                   char *name,
+                                                   size_t name_len);
+
+// Copies `len` bytes of the core's memory map starting at address `start` into `buf`.
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+enum RetroRsStatus retro_rs_emulator_read_memory(const struct RetroRsEmulator *emu,
+                                                 size_t start,
+                                                 uint8_t *buf,
+                                                 size_t len);
+
+// # Safety
+// `emu` must come from [`retro_rs_emulator_create`], and `key` and `value` must be null or NUL-terminated strings.
+enum RetroRsStatus retro_rs_emulator_set_core_option(struct RetroRsEmulator *emu,
+                                                     const char *key,
+                                                     const char *value);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RETRO_RS_H */
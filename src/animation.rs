@@ -0,0 +1,252 @@
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use crate::recorder::place;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::{self, FilterType};
+use image::{Delay, DynamicImage, Frame, RgbImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// NeuQuant sampling factor per frame, trading palette quality for encoding time
+const GIF_SPEED: i32 = 10;
+// Browsers stretch GIF frames shorter than this many centiseconds
+const GIF_MIN_DELAY: u32 = 2;
+
+/// The container [`EpisodeExporter`] writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    /// Animated GIF, with a palette per frame and delays in whole centiseconds.
+    Gif,
+    /// Animated PNG, lossless and with exact delays.
+    Apng,
+}
+
+/// Collects frames across [`Emulator::run`] calls into a looping animation, e.g. to show an
+/// episode in a pull request. Frames are kept in memory until written.
+///
+/// Frames are shown for `frame_skip / fps` seconds each, where `fps` is
+/// [`Emulator::get_video_fps`]. GIF delays are rounded to centiseconds without drifting, but
+/// can't be shorter than 2cs, so a GIF plays no faster than 50 frames per second; skip frames to
+/// keep full-speed 60fps episodes in time. Frames of different sizes are centered on a black
+/// canvas as large as the biggest of them.
+#[derive(Debug, Clone)]
+pub struct EpisodeExporter {
+    frames: Vec<RgbImage>,
+    frame_skip: usize,
+    downscale: u32,
+    fps: f64,
+    captures: usize,
+}
+
+impl Default for EpisodeExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EpisodeExporter {
+    /// An exporter which keeps every frame at full size.
+    #[must_use]
+    pub fn new() -> Self {
+        EpisodeExporter {
+            frames: Vec::new(),
+            frame_skip: 1,
+            downscale: 1,
+            fps: 60.0,
+            captures: 0,
+        }
+    }
+    /// Keeps only the first of every `frames` captured frames.
+    /// # Panics
+    /// If `frames` is 0
+    #[must_use]
+    pub fn with_frame_skip(mut self, frames: usize) -> Self {
+        assert!(frames > 0, "frame skip must be at least 1");
+        self.frame_skip = frames;
+        self
+    }
+    /// Shrinks kept frames to `1/factor` of their width and height.
+    /// # Panics
+    /// If `factor` is 0
+    #[must_use]
+    pub fn with_downscale(mut self, factor: u32) -> Self {
+        assert!(factor > 0, "downscale factor must be at least 1");
+        self.downscale = factor;
+        self
+    }
+    /// The number of frames kept so far.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+    /// Takes `emu`'s last frame, if it isn't skipped; call this after every [`Emulator::run`].
+    /// # Errors
+    /// [`RetroRsError::ImageBufferError`]: The frame is too large
+    /// Others: See [`Emulator::copy_framebuffer_rgb888`]
+    pub fn capture(&mut self, emu: &Emulator) -> Result<(), RetroRsError> {
+        let skipped = !self.captures.is_multiple_of(self.frame_skip);
+        self.captures += 1;
+        if skipped {
+            return Ok(());
+        }
+        self.fps = emu.get_video_fps();
+        let (w, h) = emu.framebuffer_size();
+        let mut bytes = vec![0; w * h * 3];
+        emu.copy_framebuffer_rgb888(&mut bytes)?;
+        let frame = RgbImage::from_vec(w.try_into()?, h.try_into()?, bytes)
+            .ok_or(RetroRsError::ImageBufferError)?;
+        self.push(frame);
+        Ok(())
+    }
+    /// Forgets every frame, e.g. between episodes.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.captures = 0;
+    }
+    /// Writes the animation to a new file at `path`.
+    /// # Errors
+    /// [`RetroRsError::AnimationIOError`]: The file couldn't be created or written
+    /// Others: See [`EpisodeExporter::write`]
+    pub fn save(&self, path: &Path, format: AnimationFormat) -> Result<(), RetroRsError> {
+        let mut out = BufWriter::new(File::create(path).map_err(RetroRsError::AnimationIOError)?);
+        self.write(&mut out, format)?;
+        out.flush().map_err(RetroRsError::AnimationIOError)
+    }
+    /// Writes the animation to `out`.
+    /// # Errors
+    /// [`RetroRsError::AnimationEncodeError`]: No frames were captured, or encoding failed
+    pub fn write(&self, out: impl Write, format: AnimationFormat) -> Result<(), RetroRsError> {
+        if self.frames.is_empty() {
+            return Err(RetroRsError::AnimationEncodeError(
+                "no frames were captured".to_owned(),
+            ));
+        }
+        let frame_time = self.frame_skip as f64 / self.fps;
+        match format {
+            AnimationFormat::Gif => self.write_gif(out, frame_time),
+            AnimationFormat::Apng => self.write_apng(out, frame_time),
+        }
+        .map_err(RetroRsError::AnimationEncodeError)
+    }
+
+    fn push(&mut self, frame: RgbImage) {
+        let frame = if self.downscale == 1 {
+            frame
+        } else {
+            let w = (frame.width() / self.downscale).max(1);
+            let h = (frame.height() / self.downscale).max(1);
+            imageops::resize(&frame, w, h, FilterType::Triangle)
+        };
+        self.frames.push(frame);
+    }
+    fn canvas_size(&self) -> (u32, u32) {
+        self.frames.iter().fold((0, 0), |(w, h), frame| {
+            (w.max(frame.width()), h.max(frame.height()))
+        })
+    }
+    // Every frame centered on the canvas, as RGB888
+    fn canvas_frames(&self) -> impl Iterator<Item = Vec<u8>> {
+        let (w, h) = self.canvas_size();
+        self.frames.iter().map(move |frame| {
+            place(
+                frame.as_raw(),
+                (frame.width() as usize, frame.height() as usize),
+                (w as usize, h as usize),
+            )
+        })
+    }
+    fn write_gif(&self, out: impl Write, frame_time: f64) -> Result<(), String> {
+        let (w, h) = self.canvas_size();
+        let mut encoder = GifEncoder::new_with_speed(out, GIF_SPEED);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|err| err.to_string())?;
+        let delays = gif_delays(self.frames.len(), frame_time);
+        for (rgb, centis) in self.canvas_frames().zip(delays) {
+            let rgb = RgbImage::from_vec(w, h, rgb).ok_or("frame doesn't fit the canvas")?;
+            let rgba = DynamicImage::ImageRgb8(rgb).into_rgba8();
+            let delay = Delay::from_numer_denom_ms(centis * 10, 1);
+            encoder
+                .encode_frame(Frame::from_parts(rgba, 0, 0, delay))
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+    fn write_apng(&self, out: impl Write, frame_time: f64) -> Result<(), String> {
+        let (w, h) = self.canvas_size();
+        let mut encoder = png::Encoder::new(out, w, h);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let frames = u32::try_from(self.frames.len()).map_err(|err| err.to_string())?;
+        // Zero plays loops forever
+        encoder
+            .set_animated(frames, 0)
+            .map_err(|err| err.to_string())?;
+        let (numerator, denominator) = apng_delay(frame_time);
+        encoder
+            .set_frame_delay(numerator, denominator)
+            .map_err(|err| err.to_string())?;
+        let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+        for rgb in self.canvas_frames() {
+            writer
+                .write_image_data(&rgb)
+                .map_err(|err| err.to_string())?;
+        }
+        writer.finish().map_err(|err| err.to_string())
+    }
+}
+
+// Each frame's delay in centiseconds, rounded so the total never drifts from `frame_time * n`
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn gif_delays(frames: usize, frame_time: f64) -> Vec<u32> {
+    let mut shown = 0;
+    (1..=frames)
+        .map(|n| {
+            let due = (n as f64 * frame_time * 100.0).round() as u32;
+            let delay = due.saturating_sub(shown).max(GIF_MIN_DELAY);
+            shown += delay;
+            delay
+        })
+        .collect()
+}
+
+// `frame_time` in seconds as a fraction APNG can hold
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn apng_delay(frame_time: f64) -> (u16, u16) {
+    let max = f64::from(u16::MAX);
+    // The largest numerator whose denominator still fits gives the closest fraction
+    let numerator = (max * frame_time.min(1.0)).floor().max(1.0);
+    let denominator = (numerator / frame_time).round().clamp(1.0, max);
+    (numerator as u16, denominator as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_and_encoding() {
+        // 60fps with every other frame kept: 1/30s each, i.e. 3.33cs
+        assert_eq!(gif_delays(6, 2.0 / 60.0), [3, 4, 3, 3, 4, 3]);
+        // Too fast for GIF: slowed to 2cs per frame
+        assert_eq!(gif_delays(3, 1.0 / 60.0), [2, 2, 2]);
+        let (numerator, denominator) = apng_delay(1.0 / 60.0988);
+        assert!((f64::from(numerator) / f64::from(denominator) - 1.0 / 60.0988).abs() < 1e-7);
+
+        let mut exporter = EpisodeExporter::new().with_downscale(2);
+        exporter.push(RgbImage::from_pixel(4, 4, image::Rgb([255, 0, 0])));
+        exporter.push(RgbImage::from_pixel(2, 2, image::Rgb([0, 255, 0])));
+        assert_eq!(exporter.canvas_size(), (2, 2));
+        let mut gif = Vec::new();
+        exporter.write(&mut gif, AnimationFormat::Gif).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+        let mut apng = Vec::new();
+        exporter.write(&mut apng, AnimationFormat::Apng).unwrap();
+        assert!(apng.windows(4).any(|chunk| chunk == b"acTL"));
+    }
+}
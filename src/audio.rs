@@ -0,0 +1,252 @@
+use crate::emulator::Emulator;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// Input frames on each side of an output sample that the resampling filter looks at
+const FILTER_HALF_WIDTH: usize = 16;
+
+/// The channel layout [`AudioBuffer`] produces from the core's stereo samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioChannels {
+    /// Left and right averaged.
+    Mono,
+    /// Interleaved left and right, as cores produce them.
+    Stereo,
+}
+
+impl AudioChannels {
+    #[must_use]
+    pub fn count(self) -> usize {
+        match self {
+            AudioChannels::Mono => 1,
+            AudioChannels::Stereo => 2,
+        }
+    }
+}
+
+/// Accumulates a core's audio across frames, since [`Emulator::peek_audio_sample`] only holds
+/// the last frame's, optionally resampling it to a fixed rate and channel layout.
+///
+/// Call [`AudioBuffer::push`] after every [`Emulator::run`] and [`AudioBuffer::read`] whenever
+/// samples are wanted. The buffer holds at most `capacity` frames (one sample per channel); when
+/// reads fall behind, the oldest frames are dropped and counted by [`AudioBuffer::dropped`].
+#[derive(Debug, Clone)]
+pub struct AudioBuffer {
+    samples: VecDeque<i16>,
+    capacity: usize,
+    channels: AudioChannels,
+    target_rate: Option<f64>,
+    input_rate: f64,
+    resampler: Resampler,
+    dropped: u64,
+}
+
+impl AudioBuffer {
+    /// A buffer of up to `capacity` stereo frames at the core's own rate.
+    /// # Panics
+    /// If `capacity` is 0
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "audio buffer capacity must be at least 1");
+        AudioBuffer {
+            samples: VecDeque::new(),
+            capacity,
+            channels: AudioChannels::Stereo,
+            target_rate: None,
+            input_rate: 0.0,
+            resampler: Resampler::new(),
+            dropped: 0,
+        }
+    }
+    /// Resamples to `rate` frames per second with the given channels, e.g. 16000 Hz mono for
+    /// speech models or 48000 Hz stereo for playback.
+    /// # Panics
+    /// If `rate` isn't positive
+    #[must_use]
+    pub fn with_output(mut self, rate: f64, channels: AudioChannels) -> Self {
+        assert!(rate > 0.0, "audio output rate must be positive");
+        self.target_rate = Some(rate);
+        self.channels = channels;
+        self
+    }
+    /// Frames per second of the samples [`AudioBuffer::read`] returns, or 0 if no rate was
+    /// requested and nothing has been pushed yet.
+    #[must_use]
+    pub fn sample_rate(&self) -> f64 {
+        self.target_rate.unwrap_or(self.input_rate)
+    }
+    #[must_use]
+    pub fn channels(&self) -> AudioChannels {
+        self.channels
+    }
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// The number of frames ready to read.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.samples.len() / self.channels.count()
+    }
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+    /// The number of frames discarded so far because the buffer was full.
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+    /// Appends the audio of `emu`'s last frame at its current sample rate.
+    pub fn push(&mut self, emu: &Emulator) {
+        let rate = emu.get_audio_sample_rate();
+        emu.peek_audio_sample(|samples| self.push_samples(samples, rate));
+    }
+    /// Appends interleaved stereo `samples` produced at `rate` frames per second.
+    pub fn push_samples(&mut self, samples: &[i16], rate: f64) {
+        self.input_rate = rate;
+        let frames = samples
+            .chunks_exact(2)
+            .map(|frame| [f64::from(frame[0]), f64::from(frame[1])]);
+        match self.target_rate {
+            Some(target) if rate > 0.0 => {
+                let mut out = Vec::new();
+                self.resampler.process(frames, rate / target, &mut out);
+                for frame in out {
+                    self.push_frame(frame);
+                }
+            }
+            _ => {
+                for frame in frames {
+                    self.push_frame(frame);
+                }
+            }
+        }
+    }
+    /// Moves as many whole frames as fit into `out`, oldest first, returning the number of
+    /// samples written.
+    pub fn read(&mut self, out: &mut [i16]) -> usize {
+        let channels = self.channels.count();
+        let count = (out.len() / channels * channels).min(self.samples.len());
+        for (dst, src) in out.iter_mut().zip(self.samples.drain(..count)) {
+            *dst = src;
+        }
+        count
+    }
+    /// Discards buffered samples and the resampler's history, e.g. after loading a state.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.resampler = Resampler::new();
+    }
+
+    fn push_frame(&mut self, [left, right]: [f64; 2]) {
+        if self.len() == self.capacity {
+            self.samples.drain(..self.channels.count());
+            self.dropped += 1;
+        }
+        match self.channels {
+            AudioChannels::Mono => self.samples.push_back(to_i16(f64::midpoint(left, right))),
+            AudioChannels::Stereo => {
+                self.samples.push_back(to_i16(left));
+                self.samples.push_back(to_i16(right));
+            }
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn to_i16(sample: f64) -> i16 {
+    sample
+        .round()
+        .clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16
+}
+
+// A windowed-sinc resampler which carries its input history between calls
+#[derive(Debug, Clone)]
+struct Resampler {
+    // Input frames from FILTER_HALF_WIDTH before `position` on
+    history: Vec<[f64; 2]>,
+    // Where the next output frame falls, in input frames from the start of `history`
+    position: f64,
+}
+
+impl Resampler {
+    fn new() -> Self {
+        // Silence before the first frame, so the first output lines up with it
+        Resampler {
+            history: vec![[0.0; 2]; FILTER_HALF_WIDTH],
+            position: FILTER_HALF_WIDTH as f64,
+        }
+    }
+    // Resamples `input` by `step` input frames per output frame
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn process(
+        &mut self,
+        input: impl Iterator<Item = [f64; 2]>,
+        step: f64,
+        out: &mut Vec<[f64; 2]>,
+    ) {
+        self.history.extend(input);
+        // Below the input's Nyquist frequency and the output's, to avoid aliasing when downsampling
+        let cutoff = (1.0 / step).min(1.0);
+        while (self.position as usize) + FILTER_HALF_WIDTH < self.history.len() {
+            let center = self.position as usize;
+            let mut sum = [0.0; 2];
+            let mut weights = 0.0;
+            for i in center + 1 - FILTER_HALF_WIDTH..=center + FILTER_HALF_WIDTH {
+                let x = i as f64 - self.position;
+                let weight = sinc(x * cutoff) * hann(x / (FILTER_HALF_WIDTH as f64 + 1.0));
+                sum[0] += self.history[i][0] * weight;
+                sum[1] += self.history[i][1] * weight;
+                weights += weight;
+            }
+            // Normalizing keeps constant signals constant whatever the cutoff
+            out.push(sum.map(|s| s / weights));
+            self.position += step;
+        }
+        let keep_from = (self.position as usize + 1).saturating_sub(FILTER_HALF_WIDTH);
+        let keep_from = keep_from.min(self.history.len());
+        self.history.drain(..keep_from);
+        self.position -= keep_from as f64;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Hann window over -1..1
+fn hann(x: f64) -> f64 {
+    0.5 * (1.0 + (PI * x).cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_and_resampling() {
+        let mut buffer = AudioBuffer::new(4);
+        buffer.push_samples(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 44100.0);
+        assert_eq!((buffer.len(), buffer.dropped()), (4, 1));
+        let mut out = [0; 5];
+        // Only whole frames are read
+        assert_eq!(buffer.read(&mut out), 4);
+        assert_eq!(out, [3, 4, 5, 6, 0]);
+        assert_eq!(buffer.len(), 2);
+
+        let mut mono = AudioBuffer::new(1000).with_output(22050.0, AudioChannels::Mono);
+        mono.push_samples(&[1000, 3000].repeat(441), 44100.0);
+        assert_eq!(mono.sample_rate(), 22050.0);
+        // Every other input frame, except those still waiting on the filter's lookahead
+        assert_eq!(mono.len(), (441 - FILTER_HALF_WIDTH + 1).div_ceil(2));
+        let mut out = vec![0; mono.len()];
+        mono.read(&mut out);
+        // A constant signal, once the filter is past the initial silence
+        assert!(out[FILTER_HALF_WIDTH..].iter().all(|s| *s == 2000));
+    }
+}
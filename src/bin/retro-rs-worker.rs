@@ -0,0 +1,4 @@
+//! Hosts one emulator for a [`retro_rs::RemoteEmulator`] in another process.
+fn main() -> std::process::ExitCode {
+    retro_rs::run_worker()
+}
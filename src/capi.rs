@@ -0,0 +1,453 @@
+//! A C API over [`Emulator`], built with the `use_capi` feature; `include/retro_rs.h` declares it.
+//!
+//! Functions which can fail return a [`RetroRsStatus`] and leave a message for
+//! [`retro_rs_last_error`] on the calling thread. Emulators must be used and destroyed on the
+//! thread which created them.
+use crate::buttons::Buttons;
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use std::cell::RefCell;
+use std::ffi::{CStr, c_char};
+use std::path::Path;
+use std::ptr;
+
+/// An emulator created by [`retro_rs_emulator_create`].
+pub struct RetroRsEmulator(Emulator);
+
+/// Result codes; every failure has a message available from [`retro_rs_last_error`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetroRsStatus {
+    Ok = 0,
+    /// A pointer argument was null or a string wasn't UTF-8
+    InvalidArgument,
+    /// The core couldn't be loaded or is missing libretro functions
+    CoreLoadFailed,
+    /// The ROM couldn't be read or the core rejected it
+    ContentFailed,
+    NoFramebuffer,
+    /// A caller's buffer is too small for the requested data
+    BufferTooSmall,
+    /// A memory address isn't mapped or a region index is out of range
+    MemoryAccessFailed,
+    StateSaveFailed,
+    StateLoadFailed,
+    CoreOptionFailed,
+    Other,
+}
+
+impl From<&RetroRsError> for RetroRsStatus {
+    fn from(err: &RetroRsError) -> Self {
+        match err {
+            RetroRsError::InvalidPathError(_) => RetroRsStatus::InvalidArgument,
+            RetroRsError::UnsupportedPlatformError
+            | RetroRsError::CoreLoadError(_)
+            | RetroRsError::CoreSymbolMissingError(_)
+            | RetroRsError::CoreCopyError(_) => RetroRsStatus::CoreLoadFailed,
+            RetroRsError::ROMIOError(_) | RetroRsError::ContentRejectedError => {
+                RetroRsStatus::ContentFailed
+            }
+            RetroRsError::NoFramebufferError => RetroRsStatus::NoFramebuffer,
+            RetroRsError::RAMCopyDestTooSmallError => RetroRsStatus::BufferTooSmall,
+            RetroRsError::RAMCopySrcOutOfBoundsError
+            | RetroRsError::RAMMapOutOfRangeError
+            | RetroRsError::RAMCopyCrossedRegionError
+            | RetroRsError::RAMCopyNotMappedIntoMemoryRegionError => {
+                RetroRsStatus::MemoryAccessFailed
+            }
+            RetroRsError::StateSaveError => RetroRsStatus::StateSaveFailed,
+            RetroRsError::StateLoadError => RetroRsStatus::StateLoadFailed,
+            RetroRsError::CoreOptionUnknownError(_)
+            | RetroRsError::CoreOptionInvalidValueError(..) => RetroRsStatus::CoreOptionFailed,
+            _ => RetroRsStatus::Other,
+        }
+    }
+}
+
+/// A region of the core's memory map, see [`crate::MemoryRegion`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetroRsMemoryRegion {
+    pub flags: u64,
+    pub start: usize,
+    pub len: usize,
+    pub offset: usize,
+    pub select: usize,
+    pub disconnect: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+fn fail(err: &RetroRsError) -> RetroRsStatus {
+    LAST_ERROR.set(err.to_string());
+    RetroRsStatus::from(err)
+}
+
+fn invalid(why: &str) -> RetroRsStatus {
+    LAST_ERROR.set(why.to_owned());
+    RetroRsStatus::InvalidArgument
+}
+
+fn status(result: Result<(), RetroRsError>) -> RetroRsStatus {
+    match result {
+        Ok(()) => RetroRsStatus::Ok,
+        Err(err) => fail(&err),
+    }
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(s) }.to_str().ok()
+}
+
+unsafe fn path_arg<'a>(path: *const c_char) -> Option<&'a Path> {
+    unsafe { str_arg(path) }.map(Path::new)
+}
+
+unsafe fn buffer_arg<'a>(buf: *mut u8, len: usize) -> Option<&'a mut [u8]> {
+    if buf.is_null() {
+        return None;
+    }
+    Some(unsafe { std::slice::from_raw_parts_mut(buf, len) })
+}
+
+// Copies as much of `text` as fits into `buf` with a NUL after it
+unsafe fn copy_c_string(text: &str, buf: *mut c_char, len: usize) {
+    if let Some(buf) = unsafe { buffer_arg(buf.cast(), len) }
+        && !buf.is_empty()
+    {
+        let n = text.len().min(buf.len() - 1);
+        buf[..n].copy_from_slice(&text.as_bytes()[..n]);
+        buf[n] = 0;
+    }
+}
+
+/// Copies the message for the last failure on this thread into `buf` as a NUL-terminated string,
+/// truncating it to fit `len` bytes. Returns the message's full length, not counting the NUL.
+/// # Safety
+/// `buf` must be null or valid for writes of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_last_error(buf: *mut c_char, len: usize) -> usize {
+    LAST_ERROR.with_borrow(|msg| {
+        unsafe { copy_c_string(msg, buf, len) };
+        msg.len()
+    })
+}
+
+/// Loads a core and ROM, storing the new emulator in `*out`.
+/// # Safety
+/// The paths must be null or NUL-terminated strings, and `out` must be null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_create(
+    core_path: *const c_char,
+    rom_path: *const c_char,
+    out: *mut *mut RetroRsEmulator,
+) -> RetroRsStatus {
+    let (Some(core), Some(rom)) = (unsafe { path_arg(core_path) }, unsafe {
+        path_arg(rom_path)
+    }) else {
+        return invalid("core and ROM paths must be UTF-8 strings");
+    };
+    if out.is_null() {
+        return invalid("out must not be null");
+    }
+    match Emulator::try_create(core, rom) {
+        Ok(emu) => {
+            unsafe { *out = Box::into_raw(Box::new(RetroRsEmulator(emu))) };
+            RetroRsStatus::Ok
+        }
+        Err(err) => fail(&err),
+    }
+}
+
+/// # Safety
+/// `emu` must be null or come from [`retro_rs_emulator_create`], and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_destroy(emu: *mut RetroRsEmulator) {
+    if !emu.is_null() {
+        drop(unsafe { Box::from_raw(emu) });
+    }
+}
+
+/// Runs one frame with the given joypad button masks held on ports 0 and 1.
+/// Bit `n` of a mask is libretro's `RETRO_DEVICE_ID_JOYPAD` button `n`.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_run(emu: *mut RetroRsEmulator, port0: i16, port1: i16) {
+    let emu = unsafe { &mut (*emu).0 };
+    emu.run([Buttons::from(port0), Buttons::from(port1)]);
+}
+
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_reset(emu: *mut RetroRsEmulator) {
+    unsafe { (*emu).0.reset() };
+}
+
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_frame_count(emu: *const RetroRsEmulator) -> u64 {
+    unsafe { (*emu).0.frame_count() }
+}
+
+/// The buffer size [`retro_rs_emulator_save`] needs.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_save_size(emu: *const RetroRsEmulator) -> usize {
+    unsafe { (*emu).0.save_size() }
+}
+
+/// Serializes the core's state into `buf`.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_save(
+    emu: *const RetroRsEmulator,
+    buf: *mut u8,
+    len: usize,
+) -> RetroRsStatus {
+    let emu = unsafe { &(*emu).0 };
+    let Some(buf) = (unsafe { buffer_arg(buf, len) }) else {
+        return invalid("buf must not be null");
+    };
+    if len < emu.save_size() {
+        return fail(&RetroRsError::RAMCopyDestTooSmallError);
+    }
+    if emu.save(buf) {
+        RetroRsStatus::Ok
+    } else {
+        fail(&RetroRsError::StateSaveError)
+    }
+}
+
+/// Restores a state produced by [`retro_rs_emulator_save`].
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for reads of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_load(
+    emu: *mut RetroRsEmulator,
+    buf: *const u8,
+    len: usize,
+) -> RetroRsStatus {
+    let emu = unsafe { &mut (*emu).0 };
+    let Some(buf) = (unsafe { buffer_arg(buf.cast_mut(), len) }) else {
+        return invalid("buf must not be null");
+    };
+    if emu.load(buf) {
+        RetroRsStatus::Ok
+    } else {
+        fail(&RetroRsError::StateLoadError)
+    }
+}
+
+/// Stores the last frame's size in `*width` and `*height`.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `width` and `height` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_framebuffer_size(
+    emu: *const RetroRsEmulator,
+    width: *mut usize,
+    height: *mut usize,
+) {
+    let (w, h) = unsafe { (*emu).0.framebuffer_size() };
+    unsafe {
+        *width = w;
+        *height = h;
+    }
+}
+
+/// Copies the last frame into `buf` with 3 bytes per pixel.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_copy_framebuffer_rgb888(
+    emu: *const RetroRsEmulator,
+    buf: *mut u8,
+    len: usize,
+) -> RetroRsStatus {
+    unsafe { copy_framebuffer(emu, buf, len, 3, Emulator::copy_framebuffer_rgb888) }
+}
+
+/// Copies the last frame into `buf` with 4 bytes per pixel.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_copy_framebuffer_rgba8888(
+    emu: *const RetroRsEmulator,
+    buf: *mut u8,
+    len: usize,
+) -> RetroRsStatus {
+    unsafe { copy_framebuffer(emu, buf, len, 4, Emulator::copy_framebuffer_rgba8888) }
+}
+
+/// Copies the last frame into `buf` with 1 byte per pixel.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_copy_framebuffer_rgb332(
+    emu: *const RetroRsEmulator,
+    buf: *mut u8,
+    len: usize,
+) -> RetroRsStatus {
+    unsafe { copy_framebuffer(emu, buf, len, 1, Emulator::copy_framebuffer_rgb332) }
+}
+
+unsafe fn copy_framebuffer(
+    emu: *const RetroRsEmulator,
+    buf: *mut u8,
+    len: usize,
+    bytes_per_pixel: usize,
+    copy: fn(&Emulator, &mut [u8]) -> Result<(), RetroRsError>,
+) -> RetroRsStatus {
+    let emu = unsafe { &(*emu).0 };
+    let Some(buf) = (unsafe { buffer_arg(buf, len) }) else {
+        return invalid("buf must not be null");
+    };
+    let (w, h) = emu.framebuffer_size();
+    if len < w * h * bytes_per_pixel {
+        return fail(&RetroRsError::RAMCopyDestTooSmallError);
+    }
+    status(copy(emu, buf))
+}
+
+/// Points `*data` at system RAM and stores its length in `*len`; the pointer is valid until the emulator is destroyed.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `data` and `len` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_system_ram(
+    emu: *mut RetroRsEmulator,
+    data: *mut *mut u8,
+    len: *mut usize,
+) {
+    let ram = unsafe { (*emu).0.system_ram_mut() };
+    unsafe {
+        *len = ram.len();
+        *data = if ram.is_empty() {
+            ptr::null_mut()
+        } else {
+            ram.as_mut_ptr()
+        };
+    }
+}
+
+/// How many regions the core's memory map has.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_memory_region_count(
+    emu: *const RetroRsEmulator,
+) -> usize {
+    unsafe { (*emu).0.memory_regions().len() }
+}
+
+/// Stores memory region `index` in `*out`, and copies its name into `name` as
+/// [`retro_rs_last_error`] copies messages if `name` isn't null.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], `out` must be valid for writes,
+/// and `name` must be null or valid for writes of `name_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_memory_region(
+    emu: *const RetroRsEmulator,
+    index: usize,
+    out: *mut RetroRsMemoryRegion,
+    name: *mut c_char,
+    name_len: usize,
+) -> RetroRsStatus {
+    let Some(mr) = unsafe { (*emu).0.memory_regions() }.into_iter().nth(index) else {
+        return fail(&RetroRsError::RAMMapOutOfRangeError);
+    };
+    if out.is_null() {
+        return invalid("out must not be null");
+    }
+    unsafe {
+        *out = RetroRsMemoryRegion {
+            flags: mr.flags,
+            start: mr.start,
+            len: mr.len,
+            offset: mr.offset,
+            select: mr.select,
+            disconnect: mr.disconnect,
+        };
+    }
+    unsafe { copy_c_string(&mr.name, name, name_len) };
+    RetroRsStatus::Ok
+}
+
+/// Copies `len` bytes of the core's memory map starting at address `start` into `buf`.
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `buf` must be null or valid for writes of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_read_memory(
+    emu: *const RetroRsEmulator,
+    start: usize,
+    buf: *mut u8,
+    len: usize,
+) -> RetroRsStatus {
+    let emu = unsafe { &(*emu).0 };
+    let Some(buf) = (unsafe { buffer_arg(buf, len) }) else {
+        return invalid("buf must not be null");
+    };
+    let result = emu.memory_ref(start).and_then(|mem| {
+        let src = mem
+            .get(..len)
+            .ok_or(RetroRsError::RAMCopyCrossedRegionError)?;
+        buf.copy_from_slice(src);
+        Ok(())
+    });
+    status(result)
+}
+
+/// # Safety
+/// `emu` must come from [`retro_rs_emulator_create`], and `key` and `value` must be null or NUL-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_rs_emulator_set_core_option(
+    emu: *mut RetroRsEmulator,
+    key: *const c_char,
+    value: *const c_char,
+) -> RetroRsStatus {
+    let emu = unsafe { &mut (*emu).0 };
+    let (Some(key), Some(value)) = (unsafe { str_arg(key) }, unsafe { str_arg(value) }) else {
+        return invalid("core option keys and values must be non-null UTF-8 strings");
+    };
+    status(emu.set_core_option(key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_reach_last_error() {
+        let mut emu = ptr::null_mut();
+        let status =
+            unsafe { retro_rs_emulator_create(c"core".as_ptr(), ptr::null(), &raw mut emu) };
+        assert_eq!(status, RetroRsStatus::InvalidArgument);
+        assert!(emu.is_null());
+        let status = unsafe {
+            retro_rs_emulator_create(c"/nonexistent.so".as_ptr(), c"rom".as_ptr(), &raw mut emu)
+        };
+        assert_eq!(status, RetroRsStatus::ContentFailed);
+        let mut msg = [0 as c_char; 8];
+        let len = unsafe { retro_rs_last_error(msg.as_mut_ptr(), msg.len()) };
+        assert!(len > 7);
+        assert_eq!(unsafe { CStr::from_ptr(msg.as_ptr()) }.to_bytes().len(), 7);
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        let generated = concat!(env!("OUT_DIR"), "/retro_rs.h");
+        assert!(
+            include_str!(concat!(env!("OUT_DIR"), "/retro_rs.h"))
+                == include_str!("../include/retro_rs.h"),
+            "include/retro_rs.h is stale; replace it with {generated}"
+        );
+    }
+}
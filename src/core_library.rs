@@ -0,0 +1,73 @@
+//! Loading core dylibs so that every live [`crate::Emulator`] gets its own copy of the core's globals.
+//!
+//! Loading the same path twice gives back the same library, so a second instance of a core would
+//! share (and trample) the first one's state. Instead, each extra instance loads a copy of the
+//! dylib from a unique temporary path. Libraries are pooled when their emulator is dropped and
+//! reused by the next emulator for the same core, so creating and dropping emulators in a loop
+//! doesn't keep loading new copies.
+use crate::error::RetroRsError;
+use libloading::Library;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+#[derive(Default)]
+struct Pool {
+    free: HashMap<PathBuf, Vec<Library>>,
+    loaded: HashMap<PathBuf, usize>,
+}
+
+static POOL: LazyLock<Mutex<Pool>> = LazyLock::new(Mutex::default);
+
+/// Loads the core at `path`, or a private copy of it if it's already in use.
+pub(crate) fn acquire(path: &Path) -> Result<Library, RetroRsError> {
+    let mut pool = POOL.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(lib) = pool.free.get_mut(path).and_then(Vec::pop) {
+        return Ok(lib);
+    }
+    let loaded = pool.loaded.entry(path.to_path_buf()).or_default();
+    let lib = if *loaded == 0 {
+        open(path)?
+    } else {
+        open_copy(path, *loaded)?
+    };
+    *loaded += 1;
+    Ok(lib)
+}
+
+/// Returns a library from [`acquire`] once its core has been deinitialized.
+pub(crate) fn release(path: &Path, lib: Library) {
+    let mut pool = POOL.lock().unwrap_or_else(PoisonError::into_inner);
+    pool.free.entry(path.to_path_buf()).or_default().push(lib);
+}
+
+fn open_copy(path: &Path, n: usize) -> Result<Library, RetroRsError> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| RetroRsError::InvalidPathError(path.to_path_buf()))?;
+    let mut copy_name = format!("retro-rs-{}-{n}-", std::process::id());
+    copy_name.push_str(&name.to_string_lossy());
+    let copy = std::env::temp_dir().join(copy_name);
+    std::fs::copy(path, &copy).map_err(RetroRsError::CoreCopyError)?;
+    let lib = open(&copy);
+    // Windows can't delete a loaded dll, so its copies stay in the temp directory
+    if cfg!(unix) || lib.is_err() {
+        let _ = std::fs::remove_file(&copy);
+    }
+    lib
+}
+
+#[cfg(target_os = "linux")]
+fn open(path: &Path) -> Result<Library, RetroRsError> {
+    use libc::RTLD_NODELETE;
+    use libloading::os::unix::{self, RTLD_LOCAL, RTLD_NOW};
+    // Load library with `RTLD_NOW | RTLD_LOCAL | RTLD_NODELETE` to fix a SIGSEGV
+    unsafe { unix::Library::open(Some(path), RTLD_NOW | RTLD_LOCAL | RTLD_NODELETE) }
+        .map(Library::from)
+        .map_err(RetroRsError::CoreLoadError)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open(path: &Path) -> Result<Library, RetroRsError> {
+    unsafe { Library::new(path) }.map_err(RetroRsError::CoreLoadError)
+}
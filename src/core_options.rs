@@ -0,0 +1,415 @@
+use crate::diagnostics::{self, Diagnostic};
+use crate::error::RetroRsError;
+use rust_libretro_sys::{
+    RETRO_NUM_CORE_OPTION_VALUES_MAX, retro_core_option_definition, retro_core_option_v2_category,
+    retro_core_option_v2_definition, retro_core_option_value, retro_core_options_v2,
+    retro_variable,
+};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char};
+use std::fmt::Write;
+use std::path::Path;
+
+/// One allowed setting of a [`CoreOption`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoreOptionValue {
+    pub value: String,
+    pub label: Option<String>,
+}
+
+/// A variable declared by the core through `SET_VARIABLES` or one of the `SET_CORE_OPTIONS` calls.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoreOption {
+    pub key: String,
+    pub description: String,
+    pub info: Option<String>,
+    pub category: Option<String>,
+    pub values: Vec<CoreOptionValue>,
+    pub default: Option<String>,
+    pub visible: bool,
+}
+
+impl CoreOption {
+    /// Whether `value` is one of the settings the core declared for this option.
+    /// Options declared without any values accept anything.
+    #[must_use]
+    pub fn accepts(&self, value: &str) -> bool {
+        self.values.is_empty() || self.values.iter().any(|v| v.value == value)
+    }
+}
+
+/// A grouping of options declared with `SET_CORE_OPTIONS_V2`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoreOptionCategory {
+    pub key: String,
+    pub description: String,
+    pub info: Option<String>,
+}
+
+// Some cores need a particular setting to work headless; these are used when the user hasn't chosen a value.
+fn frontend_default(key: &str) -> Option<&'static str> {
+    #[allow(clippy::match_same_arms)]
+    match key {
+        "ppsspp_internal_resolution" => Some("480x272"),
+        "ppsspp_backend" => Some("opengl"),
+        "ppsspp_psp_model" => Some("psp_2000_3000"),
+        "ppsspp_cache_iso" => Some("disabled"),
+        "ppsspp_change_mac_address01" => Some("e"),
+        "ppsspp_change_mac_address02" => Some("c"),
+        "ppsspp_change_mac_address03" => Some("c"),
+        "ppsspp_change_mac_address04" => Some("a"),
+        "ppsspp_change_mac_address05" => Some("4"),
+        "ppsspp_change_mac_address06" => Some("7"),
+        "ppsspp_change_mac_address07" => Some("b"),
+        "ppsspp_change_mac_address08" => Some("c"),
+        "ppsspp_change_mac_address09" => Some("5"),
+        "ppsspp_change_mac_address10" => Some("b"),
+        "ppsspp_change_mac_address11" => Some("1"),
+        "ppsspp_change_mac_address12" => Some("d"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CoreOptions {
+    pub(crate) definitions: Vec<CoreOption>,
+    pub(crate) categories: Vec<CoreOptionCategory>,
+    // Values chosen by the user (or by the core via `SET_VARIABLE`), kept as C strings so `GET_VARIABLE` can hand out pointers.
+    values: HashMap<String, CString>,
+    // Value strings handed to the core for options nobody has set yet.
+    defaults: HashMap<String, CString>,
+    updated: bool,
+}
+
+impl CoreOptions {
+    pub(crate) fn definition(&self, key: &str) -> Option<&CoreOption> {
+        self.definitions.iter().find(|opt| opt.key == key)
+    }
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .get(key)
+            .or_else(|| self.defaults.get(key))
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| frontend_default(key))
+            .or_else(|| self.definition(key).and_then(|opt| opt.default.as_deref()))
+    }
+    /// Returns false if the value has an interior NUL and can't be given to the core.
+    pub(crate) fn set(&mut self, key: &str, value: &str) -> bool {
+        let Ok(cvalue) = CString::new(value) else {
+            return false;
+        };
+        self.values.insert(key.to_owned(), cvalue);
+        self.updated = true;
+        true
+    }
+    /// Keys which have been given values but which the core never declared.
+    pub(crate) fn undeclared(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .values
+            .keys()
+            .filter(|key| self.definition(key).is_none())
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+    /// Reports each of [`CoreOptions::undeclared`] as a [`Diagnostic::UndeclaredCoreOption`].
+    pub(crate) fn report_undeclared(&self) {
+        for key in self.undeclared() {
+            diagnostics::emit(&Diagnostic::UndeclaredCoreOption(key));
+        }
+    }
+    pub(crate) fn take_updated(&mut self) -> bool {
+        std::mem::replace(&mut self.updated, false)
+    }
+    pub(crate) fn set_visible(&mut self, key: &str, visible: bool) {
+        if let Some(opt) = self.definitions.iter_mut().find(|opt| opt.key == key) {
+            opt.visible = visible;
+        }
+    }
+    /// The pointer stays valid until the option is next set.
+    pub(crate) fn value_ptr(&mut self, key: &str) -> Option<*const c_char> {
+        if let Some(v) = self.values.get(key) {
+            return Some(v.as_ptr());
+        }
+        let value = frontend_default(key)
+            .or_else(|| self.definition(key).and_then(|opt| opt.default.as_deref()))?;
+        let cvalue = CString::new(value).ok()?;
+        Some(
+            self.defaults
+                .entry(key.to_owned())
+                .or_insert(cvalue)
+                .as_ptr(),
+        )
+    }
+    fn replace_definitions(
+        &mut self,
+        definitions: Vec<CoreOption>,
+        categories: Vec<CoreOptionCategory>,
+    ) {
+        self.definitions = definitions;
+        self.categories = categories;
+        self.defaults.clear();
+    }
+
+    /// # Safety
+    /// `vars` must point to an array of [`retro_variable`] terminated by a null key.
+    pub(crate) unsafe fn set_variables(&mut self, vars: *const retro_variable) {
+        let mut definitions = Vec::new();
+        let mut var = vars;
+        unsafe {
+            while !var.is_null() && !(*var).key.is_null() {
+                let key = string_from_ptr((*var).key).unwrap_or_default();
+                let decl = string_from_ptr((*var).value).unwrap_or_default();
+                definitions.push(parse_variable_declaration(key, &decl));
+                var = var.add(1);
+            }
+        }
+        self.replace_definitions(definitions, Vec::new());
+    }
+
+    /// # Safety
+    /// `defs` must point to an array of [`retro_core_option_definition`] terminated by a null key.
+    pub(crate) unsafe fn set_core_options(&mut self, defs: *const retro_core_option_definition) {
+        let mut definitions = Vec::new();
+        let mut def = defs;
+        unsafe {
+            while !def.is_null() && !(*def).key.is_null() {
+                let def_ref = &*def;
+                definitions.push(CoreOption {
+                    key: string_from_ptr(def_ref.key).unwrap_or_default(),
+                    description: string_from_ptr(def_ref.desc).unwrap_or_default(),
+                    info: string_from_ptr(def_ref.info),
+                    category: None,
+                    values: option_values(&def_ref.values),
+                    default: string_from_ptr(def_ref.default_value),
+                    visible: true,
+                });
+                def = def.add(1);
+            }
+        }
+        self.replace_definitions(definitions, Vec::new());
+    }
+
+    /// # Safety
+    /// `opts` must point to a valid [`retro_core_options_v2`] whose arrays are terminated by null keys.
+    pub(crate) unsafe fn set_core_options_v2(&mut self, opts: *const retro_core_options_v2) {
+        let mut definitions = Vec::new();
+        let mut categories = Vec::new();
+        unsafe {
+            let Some(opts) = opts.as_ref() else {
+                return;
+            };
+            let mut cat: *const retro_core_option_v2_category = opts.categories;
+            while !cat.is_null() && !(*cat).key.is_null() {
+                let cat_ref = &*cat;
+                categories.push(CoreOptionCategory {
+                    key: string_from_ptr(cat_ref.key).unwrap_or_default(),
+                    description: string_from_ptr(cat_ref.desc).unwrap_or_default(),
+                    info: string_from_ptr(cat_ref.info),
+                });
+                cat = cat.add(1);
+            }
+            let mut def: *const retro_core_option_v2_definition = opts.definitions;
+            while !def.is_null() && !(*def).key.is_null() {
+                let def_ref = &*def;
+                definitions.push(CoreOption {
+                    key: string_from_ptr(def_ref.key).unwrap_or_default(),
+                    description: string_from_ptr(def_ref.desc).unwrap_or_default(),
+                    info: string_from_ptr(def_ref.info),
+                    category: string_from_ptr(def_ref.category_key),
+                    values: option_values(&def_ref.values),
+                    default: string_from_ptr(def_ref.default_value),
+                    visible: true,
+                });
+                def = def.add(1);
+            }
+        }
+        self.replace_definitions(definitions, categories);
+    }
+}
+
+/// Parses the `key = "value"` lines of a RetroArch core options file (`.opt` or `retroarch-core-options.cfg`).
+/// Blank lines, `#` comments and lines without an `=` are skipped.
+#[must_use]
+pub fn parse_core_options(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            (key.trim().to_owned(), value.to_owned())
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+/// Formats options in the same `key = "value"` layout RetroArch writes.
+#[must_use]
+pub fn format_core_options<'a>(options: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let mut text = String::new();
+    for (key, value) in options {
+        // Writing to a String can't fail
+        let _ = writeln!(text, "{key} = \"{value}\"");
+    }
+    text
+}
+
+/// # Errors
+/// [`RetroRsError::CoreOptionsFileError`]: The file couldn't be read
+pub fn read_core_options_file(path: &Path) -> Result<Vec<(String, String)>, RetroRsError> {
+    std::fs::read_to_string(path)
+        .map(|text| parse_core_options(&text))
+        .map_err(RetroRsError::CoreOptionsFileError)
+}
+
+/// # Errors
+/// [`RetroRsError::CoreOptionsFileError`]: The file couldn't be written
+pub fn write_core_options_file<'a>(
+    path: &Path,
+    options: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<(), RetroRsError> {
+    std::fs::write(path, format_core_options(options)).map_err(RetroRsError::CoreOptionsFileError)
+}
+
+// Legacy `SET_VARIABLES` values look like "Description; first|second|third", and the first is the default.
+fn parse_variable_declaration(key: String, decl: &str) -> CoreOption {
+    let (description, choices) = decl.split_once("; ").unwrap_or(("", decl));
+    let values: Vec<CoreOptionValue> = choices
+        .split('|')
+        .filter(|v| !v.is_empty())
+        .map(|v| CoreOptionValue {
+            value: v.to_owned(),
+            label: None,
+        })
+        .collect();
+    CoreOption {
+        key,
+        description: description.to_owned(),
+        info: None,
+        category: None,
+        default: values.first().map(|v| v.value.clone()),
+        values,
+        visible: true,
+    }
+}
+
+unsafe fn option_values(
+    values: &[retro_core_option_value; RETRO_NUM_CORE_OPTION_VALUES_MAX as usize],
+) -> Vec<CoreOptionValue> {
+    values
+        .iter()
+        .take_while(|v| !v.value.is_null())
+        .map(|v| unsafe {
+            CoreOptionValue {
+                value: string_from_ptr(v.value).unwrap_or_default(),
+                label: string_from_ptr(v.label),
+            }
+        })
+        .collect()
+}
+
+unsafe fn string_from_ptr(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex, PoisonError};
+
+    #[test]
+    fn legacy_variable_declaration() {
+        let opt = parse_variable_declaration("fceumm_region".to_owned(), "Region; Auto|NTSC|PAL");
+        assert_eq!(opt.description, "Region");
+        assert_eq!(opt.default.as_deref(), Some("Auto"));
+        assert_eq!(opt.values.len(), 3);
+        assert!(opt.accepts("PAL"));
+        assert!(!opt.accepts("SECAM"));
+    }
+
+    #[test]
+    fn set_values_shadow_defaults() {
+        let mut opts = CoreOptions::default();
+        opts.replace_definitions(
+            vec![parse_variable_declaration(
+                "snes9x_overclock".to_owned(),
+                "SuperFX Overclock; 10 MHz|20 MHz|40 MHz",
+            )],
+            Vec::new(),
+        );
+        assert_eq!(opts.get("snes9x_overclock"), Some("10 MHz"));
+        assert!(!opts.take_updated());
+        assert!(opts.set("snes9x_overclock", "40 MHz"));
+        assert_eq!(opts.get("snes9x_overclock"), Some("40 MHz"));
+        assert!(opts.take_updated());
+        assert!(!opts.take_updated());
+        assert_eq!(opts.get("ppsspp_backend"), Some("opengl"));
+        assert_eq!(opts.get("unknown"), None);
+        opts.set("mgba_solar_sensor_level", "3");
+        assert_eq!(
+            opts.undeclared(),
+            vec!["mgba_solar_sensor_level".to_owned()]
+        );
+    }
+
+    #[test]
+    fn undeclared_options_are_reported() {
+        let _lock = diagnostics::TEST_HOOK_LOCK
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reported);
+        diagnostics::set_diagnostic_hook(Some(Box::new(move |diagnostic| {
+            if let Diagnostic::UndeclaredCoreOption(key) = diagnostic {
+                sink.lock().unwrap().push(key.clone());
+            }
+        })));
+        let mut opts = CoreOptions::default();
+        opts.replace_definitions(
+            vec![parse_variable_declaration(
+                "fceumm_region".to_owned(),
+                "Region; Auto|NTSC|PAL",
+            )],
+            Vec::new(),
+        );
+        opts.set("fceumm_region", "PAL");
+        opts.set("fceumm_regoin", "PAL");
+        opts.report_undeclared();
+        diagnostics::set_diagnostic_hook(None);
+        assert_eq!(*reported.lock().unwrap(), ["fceumm_regoin"]);
+    }
+
+    #[test]
+    fn options_file_round_trip() {
+        let text =
+            "# comment\nfceumm_region = \"PAL\"\n\n  fceumm_aspect=\"8:7 PAR\"\nbogus line\n";
+        let opts = parse_core_options(text);
+        assert_eq!(
+            opts,
+            vec![
+                ("fceumm_region".to_owned(), "PAL".to_owned()),
+                ("fceumm_aspect".to_owned(), "8:7 PAR".to_owned())
+            ]
+        );
+        let written = format_core_options(opts.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        assert_eq!(
+            written,
+            "fceumm_region = \"PAL\"\nfceumm_aspect = \"8:7 PAR\"\n"
+        );
+        assert_eq!(parse_core_options(&written), opts);
+    }
+}
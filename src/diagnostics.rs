@@ -0,0 +1,96 @@
+use rust_libretro_sys::{retro_hw_context_type, retro_pixel_format};
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Noteworthy events from the frontend's negotiation with cores and graphics backends.
+/// Nothing is reported unless a hook is installed with [`set_diagnostic_hook`] or the `log` feature is enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// The core chose the pixel format of its framebuffer.
+    PixelFormatNegotiated(retro_pixel_format),
+    /// A framebuffer was allocated to receive frames from a hardware-rendering core.
+    HardwareFramebufferAllocated {
+        width: u32,
+        height: u32,
+        pitch: usize,
+    },
+    /// The core asked for a hardware context type the graphics backend can't provide.
+    UnsupportedHardwareContext(retro_hw_context_type),
+    /// The core reported a framebuffer size the graphics backend can't use.
+    InvalidFramebufferSize { width: u32, height: u32 },
+    /// An OpenGL context was created for a hardware-rendering core.
+    GlContextCreated { major: u8, minor: u8 },
+    /// Creating the OpenGL context failed at the named step.
+    GlContextFailed(&'static str),
+    /// Tearing down the OpenGL context failed.
+    GlContextDestroyFailed(String),
+    /// OpenGL reported an error code after the named call.
+    GlError { call: &'static str, code: u32 },
+    /// An option set before the game was loaded, e.g. from an options file, which the core never declared.
+    UndeclaredCoreOption(String),
+    /// Save RAM couldn't be written to its file automatically or when the emulator was dropped.
+    SramFlushFailed(String),
+    /// A [`Recorder`](crate::Recorder) dropped without [`finish`](crate::Recorder::finish) couldn't finish its files.
+    RecorderFinishFailed(String),
+}
+
+// Held by tests which install a hook, so they don't replace each other's
+#[cfg(test)]
+pub(crate) static TEST_HOOK_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+pub type DiagnosticHook = Box<dyn Fn(&Diagnostic) + Send + Sync>;
+
+// Arc so that the hook can be called without holding the lock, and may replace itself
+type SharedHook = Arc<dyn Fn(&Diagnostic) + Send + Sync>;
+
+static HOOK: RwLock<Option<SharedHook>> = RwLock::new(None);
+
+/// Installs a process-wide hook which receives every [`Diagnostic`], or removes it if `None`.
+/// Hooks may call this themselves; diagnostics already being handled still go to the old hook.
+pub fn set_diagnostic_hook(hook: Option<DiagnosticHook>) {
+    *HOOK.write().unwrap_or_else(PoisonError::into_inner) = hook.map(Arc::from);
+}
+
+pub(crate) fn emit(diagnostic: &Diagnostic) {
+    let hook = HOOK.read().unwrap_or_else(PoisonError::into_inner).clone();
+    if let Some(hook) = hook {
+        hook(diagnostic);
+    } else {
+        log_diagnostic(diagnostic);
+    }
+}
+
+#[cfg(feature = "log")]
+fn log_diagnostic(diagnostic: &Diagnostic) {
+    let level = match diagnostic {
+        Diagnostic::PixelFormatNegotiated(_)
+        | Diagnostic::HardwareFramebufferAllocated { .. }
+        | Diagnostic::GlContextCreated { .. } => log::Level::Debug,
+        _ => log::Level::Warn,
+    };
+    log::log!(target: "retro_rs", level, "{diagnostic:?}");
+}
+
+#[cfg(not(feature = "log"))]
+fn log_diagnostic(_diagnostic: &Diagnostic) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn hooks_can_replace_themselves() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let _lock = TEST_HOOK_LOCK
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        set_diagnostic_hook(Some(Box::new(|_| {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            set_diagnostic_hook(None);
+        })));
+        emit(&Diagnostic::SramFlushFailed("disk full".to_owned()));
+        emit(&Diagnostic::SramFlushFailed("disk full".to_owned()));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    }
+}
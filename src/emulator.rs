@@ -1,36 +1,89 @@
 use crate::buttons::Buttons;
+use crate::core_library;
+use crate::core_options::{
+    CoreOption, CoreOptionCategory, CoreOptions, read_core_options_file, write_core_options_file,
+};
+use crate::diagnostics::{self, Diagnostic};
 use crate::error::RetroRsError;
 use crate::gfx::Gfx;
+use crate::input::{
+    ControllerDescription, Input, InputDescriptor, InputQuery, read_controller_info,
+    read_input_descriptors,
+};
+use crate::keyboard::{KeyEvent, Keyboard};
 use crate::pixels::{argb555to888, rgb565to888, rgb888_to_rgb332};
 
 use libloading::Library;
 use libloading::Symbol;
 #[allow(clippy::wildcard_imports)]
 use rust_libretro_sys::*;
-use std::ffi::{CStr, CString, c_char, c_uint, c_void};
-use std::fs::File;
-use std::io::Read;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString, c_char, c_int, c_uint, c_void};
 use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
 use std::panic;
 use std::path::{Path, PathBuf};
 use std::ptr;
+use std::rc::Rc;
 
 unsafe extern "C" {
     fn retrors_log_print(lev: retro_log_level, fmt: *const i8, ...);
 }
 
 thread_local! {
-    static CTX:std::cell::RefCell<Option<EmulatorContext>> = const{std::cell::RefCell::new(None)};
+    // The context of the emulator whose core is being called into on this thread, for the libretro callbacks
+    static ACTIVE_CTX: Cell<*const RefCell<EmulatorContext>> = const { Cell::new(ptr::null()) };
+}
+
+// Makes a context the one libretro callbacks see until it is dropped
+struct ActiveContext<'a> {
+    previous: *const RefCell<EmulatorContext>,
+    _ctx: PhantomData<&'a RefCell<EmulatorContext>>,
+}
+impl<'a> ActiveContext<'a> {
+    fn enter(ctx: &'a RefCell<EmulatorContext>) -> Self {
+        ActiveContext {
+            previous: ACTIVE_CTX.replace(ctx),
+            _ctx: PhantomData,
+        }
+    }
+}
+impl Drop for ActiveContext<'_> {
+    fn drop(&mut self) {
+        ACTIVE_CTX.set(self.previous);
+    }
+}
+
+// None if no core is being called into on this thread, or the context is already borrowed
+fn with_active_ctx<R>(f: impl FnOnce(&mut EmulatorContext) -> R) -> Option<R> {
+    let ctx = ACTIVE_CTX.get();
+    if ctx.is_null() {
+        return None;
+    }
+    // Safe since an ActiveContext borrows the context for as long as it's active
+    let ctx = unsafe { &*ctx };
+    let mut ctx = ctx.try_borrow_mut().ok()?;
+    Some(f(&mut ctx))
 }
 
 type NotSendSync = *const [u8; 0];
 struct EmulatorCore {
-    core_lib: Library,
+    core_lib: ManuallyDrop<Library>,
+    lib_path: PathBuf,
     rom_path: CString,
+    rom_sha1: String,
     core: CoreFns,
     _marker: PhantomData<NotSendSync>,
 }
 
+impl Drop for EmulatorCore {
+    fn drop(&mut self) {
+        let lib = unsafe { ManuallyDrop::take(&mut self.core_lib) };
+        core_library::release(&self.lib_path, lib);
+    }
+}
+
 #[allow(dead_code, clippy::struct_field_names)]
 struct CoreFns {
     retro_api_version: unsafe extern "C" fn() -> c_uint,
@@ -60,13 +113,239 @@ struct CoreFns {
     retro_unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool,
 }
 
+impl CoreFns {
+    unsafe fn load(dll: &Library) -> Result<Self, RetroRsError> {
+        unsafe {
+            Ok(CoreFns {
+                retro_api_version: core_symbol(dll, "retro_api_version")?,
+                retro_cheat_reset: core_symbol(dll, "retro_cheat_reset")?,
+                retro_cheat_set: core_symbol(dll, "retro_cheat_set")?,
+                retro_deinit: core_symbol(dll, "retro_deinit")?,
+                retro_get_memory_data: core_symbol(dll, "retro_get_memory_data")?,
+                retro_get_memory_size: core_symbol(dll, "retro_get_memory_size")?,
+                retro_get_region: core_symbol(dll, "retro_get_region")?,
+                retro_get_system_av_info: core_symbol(dll, "retro_get_system_av_info")?,
+                retro_get_system_info: core_symbol(dll, "retro_get_system_info")?,
+                retro_init: core_symbol(dll, "retro_init")?,
+                retro_load_game: core_symbol(dll, "retro_load_game")?,
+                retro_load_game_special: core_symbol(dll, "retro_load_game_special")?,
+                retro_reset: core_symbol(dll, "retro_reset")?,
+                retro_run: core_symbol(dll, "retro_run")?,
+                retro_serialize: core_symbol(dll, "retro_serialize")?,
+                retro_serialize_size: core_symbol(dll, "retro_serialize_size")?,
+                retro_set_audio_sample: core_symbol(dll, "retro_set_audio_sample")?,
+                retro_set_audio_sample_batch: core_symbol(dll, "retro_set_audio_sample_batch")?,
+                retro_set_controller_port_device: core_symbol(
+                    dll,
+                    "retro_set_controller_port_device",
+                )?,
+                retro_set_environment: core_symbol(dll, "retro_set_environment")?,
+                retro_set_input_poll: core_symbol(dll, "retro_set_input_poll")?,
+                retro_set_input_state: core_symbol(dll, "retro_set_input_state")?,
+                retro_set_video_refresh: core_symbol(dll, "retro_set_video_refresh")?,
+                retro_unload_game: core_symbol(dll, "retro_unload_game")?,
+                retro_unserialize: core_symbol(dll, "retro_unserialize")?,
+            })
+        }
+    }
+}
+
+/// Configures an [`Emulator`] before its core and ROM are loaded; see [`Emulator::builder`].
+pub struct EmulatorBuilder {
+    core_path: PathBuf,
+    rom_path: PathBuf,
+    gfx: Box<dyn Gfx>,
+    options: Vec<(String, String)>,
+    log_callback: Option<LogCallback>,
+    sram_path: Option<PathBuf>,
+    port_devices: Vec<(u32, u32)>,
+}
+
+impl EmulatorBuilder {
+    #[must_use]
+    pub fn gfx(mut self, gfx: Box<dyn Gfx>) -> Self {
+        self.gfx = gfx;
+        self
+    }
+    /// Sets a core option before the game is loaded, for cores which only read their options at load time.
+    /// Unlike [`Emulator::set_core_option`], the key and value are not checked against the core's declarations,
+    /// though keys it never declares are reported as [`Diagnostic::UndeclaredCoreOption`] once the game is loaded.
+    #[must_use]
+    pub fn option(mut self, key: &str, value: &str) -> Self {
+        self.options.push((key.to_owned(), value.to_owned()));
+        self
+    }
+    /// Installs a log callback before the core is initialized, so messages logged while loading are captured too.
+    /// See [`Emulator::set_log_callback`].
+    #[must_use]
+    pub fn log_callback(mut self, callback: LogCallback) -> Self {
+        self.log_callback = Some(callback);
+        self
+    }
+    /// Persists the game's battery-backed save RAM in `path`, like a RetroArch `.srm` file.
+    /// The file is loaded into save RAM once the game is loaded, if it exists, and written back
+    /// by [`Emulator::flush_sram`], periodically if [`Emulator::set_sram_autoflush`] is used, and when the emulator is dropped.
+    #[must_use]
+    pub fn sram_path(mut self, path: &Path) -> Self {
+        self.sram_path = Some(path.to_path_buf());
+        self
+    }
+    /// Connects `device` to `port` once the game is loaded, e.g. a multitap for four-player games.
+    /// See [`Emulator::set_controller_port_device`].
+    #[must_use]
+    pub fn port_device(mut self, port: u32, device: u32) -> Self {
+        self.port_devices.push((port, device));
+        self
+    }
+    /// Sets every option in a RetroArch-style `key = "value"` file before the game is loaded.
+    /// Options given later, including with [`EmulatorBuilder::option`], take precedence.
+    /// Keys the core doesn't declare are reported as [`Diagnostic::UndeclaredCoreOption`] once the
+    /// game is loaded, and listed by [`Emulator::undeclared_core_options`].
+    /// # Errors
+    /// [`RetroRsError::CoreOptionsFileError`]: The file couldn't be read
+    pub fn options_file(mut self, path: &Path) -> Result<Self, RetroRsError> {
+        self.options.extend(read_core_options_file(path)?);
+        Ok(self)
+    }
+    /// # Errors
+    /// See [`Emulator::try_create_with_gfx`].
+    /// [`RetroRsError::CoreOptionInvalidValueError`]: An option value contains a NUL byte
+    /// [`RetroRsError::SramIOError`]: The SRAM file exists but couldn't be read
+    #[allow(clippy::missing_panics_doc, clippy::too_many_lines)]
+    pub fn build(self) -> Result<Emulator, RetroRsError> {
+        let mut options = CoreOptions::default();
+        for (key, value) in &self.options {
+            if !options.set(key, value) {
+                return Err(RetroRsError::CoreOptionInvalidValueError(
+                    key.clone(),
+                    value.clone(),
+                ));
+            }
+        }
+        let suffix = if cfg!(target_os = "windows") {
+            "dll"
+        } else if cfg!(target_os = "macos") {
+            "dylib"
+        } else if cfg!(target_os = "linux") {
+            "so"
+        } else {
+            return Err(RetroRsError::UnsupportedPlatformError);
+        };
+        let path: PathBuf = self.core_path.with_extension(suffix);
+        let core_dir = path_to_cstring(self.core_path.parent().unwrap_or(Path::new("")))?;
+        let rom_cstr = path_to_cstring(&self.rom_path)?;
+        let mut buffer = std::fs::read(&self.rom_path).map_err(RetroRsError::ROMIOError)?;
+        buffer.shrink_to_fit();
+        let dll = core_library::acquire(&path)?;
+        let core = match unsafe { CoreFns::load(&dll) } {
+            Ok(core) => core,
+            Err(err) => {
+                core_library::release(&path, dll);
+                return Err(err);
+            }
+        };
+        let emu = EmulatorCore {
+            core_lib: ManuallyDrop::new(dll),
+            lib_path: path,
+            rom_path: rom_cstr,
+            rom_sha1: sha1_smol::Sha1::from(&buffer).digest().to_string(),
+            core,
+            _marker: PhantomData,
+        };
+        let ctx = Box::new(RefCell::new(EmulatorContext::new(
+            core_dir,
+            options,
+            self.gfx,
+            self.log_callback,
+        )));
+        let active = ActiveContext::enter(&ctx);
+        unsafe {
+            // Set up callbacks
+            (emu.core.retro_set_environment)(Some(callback_environment));
+            (emu.core.retro_set_video_refresh)(Some(callback_video_refresh));
+            (emu.core.retro_set_audio_sample)(Some(callback_audio_sample));
+            (emu.core.retro_set_audio_sample_batch)(Some(callback_audio_sample_batch));
+            (emu.core.retro_set_input_poll)(Some(callback_input_poll));
+            (emu.core.retro_set_input_state)(Some(callback_input_state));
+            // Load the core and game
+            (emu.core.retro_init)();
+            let game_info = retro_game_info {
+                path: emu.rom_path.as_ptr(),
+                data: buffer.as_ptr().cast(),
+                size: buffer.len(),
+                meta: ptr::null(),
+            };
+            if !(emu.core.retro_load_game)(&raw const game_info) {
+                (emu.core.retro_deinit)();
+                return Err(RetroRsError::ContentRejectedError);
+            }
+            let mut ctx = ctx.borrow_mut();
+            (emu.core.retro_get_system_info)(&raw mut ctx.sys_info);
+            (emu.core.retro_get_system_av_info)(&raw mut ctx.av_info);
+            // Cores declare their options by the time the game is loaded
+            ctx.options.report_undeclared();
+        }
+        drop(active);
+        let mut emu = Emulator {
+            core: emu,
+            frame_count: 0,
+            sram: None,
+            ctx,
+        };
+        for (port, device) in self.port_devices {
+            emu.set_controller_port_device(port, device);
+        }
+        if let Some(path) = self.sram_path {
+            // Only attach the file once it's been read, so a failed read can't be clobbered on drop
+            emu.sram = Some(SramFile::open(path, emu.save_ram_mut())?);
+        }
+        Ok(emu)
+    }
+}
+
+unsafe fn core_symbol<T: Copy>(dll: &Library, name: &str) -> Result<T, RetroRsError> {
+    unsafe { dll.get::<T>(name.as_bytes()) }
+        .map(|sym| *sym)
+        .map_err(|_| RetroRsError::CoreSymbolMissingError(name.to_owned()))
+}
+
+pub(crate) fn cstr_to_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+fn path_to_cstring(path: &Path) -> Result<CString, RetroRsError> {
+    path.to_str()
+        .and_then(|s| CString::new(s).ok())
+        .ok_or_else(|| RetroRsError::InvalidPathError(path.to_path_buf()))
+}
+
 pub type ButtonCallback = Box<dyn Fn(u32, u32, u32, u32) -> i16>;
+/// Receives each message the core logs, already formatted and without its trailing newline.
+pub type LogCallback = Box<dyn Fn(retro_log_level, &str)>;
+// Rc so that the callback can be called without holding the context borrowed
+type SharedLogCallback = Rc<dyn Fn(retro_log_level, &str)>;
 
 #[allow(dead_code)]
 struct EmulatorContext {
     audio_sample: Vec<i16>,
-    buttons: [Buttons; 2],
+    inputs: Vec<Input>,
+    // Devices connected with retro_set_controller_port_device, by port; ports are arbitrary u32s
+    port_devices: BTreeMap<u32, u32>,
+    controller_info: Vec<Vec<ControllerDescription>>,
+    input_descriptors: Vec<InputDescriptor>,
+    // What the core did with input during the last frame
+    input_polled: bool,
+    input_queries: Vec<InputQuery>,
+    keyboard: Keyboard,
+    keyboard_callback: retro_keyboard_event_t,
     button_callback: Option<ButtonCallback>,
+    log_callback: Option<SharedLogCallback>,
     core_path: CString,
     frame_ptr: *const c_void,
     frame_pitch: usize,
@@ -75,12 +354,88 @@ struct EmulatorContext {
     pixfmt: retro_pixel_format,
     image_depth: usize,
     memory_map: Vec<retro_memory_descriptor>,
+    options: CoreOptions,
     av_info: retro_system_av_info,
     sys_info: retro_system_info,
     gfx: Box<dyn Gfx>,
     _marker: PhantomData<NotSendSync>,
 }
 
+impl EmulatorContext {
+    fn new(
+        core_path: CString,
+        options: CoreOptions,
+        gfx: Box<dyn Gfx>,
+        log_callback: Option<LogCallback>,
+    ) -> Self {
+        let sys_info = retro_system_info {
+            library_name: ptr::null(),
+            library_version: ptr::null(),
+            valid_extensions: ptr::null(),
+            need_fullpath: false,
+            block_extract: false,
+        };
+        let av_info = retro_system_av_info {
+            geometry: retro_game_geometry {
+                base_width: 0,
+                base_height: 0,
+                max_width: 0,
+                max_height: 0,
+                aspect_ratio: 0.0,
+            },
+            timing: retro_system_timing {
+                fps: 0.0,
+                sample_rate: 0.0,
+            },
+        };
+        EmulatorContext {
+            av_info,
+            sys_info,
+            core_path,
+            audio_sample: Vec::new(),
+            inputs: Vec::new(),
+            port_devices: BTreeMap::new(),
+            controller_info: Vec::new(),
+            input_descriptors: Vec::new(),
+            input_polled: false,
+            input_queries: Vec::new(),
+            keyboard: Keyboard::default(),
+            keyboard_callback: None,
+            button_callback: None,
+            log_callback: log_callback.map(Rc::from),
+            frame_ptr: ptr::null(),
+            frame_pitch: 0,
+            frame_width: 0,
+            frame_height: 0,
+            pixfmt: retro_pixel_format::RETRO_PIXEL_FORMAT_0RGB1555,
+            image_depth: 0,
+            memory_map: Vec::new(),
+            options,
+            gfx,
+            _marker: PhantomData,
+        }
+    }
+    fn queried_buttons(&self, port: u32) -> Buttons {
+        let bits = self
+            .input_queries
+            .iter()
+            .filter(|q| {
+                q.port == port
+                    && q.device & RETRO_DEVICE_MASK == RETRO_DEVICE_JOYPAD
+                    && q.index == 0
+            })
+            .fold(0, |bits, q| match q.id {
+                RETRO_DEVICE_ID_JOYPAD_MASK => -1,
+                0..16 => bits | (1 << q.id),
+                _ => bits,
+            });
+        Buttons::from(bits)
+    }
+    fn is_lag_frame(&self) -> bool {
+        self.input_queries.is_empty()
+    }
+}
+
 // A more pleasant wrapper over MemoryDescriptor
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MemoryRegion {
@@ -96,186 +451,108 @@ pub struct MemoryRegion {
 
 pub struct Emulator {
     core: EmulatorCore,
+    frame_count: u64,
+    sram: Option<SramFile>,
+    // Boxed so the callbacks' pointer to it survives the Emulator being moved
+    ctx: Box<RefCell<EmulatorContext>>,
+}
+
+struct SramFile {
+    path: PathBuf,
+    // The contents as of the last load or flush, to skip writing unchanged SRAM
+    flushed: Vec<u8>,
+    autoflush: Option<u64>,
+}
+
+impl SramFile {
+    // Loads the file into `sram`, if it exists
+    fn open(path: PathBuf, sram: &mut [u8]) -> Result<SramFile, RetroRsError> {
+        match std::fs::read(&path) {
+            Ok(saved) => {
+                let len = saved.len().min(sram.len());
+                sram[..len].copy_from_slice(&saved[..len]);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(RetroRsError::SramIOError(err)),
+        }
+        Ok(SramFile {
+            path,
+            flushed: sram.to_vec(),
+            autoflush: None,
+        })
+    }
+    // Writes `sram` to the file if it has changed, returning whether it did
+    fn flush(&mut self, sram: &[u8]) -> Result<bool, RetroRsError> {
+        if sram == self.flushed.as_slice() {
+            return Ok(false);
+        }
+        std::fs::write(&self.path, sram).map_err(RetroRsError::SramIOError)?;
+        self.flushed = sram.to_vec();
+        Ok(true)
+    }
 }
 
 impl Emulator {
     /// # Panics
-    /// If the platform is not Windows, Mac, or Linux; if the dylib fails to load successfully; if any Emulator has been created on this thread but not yet dropped.
+    /// If the platform is not Windows, Mac, or Linux; if the dylib fails to load successfully.
+    /// See [`Emulator::try_create`] for a version which returns these failures as errors.
     #[must_use]
     pub fn create(core_path: &Path, rom_path: &Path) -> Emulator {
         Self::create_with_gfx(core_path, rom_path, Box::new(crate::SoftwareGfx::default()))
     }
     /// # Panics
-    /// If the platform is not Windows, Mac, or Linux; if the dylib fails to load successfully; if any Emulator has been created on this thread but not yet dropped.
+    /// If the platform is not Windows, Mac, or Linux; if the dylib fails to load successfully.
+    /// See [`Emulator::try_create_with_gfx`] for a version which returns these failures as errors.
     #[must_use]
-    #[allow(clippy::too_many_lines)]
     pub fn create_with_gfx(core_path: &Path, rom_path: &Path, gfx: Box<dyn Gfx>) -> Emulator {
-        let emu = CTX.with_borrow_mut(move |ctx_opt| {
-            assert!(
-                ctx_opt.is_none(),
-                "Can't use multiple emulators in one thread currently"
-            );
-            let suffix = if cfg!(target_os = "windows") {
-                "dll"
-            } else if cfg!(target_os = "macos") {
-                "dylib"
-            } else if cfg!(target_os = "linux") {
-                "so"
-            } else {
-                panic!("Unsupported platform")
-            };
-            let path: PathBuf = core_path.with_extension(suffix);
-            let core_path = core_path.parent().unwrap();
-            #[cfg(target_os = "linux")]
-            let dll: Library = unsafe {
-                use libc::RTLD_NODELETE;
-                use libloading::os::unix::{self, RTLD_LOCAL, RTLD_NOW};
-                // Load library with `RTLD_NOW | RTLD_LOCAL | RTLD_NODELETE` to fix a SIGSEGV
-                unix::Library::open(Some(path), RTLD_NOW | RTLD_LOCAL | RTLD_NODELETE)
-                    .unwrap()
-                    .into()
-            };
-            #[cfg(not(target_os = "linux"))]
-            let dll = unsafe { Library::new(path).unwrap() };
-            unsafe {
-                let retro_set_environment = *(dll.get(b"retro_set_environment").unwrap());
-                let retro_set_video_refresh = *(dll.get(b"retro_set_video_refresh").unwrap());
-                let retro_set_audio_sample = *(dll.get(b"retro_set_audio_sample").unwrap());
-                let retro_set_audio_sample_batch =
-                    *(dll.get(b"retro_set_audio_sample_batch").unwrap());
-                let retro_set_input_poll = *(dll.get(b"retro_set_input_poll").unwrap());
-                let retro_set_input_state = *(dll.get(b"retro_set_input_state").unwrap());
-                let retro_init = *(dll.get(b"retro_init").unwrap());
-                let retro_deinit = *(dll.get(b"retro_deinit").unwrap());
-                let retro_api_version = *(dll.get(b"retro_api_version").unwrap());
-                let retro_get_system_info = *(dll.get(b"retro_get_system_info").unwrap());
-                let retro_get_system_av_info = *(dll.get(b"retro_get_system_av_info").unwrap());
-                let retro_set_controller_port_device =
-                    *(dll.get(b"retro_set_controller_port_device").unwrap());
-                let retro_reset = *(dll.get(b"retro_reset").unwrap());
-                let retro_run = *(dll.get(b"retro_run").unwrap());
-                let retro_serialize_size = *(dll.get(b"retro_serialize_size").unwrap());
-                let retro_serialize = *(dll.get(b"retro_serialize").unwrap());
-                let retro_unserialize = *(dll.get(b"retro_unserialize").unwrap());
-                let retro_cheat_reset = *(dll.get(b"retro_cheat_reset").unwrap());
-                let retro_cheat_set = *(dll.get(b"retro_cheat_set").unwrap());
-                let retro_load_game = *(dll.get(b"retro_load_game").unwrap());
-                let retro_load_game_special = *(dll.get(b"retro_load_game_special").unwrap());
-                let retro_unload_game = *(dll.get(b"retro_unload_game").unwrap());
-                let retro_get_region = *(dll.get(b"retro_get_region").unwrap());
-                let retro_get_memory_data = *(dll.get(b"retro_get_memory_data").unwrap());
-                let retro_get_memory_size = *(dll.get(b"retro_get_memory_size").unwrap());
-                let emu = EmulatorCore {
-                    core_lib: dll,
-                    rom_path: CString::new(rom_path.to_str().unwrap()).unwrap(),
-                    core: CoreFns {
-                        retro_api_version,
-                        retro_cheat_reset,
-                        retro_cheat_set,
-                        retro_deinit,
-                        retro_get_memory_data,
-                        retro_get_memory_size,
-
-                        retro_get_region,
-                        retro_get_system_av_info,
-
-                        retro_get_system_info,
-
-                        retro_init,
-                        retro_load_game,
-                        retro_load_game_special,
-
-                        retro_reset,
-                        retro_run,
-
-                        retro_serialize,
-                        retro_serialize_size,
-                        retro_set_audio_sample,
-
-                        retro_set_audio_sample_batch,
-                        retro_set_controller_port_device,
-
-                        retro_set_environment,
-                        retro_set_input_poll,
-                        retro_set_input_state,
-
-                        retro_set_video_refresh,
-                        retro_unload_game,
-                        retro_unserialize,
-                    },
-                    _marker: PhantomData,
-                };
-                let sys_info = retro_system_info {
-                    library_name: ptr::null(),
-                    library_version: ptr::null(),
-                    valid_extensions: ptr::null(),
-                    need_fullpath: false,
-                    block_extract: false,
-                };
-                let av_info = retro_system_av_info {
-                    geometry: retro_game_geometry {
-                        base_width: 0,
-                        base_height: 0,
-                        max_width: 0,
-                        max_height: 0,
-                        aspect_ratio: 0.0,
-                    },
-                    timing: retro_system_timing {
-                        fps: 0.0,
-                        sample_rate: 0.0,
-                    },
-                };
-
-                let ctx = EmulatorContext {
-                    av_info,
-                    sys_info,
-                    core_path: CString::new(core_path.to_str().unwrap()).unwrap(),
-                    audio_sample: Vec::new(),
-                    buttons: [Buttons::new(), Buttons::new()],
-                    button_callback: None,
-                    frame_ptr: ptr::null(),
-                    frame_pitch: 0,
-                    frame_width: 0,
-                    frame_height: 0,
-                    pixfmt: retro_pixel_format::RETRO_PIXEL_FORMAT_0RGB1555,
-                    image_depth: 0,
-                    memory_map: Vec::new(),
-                    gfx,
-                    _marker: PhantomData,
-                };
-                *ctx_opt = Some(ctx);
-                emu
-            }
-        });
-        unsafe {
-            // Set up callbacks
-            (emu.core.retro_set_environment)(Some(callback_environment));
-            (emu.core.retro_set_video_refresh)(Some(callback_video_refresh));
-            (emu.core.retro_set_audio_sample)(Some(callback_audio_sample));
-            (emu.core.retro_set_audio_sample_batch)(Some(callback_audio_sample_batch));
-            (emu.core.retro_set_input_poll)(Some(callback_input_poll));
-            (emu.core.retro_set_input_state)(Some(callback_input_state));
-            // Load the core and game
-            (emu.core.retro_init)();
-            let rom_cstr = emu.rom_path.clone();
-            let mut rom_file = File::open(rom_path).unwrap();
-            let mut buffer = Vec::new();
-            rom_file.read_to_end(&mut buffer).unwrap();
-            buffer.shrink_to_fit();
-            let game_info = retro_game_info {
-                path: rom_cstr.as_ptr(),
-                data: buffer.as_ptr().cast(),
-                size: buffer.len(),
-                meta: ptr::null(),
-            };
-            (emu.core.retro_load_game)(&raw const game_info);
-            CTX.with_borrow_mut(|ctx| {
-                let ctx = ctx.as_mut().unwrap();
-                (emu.core.retro_get_system_info)(&raw mut ctx.sys_info);
-                (emu.core.retro_get_system_av_info)(&raw mut ctx.av_info);
-            });
+        match Self::try_create_with_gfx(core_path, rom_path, gfx) {
+            Ok(emu) => emu,
+            Err(err) => panic!("{err}"),
         }
-        Emulator { core: emu }
+    }
+    /// # Errors
+    /// See [`Emulator::try_create_with_gfx`].
+    pub fn try_create(core_path: &Path, rom_path: &Path) -> Result<Emulator, RetroRsError> {
+        Self::try_create_with_gfx(core_path, rom_path, Box::new(crate::SoftwareGfx::default()))
+    }
+    /// # Errors
+    /// [`RetroRsError::UnsupportedPlatformError`]: The platform is not Windows, Mac, or Linux
+    /// [`RetroRsError::InvalidPathError`]: The core or ROM path can't be passed to the core as a C string
+    /// [`RetroRsError::ROMIOError`]: The ROM file couldn't be read
+    /// [`RetroRsError::CoreLoadError`]: The core dylib failed to load
+    /// [`RetroRsError::CoreCopyError`]: The core is already in use and couldn't be copied for this instance
+    /// [`RetroRsError::CoreSymbolMissingError`]: The core dylib doesn't export a required libretro function
+    /// [`RetroRsError::ContentRejectedError`]: The core's `retro_load_game` refused the ROM
+    pub fn try_create_with_gfx(
+        core_path: &Path,
+        rom_path: &Path,
+        gfx: Box<dyn Gfx>,
+    ) -> Result<Emulator, RetroRsError> {
+        Self::builder(core_path, rom_path).gfx(gfx).build()
+    }
+    /// Starts configuring an [`Emulator`] which needs settings applied before its game is loaded.
+    #[must_use]
+    pub fn builder(core_path: &Path, rom_path: &Path) -> EmulatorBuilder {
+        EmulatorBuilder {
+            core_path: core_path.to_path_buf(),
+            rom_path: rom_path.to_path_buf(),
+            gfx: Box::new(crate::SoftwareGfx::default()),
+            options: Vec::new(),
+            log_callback: None,
+            sram_path: None,
+            port_devices: Vec::new(),
+        }
+    }
+    fn with_ctx<R>(&self, f: impl FnOnce(&EmulatorContext) -> R) -> R {
+        f(&self.ctx.borrow())
+    }
+    fn with_ctx_mut<R>(&self, f: impl FnOnce(&mut EmulatorContext) -> R) -> R {
+        f(&mut self.ctx.borrow_mut())
+    }
+    // Calls into the core with this emulator's context visible to the libretro callbacks
+    fn call_core<R>(&self, f: impl FnOnce(&CoreFns) -> R) -> R {
+        let _active = ActiveContext::enter(&self.ctx);
+        f(&self.core.core)
     }
     pub fn get_library(&mut self) -> &Library {
         &self.core.core_lib
@@ -286,58 +563,204 @@ impl Emulator {
         let sym: Result<Symbol<T>, _> = unsafe { dll.get(symbol) };
         sym.ok()
     }
-    #[allow(clippy::missing_panics_doc)]
     pub fn run(&mut self, inputs: [Buttons; 2]) {
-        CTX.with_borrow_mut(|ctx| {
-            let ctx = ctx.as_mut().unwrap();
+        self.run_with_input(&inputs.map(Input::from));
+    }
+    /// Like [`Emulator::run`], but with analog sticks, mice, light guns and pointers as well as buttons,
+    /// on any number of ports. Ports past the end of `inputs` read as idle.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn run_with_input(&mut self, inputs: &[Input]) {
+        self.with_ctx_mut(|ctx| {
             //clear audio buffers and whatever else
             ctx.audio_sample.clear();
             //set inputs on CB
-            ctx.buttons = inputs;
+            ctx.inputs.clear();
+            ctx.inputs.extend_from_slice(inputs);
+            ctx.input_polled = false;
+            ctx.input_queries.clear();
             ctx.button_callback = None;
             ctx.gfx.bind();
         });
-        unsafe {
-            //run one step
-            (self.core.core.retro_run)();
-        }
-        CTX.with_borrow_mut(|ctx| {
-            let ctx = ctx.as_mut().unwrap();
+        //run one step
+        self.run_core();
+        self.frame_count += 1;
+        self.with_ctx_mut(|ctx| {
             ctx.gfx.unbind();
         });
+        self.autoflush_sram();
     }
     #[allow(clippy::missing_panics_doc)]
     pub fn run_with_button_callback(&mut self, input: Box<dyn Fn(u32, u32, u32, u32) -> i16>) {
-        CTX.with_borrow_mut(|ctx| {
-            let ctx = ctx.as_mut().unwrap();
+        self.with_ctx_mut(|ctx| {
             //clear audio buffers and whatever else
             ctx.audio_sample.clear();
             //set inputs on CB
             ctx.button_callback = Some(Box::new(input));
+            ctx.input_polled = false;
+            ctx.input_queries.clear();
             ctx.gfx.bind();
         });
-        unsafe {
-            //run one step
-            (self.core.core.retro_run)();
-        }
-        CTX.with_borrow_mut(|ctx| {
-            let ctx = ctx.as_mut().unwrap();
+        //run one step
+        self.run_core();
+        self.frame_count += 1;
+        self.with_ctx_mut(|ctx| {
             ctx.gfx.unbind();
         });
+        self.autoflush_sram();
+    }
+    // Delivers this frame's key events and runs the core for a frame
+    fn run_core(&mut self) {
+        let (events, callback) =
+            self.with_ctx_mut(|ctx| (ctx.keyboard.next_frame(), ctx.keyboard_callback));
+        self.call_core(|core| unsafe {
+            send_key_events(callback, events);
+            (core.retro_run)();
+        });
+    }
+    /// How many times [`Emulator::run`] or [`Emulator::run_with_button_callback`] has been called on this emulator.
+    /// Resets and savestate loads don't change it, but [`crate::RewindBuffer::rewind`] and [`crate::Savestate::restore`] restore the count saved with the state.
+    #[must_use]
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
     }
+    pub(crate) fn set_frame_count(&mut self, frame_count: u64) {
+        self.frame_count = frame_count;
+    }
+    /// The lowercase hex SHA-1 of the ROM file's contents.
+    #[must_use]
+    pub fn rom_sha1(&self) -> &str {
+        &self.core.rom_sha1
+    }
+    /// The core's `library_name` from `retro_get_system_info`.
+    #[must_use]
+    pub fn get_library_name(&self) -> String {
+        self.with_ctx(|ctx| cstr_to_string(ctx.sys_info.library_name))
+    }
+    /// The core's `library_version` from `retro_get_system_info`.
+    #[must_use]
+    pub fn get_library_version(&self) -> String {
+        self.with_ctx(|ctx| cstr_to_string(ctx.sys_info.library_version))
+    }
+    /// Tells the core which kind of device is plugged into `port`, e.g. [`RETRO_DEVICE_ANALOG`] for a
+    /// DualShock or a [`subclass`](crate::input::subclass) of a base device that the core declares,
+    /// such as a multitap. Cores assume a [`RETRO_DEVICE_JOYPAD`] until told otherwise.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn set_controller_port_device(&mut self, port: u32, device: u32) {
+        self.with_ctx_mut(|ctx| ctx.port_devices.insert(port, device));
+        self.call_core(|core| unsafe { (core.retro_set_controller_port_device)(port, device) });
+    }
+    /// The device last connected to `port` with [`Emulator::set_controller_port_device`].
+    #[must_use]
+    pub fn controller_port_device(&self, port: u32) -> u32 {
+        self.with_ctx(|ctx| {
+            ctx.port_devices
+                .get(&port)
+                .copied()
+                .unwrap_or(RETRO_DEVICE_JOYPAD)
+        })
+    }
+    /// The devices the core accepts on each port, in port order, or nothing if the core didn't say.
+    #[must_use]
+    pub fn controller_info(&self) -> Vec<Vec<ControllerDescription>> {
+        self.with_ctx(|ctx| ctx.controller_info.clone())
+    }
+    /// The buttons and axes the loaded game uses and what they do, or nothing if the core didn't say.
+    #[must_use]
+    pub fn input_descriptors(&self) -> Vec<InputDescriptor> {
+        self.with_ctx(|ctx| ctx.input_descriptors.clone())
+    }
+    /// The joypad buttons the core declared for `port`, for trimming an action space down to
+    /// the buttons a game reads. All buttons if the core declared none for any port.
+    #[must_use]
+    pub fn declared_buttons(&self, port: u32) -> Buttons {
+        self.with_ctx(|ctx| {
+            if ctx.input_descriptors.is_empty() {
+                return Buttons::from(-1);
+            }
+            let bits = ctx
+                .input_descriptors
+                .iter()
+                .filter(|d| {
+                    d.port == port
+                        && d.device & RETRO_DEVICE_MASK == RETRO_DEVICE_JOYPAD
+                        && d.id < 16
+                })
+                .fold(0, |bits, d| bits | (1 << d.id));
+            Buttons::from(bits)
+        })
+    }
+    /// Presses `key` at the start of the next [`Emulator::run`], for computer cores' keyboards.
+    /// The key reads as held to `RETRO_DEVICE_KEYBOARD` queries on any port, and is passed to the
+    /// core's keyboard callback if it registered one.
+    pub fn key_down(&mut self, key: retro_key) {
+        self.with_ctx_mut(|ctx| ctx.keyboard.queue(key, true));
+    }
+    /// Releases `key` at the start of the next [`Emulator::run`].
+    pub fn key_up(&mut self, key: retro_key) {
+        self.with_ctx_mut(|ctx| ctx.keyboard.queue(key, false));
+    }
+    /// Types `text` as a US keyboard would, over the frames after any keystrokes already queued:
+    /// each key is held for `hold_frames` runs (at least one), then released for one run, with
+    /// shift held for capitals and symbols, or released for other characters if it's already held.
+    /// Characters without a key are sent with [`retro_key::RETROK_UNKNOWN`] for cores which read
+    /// the event's character.
+    pub fn type_text(&mut self, text: &str, hold_frames: usize) {
+        self.with_ctx_mut(|ctx| ctx.keyboard.queue_text(text, hold_frames.max(1)));
+    }
+    /// Whether `key` is held, as of the last [`Emulator::run`].
+    #[must_use]
+    pub fn is_key_pressed(&self, key: retro_key) -> bool {
+        self.with_ctx(|ctx| ctx.keyboard.is_pressed(key))
+    }
+    /// How many more runs it will take to deliver the queued keystrokes.
+    #[must_use]
+    pub fn pending_key_frames(&self) -> usize {
+        self.with_ctx(|ctx| ctx.keyboard.pending_frames())
+    }
+    /// Whether the core polled input during the last [`Emulator::run`].
+    #[must_use]
+    pub fn input_polled(&self) -> bool {
+        self.with_ctx(|ctx| ctx.input_polled)
+    }
+    /// Each distinct input the core read during the last [`Emulator::run`], in the order it first read them.
+    #[must_use]
+    pub fn input_queries(&self) -> Vec<InputQuery> {
+        self.with_ctx(|ctx| ctx.input_queries.clone())
+    }
+    /// The joypad buttons the core read on `port` during the last [`Emulator::run`], or all of
+    /// them if it read the whole bitmask at once.
+    #[must_use]
+    pub fn queried_buttons(&self, port: u32) -> Buttons {
+        self.with_ctx(|ctx| ctx.queried_buttons(port))
+    }
+    /// Whether the last [`Emulator::run`] was a lag frame as the frontend sees it: the core read no
+    /// input through `retro_input_state_t`. Polling alone doesn't count. How well this matches
+    /// the game's own lag depends on the core, since some read every input each frame whether or
+    /// not the game asks and so never report lag.
+    #[must_use]
+    pub fn is_lag_frame(&self) -> bool {
+        self.with_ctx(EmulatorContext::is_lag_frame)
+    }
+    /// Resets the core, first releasing any held keys and dropping queued keystrokes.
     #[allow(clippy::missing_panics_doc)]
     pub fn reset(&mut self) {
-        CTX.with_borrow_mut(|ctx| {
-            let ctx = ctx.as_mut().unwrap();
+        let (releases, callback) = self.with_ctx_mut(|ctx| {
             // clear audio buffers and whatever else
             ctx.audio_sample.clear();
             // set inputs on CB
-            ctx.buttons = [Buttons::new(), Buttons::new()];
+            ctx.inputs.clear();
             ctx.button_callback = None;
+            ctx.input_polled = false;
+            ctx.input_queries.clear();
+            let releases = ctx.keyboard.release_all();
             // clear fb
             ctx.frame_ptr = ptr::null();
+            (releases, ctx.keyboard_callback)
+        });
+        self.call_core(|core| unsafe {
+            send_key_events(callback, releases);
+            (core.retro_reset)();
         });
-        unsafe { (self.core.core.retro_reset)() }
     }
     #[must_use]
     fn get_ram_size(&self, rtype: libc::c_uint) -> usize {
@@ -371,6 +794,46 @@ impl Emulator {
     pub fn save_ram(&self) -> &[u8] {
         self.get_ram(RETRO_MEMORY_SAVE_RAM)
     }
+    /// Changes made here are persisted like the game's own if an SRAM path was given to [`EmulatorBuilder::sram_path`].
+    #[must_use]
+    pub fn save_ram_mut(&mut self) -> &mut [u8] {
+        self.get_ram_mut(RETRO_MEMORY_SAVE_RAM)
+    }
+    /// The file save RAM is persisted in, if any.
+    #[must_use]
+    pub fn sram_path(&self) -> Option<&Path> {
+        self.sram.as_ref().map(|sram| sram.path.as_path())
+    }
+    /// Writes save RAM to its file if it has changed since it was loaded or last flushed.
+    /// Returns whether the file was written; it never is without an SRAM path.
+    /// # Errors
+    /// [`RetroRsError::SramIOError`]: The file couldn't be written
+    pub fn flush_sram(&mut self) -> Result<bool, RetroRsError> {
+        let Some(mut sram) = self.sram.take() else {
+            return Ok(false);
+        };
+        let flushed = sram.flush(self.save_ram());
+        self.sram = Some(sram);
+        flushed
+    }
+    /// Flushes save RAM every `frames` frames, or stops doing so if `None`.
+    /// Failed flushes are reported as [`Diagnostic::SramFlushFailed`] and retried on the next interval.
+    /// Has no effect without an SRAM path.
+    pub fn set_sram_autoflush(&mut self, frames: Option<u64>) {
+        if let Some(sram) = &mut self.sram {
+            sram.autoflush = frames.filter(|&f| f > 0);
+        }
+    }
+    fn autoflush_sram(&mut self) {
+        let due = self
+            .sram
+            .as_ref()
+            .and_then(|sram| sram.autoflush)
+            .is_some_and(|interval| self.frame_count.is_multiple_of(interval));
+        if due && let Err(err) = self.flush_sram() {
+            diagnostics::emit(&Diagnostic::SramFlushFailed(err.to_string()));
+        }
+    }
 
     #[must_use]
     fn get_ram(&self, ramtype: libc::c_uint) -> &[u8] {
@@ -388,11 +851,11 @@ impl Emulator {
             std::slice::from_raw_parts_mut(ptr, len)
         }
     }
-    #[allow(clippy::missing_panics_doc, clippy::unused_self)]
+    #[allow(clippy::missing_panics_doc)]
     #[must_use]
     pub fn memory_regions(&self) -> Vec<MemoryRegion> {
-        CTX.with_borrow(|ctx| {
-            let map = &ctx.as_ref().unwrap().memory_map;
+        self.with_ctx(|ctx| {
+            let map = &ctx.memory_map;
             map.iter()
                 .enumerate()
                 .map(|(i, mdesc)| MemoryRegion {
@@ -416,16 +879,14 @@ impl Emulator {
     }
     /// # Errors
     /// [`RetroRsError::RAMCopyNotMappedIntoMemoryRegionError`]: Returns an error if the desired address is not mapped into memory regions
-    /// # Panics
-    /// If called on a thread without a running emulator core
     pub fn memory_ref(&self, start: usize) -> Result<&[u8], RetroRsError> {
         for mr in self.memory_regions() {
             if mr.select != 0 && (start & mr.select) == 0 {
                 continue;
             }
             if start >= mr.start && start < mr.start + mr.len {
-                return CTX.with_borrow(|ctx| {
-                    let maps = &ctx.as_ref().unwrap().memory_map;
+                return self.with_ctx(|ctx| {
+                    let maps = &ctx.memory_map;
                     if mr.which >= maps.len() {
                         // TODO more aggressive checking of mr vs map
                         return Err(RetroRsError::RAMMapOutOfRangeError);
@@ -446,7 +907,7 @@ impl Emulator {
         }
         Err(RetroRsError::RAMCopyNotMappedIntoMemoryRegionError)
     }
-    #[allow(clippy::missing_panics_doc, clippy::unused_self)]
+    #[allow(clippy::missing_panics_doc)]
     /// # Errors
     /// [`RetroRsError::RAMMapOutOfRangeError`]: The desired address is out of mapped range
     /// [`RetroRsError::RAMCopySrcOutOfBoundsError`]: The desired range is not in the requested region
@@ -455,8 +916,8 @@ impl Emulator {
         mr: &MemoryRegion,
         start: usize,
     ) -> Result<&mut [u8], RetroRsError> {
-        CTX.with_borrow_mut(|ctx| {
-            let maps = &mut ctx.as_mut().unwrap().memory_map;
+        self.with_ctx_mut(|ctx| {
+            let maps = &mut ctx.memory_map;
             if mr.which >= maps.len() {
                 // TODO more aggressive checking of mr vs map
                 return Err(RetroRsError::RAMMapOutOfRangeError);
@@ -475,33 +936,30 @@ impl Emulator {
             Ok(slice)
         })
     }
-    #[allow(clippy::missing_panics_doc, clippy::unused_self)]
+    #[allow(clippy::missing_panics_doc)]
     #[must_use]
     pub fn pixel_format(&self) -> retro_pixel_format {
-        CTX.with_borrow(|ctx| ctx.as_ref().unwrap().pixfmt)
+        self.with_ctx(|ctx| ctx.pixfmt)
     }
-    #[allow(clippy::missing_panics_doc, clippy::unused_self)]
+    #[allow(clippy::missing_panics_doc)]
     #[must_use]
     pub fn framebuffer_size(&self) -> (usize, usize) {
-        CTX.with_borrow(|ctx| {
-            let ctx = ctx.as_ref().unwrap();
-            (ctx.frame_width as usize, ctx.frame_height as usize)
-        })
+        self.with_ctx(|ctx| (ctx.frame_width as usize, ctx.frame_height as usize))
     }
-    #[allow(clippy::missing_panics_doc, clippy::unused_self)]
+    #[allow(clippy::missing_panics_doc)]
     #[must_use]
     pub fn framebuffer_pitch(&self) -> usize {
-        CTX.with_borrow(|ctx| ctx.as_ref().unwrap().frame_pitch)
+        self.with_ctx(|ctx| ctx.frame_pitch)
     }
-    #[allow(clippy::missing_panics_doc, clippy::unused_self)]
+    #[allow(clippy::missing_panics_doc)]
     /// # Errors
     /// [`RetroRsError::NoFramebufferError`]: Emulator has not created a framebuffer.
+    /// Others: See [`Gfx::sync_framebuffer`].
     pub fn peek_framebuffer<FBPeek, FBPeekRet>(&self, f: FBPeek) -> Result<FBPeekRet, RetroRsError>
     where
         FBPeek: FnOnce(&[u8]) -> FBPeekRet,
     {
-        CTX.with_borrow(|ctx| {
-            let ctx = ctx.as_ref().unwrap();
+        self.with_ctx(|ctx| {
             if ctx.frame_ptr.is_null() {
                 Err(RetroRsError::NoFramebufferError)
             } else {
@@ -509,7 +967,7 @@ impl Emulator {
                     ctx.gfx.sync_framebuffer(std::slice::from_raw_parts_mut(
                         ctx.frame_ptr.cast_mut().cast(),
                         ctx.frame_pitch * ctx.frame_height as usize,
-                    ));
+                    ))?;
                     #[allow(clippy::cast_possible_truncation)]
                     let frame_slice = std::slice::from_raw_parts(
                         ctx.frame_ptr.cast(),
@@ -520,37 +978,113 @@ impl Emulator {
             }
         })
     }
-    #[allow(clippy::missing_panics_doc, clippy::unused_self)]
+    #[allow(clippy::missing_panics_doc)]
     pub fn peek_audio_sample<AudioPeek, AudioPeekRet>(&self, f: AudioPeek) -> AudioPeekRet
     where
         AudioPeek: FnOnce(&[i16]) -> AudioPeekRet,
     {
-        CTX.with_borrow(|ctx| f(&ctx.as_ref().unwrap().audio_sample))
+        self.with_ctx(|ctx| f(&ctx.audio_sample))
     }
-    /// # Panics
-    /// If called on a thread without a running emulator core
     #[must_use]
     pub fn get_audio_sample_rate(&self) -> f64 {
-        CTX.with_borrow_mut(|ctx| ctx.as_ref().unwrap().av_info.timing.sample_rate)
+        self.with_ctx(|ctx| ctx.av_info.timing.sample_rate)
     }
-    /// # Panics
-    /// If called on a thread without a running emulator core
     #[must_use]
     pub fn get_video_fps(&self) -> f64 {
-        CTX.with_borrow_mut(|ctx| ctx.as_ref().unwrap().av_info.timing.fps)
+        self.with_ctx(|ctx| ctx.av_info.timing.fps)
     }
     #[must_use]
     pub fn get_aspect_ratio(&self) -> f32 {
-        CTX.with_borrow_mut(|ctx| ctx.as_ref().unwrap().av_info.geometry.aspect_ratio)
+        self.with_ctx(|ctx| ctx.av_info.geometry.aspect_ratio)
+    }
+    /// The largest `(width, height)` the core says its frames will have, which may be `(0, 0)`
+    /// if the core doesn't say.
+    #[must_use]
+    pub fn max_framebuffer_size(&self) -> (usize, usize) {
+        self.with_ctx(|ctx| {
+            let geometry = ctx.av_info.geometry;
+            (geometry.max_width as usize, geometry.max_height as usize)
+        })
     }
 
+    /// Sends the core's log messages to `callback`, or restores the default destination if `None`.
+    /// By default messages go to the `log` crate (with the `log` feature) or otherwise to stderr.
+    pub fn set_log_callback(&mut self, callback: Option<LogCallback>) {
+        self.with_ctx_mut(|ctx| ctx.log_callback = callback.map(Rc::from));
+    }
+    /// The options the core has declared, in declaration order.
+    #[must_use]
+    pub fn core_options(&self) -> Vec<CoreOption> {
+        self.with_ctx(|ctx| ctx.options.definitions.clone())
+    }
+    /// The option categories the core has declared, if it uses `SET_CORE_OPTIONS_V2`.
+    #[must_use]
+    pub fn core_option_categories(&self) -> Vec<CoreOptionCategory> {
+        self.with_ctx(|ctx| ctx.options.categories.clone())
+    }
+    /// The value the core will see for `key`, whether set explicitly or defaulted.
+    #[must_use]
+    pub fn get_core_option(&self, key: &str) -> Option<String> {
+        self.with_ctx(|ctx| ctx.options.get(key).map(str::to_owned))
+    }
+    /// Changes a core option; the core is told to re-read its options during the next [`Emulator::run`].
+    /// # Errors
+    /// [`RetroRsError::CoreOptionUnknownError`]: The core never declared `key`
+    /// [`RetroRsError::CoreOptionInvalidValueError`]: `value` is not one the core declared for `key`
+    pub fn set_core_option(&mut self, key: &str, value: &str) -> Result<(), RetroRsError> {
+        self.with_ctx_mut(|ctx| {
+            let options = &mut ctx.options;
+            let Some(def) = options.definition(key) else {
+                return Err(RetroRsError::CoreOptionUnknownError(key.to_owned()));
+            };
+            if !def.accepts(value) || !options.set(key, value) {
+                return Err(RetroRsError::CoreOptionInvalidValueError(
+                    key.to_owned(),
+                    value.to_owned(),
+                ));
+            }
+            Ok(())
+        })
+    }
+    /// Keys which were given values (e.g. by [`EmulatorBuilder::options_file`]) but which the core never declared.
+    #[must_use]
+    pub fn undeclared_core_options(&self) -> Vec<String> {
+        self.with_ctx(|ctx| ctx.options.undeclared())
+    }
+    /// Applies a RetroArch-style `key = "value"` options file with [`Emulator::set_core_option`].
+    /// Entries the core rejects are skipped and returned, so callers can warn about them.
+    /// # Errors
+    /// [`RetroRsError::CoreOptionsFileError`]: The file couldn't be read
+    pub fn load_core_options_file(
+        &mut self,
+        path: &Path,
+    ) -> Result<Vec<RetroRsError>, RetroRsError> {
+        Ok(read_core_options_file(path)?
+            .into_iter()
+            .filter_map(|(key, value)| self.set_core_option(&key, &value).err())
+            .collect())
+    }
+    /// Writes the current value of every declared core option to a RetroArch-style options file.
+    /// # Errors
+    /// [`RetroRsError::CoreOptionsFileError`]: The file couldn't be written
+    pub fn save_core_options_file(&self, path: &Path) -> Result<(), RetroRsError> {
+        let options: Vec<(String, String)> = self
+            .core_options()
+            .into_iter()
+            .filter_map(|opt| {
+                let value = self.get_core_option(&opt.key)?;
+                Some((opt.key, value))
+            })
+            .collect();
+        write_core_options_file(path, options.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+    }
     #[must_use]
     pub fn save(&self, bytes: &mut [u8]) -> bool {
         let size = self.save_size();
         if bytes.len() < size {
             return false;
         }
-        unsafe { (self.core.core.retro_serialize)(bytes.as_mut_ptr().cast(), size) }
+        self.call_core(|core| unsafe { (core.retro_serialize)(bytes.as_mut_ptr().cast(), size) })
     }
     #[must_use]
     pub fn load(&mut self, bytes: &[u8]) -> bool {
@@ -558,27 +1092,27 @@ impl Emulator {
         if bytes.len() < size {
             return false;
         }
-        unsafe { (self.core.core.retro_unserialize)(bytes.as_ptr().cast(), size) }
+        self.call_core(|core| unsafe { (core.retro_unserialize)(bytes.as_ptr().cast(), size) })
     }
     #[must_use]
     pub fn save_size(&self) -> usize {
         unsafe { (self.core.core.retro_serialize_size)() }
     }
     pub fn clear_cheats(&mut self) {
-        unsafe { (self.core.core.retro_cheat_reset)() }
+        self.call_core(|core| unsafe { (core.retro_cheat_reset)() });
     }
     /// # Panics
     /// May panic if code can't be converted to a [`CString`]
     pub fn set_cheat(&mut self, index: usize, enabled: bool, code: &str) {
-        unsafe {
+        self.call_core(|core| unsafe {
             // FIXME: Creates a memory leak since the libretro api won't let me from_raw() it back and drop it.  I don't know if libretro guarantees anything about ownership of this str to cores.
             #[allow(clippy::cast_possible_truncation)]
-            (self.core.core.retro_cheat_set)(
+            (core.retro_cheat_set)(
                 index as u32,
                 enabled,
                 CString::new(code).unwrap().into_raw(),
             );
-        }
+        });
     }
     /// # Panics
     /// Panics if the pixel format used by the core is not supported for reads.
@@ -910,114 +1444,164 @@ impl Emulator {
     }
 }
 
+#[allow(clippy::too_many_lines)]
 unsafe extern "C" fn callback_environment(cmd: u32, data: *mut c_void) -> bool {
     let result = panic::catch_unwind(|| {
-        CTX.with_borrow_mut(|ctx| {
-                let ctx = ctx.as_mut().unwrap();
-                match cmd {
-                    RETRO_ENVIRONMENT_SET_CONTROLLER_INFO => true,
-                    RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
-                        let pixfmt = unsafe { *(data as *const retro_pixel_format) };
-                        dbg!(pixfmt);
-                        ctx.image_depth = match pixfmt {
-                            retro_pixel_format::RETRO_PIXEL_FORMAT_0RGB1555 => 15,
-                            retro_pixel_format::RETRO_PIXEL_FORMAT_XRGB8888 => 32,
-                            retro_pixel_format::RETRO_PIXEL_FORMAT_RGB565 => 16,
-                            _ => panic!("Unsupported pixel format"),
-                        };
-                        ctx.pixfmt = pixfmt;
+        with_active_ctx(|ctx| {
+            match cmd {
+                RETRO_ENVIRONMENT_SET_CONTROLLER_INFO => unsafe {
+                    ctx.controller_info = read_controller_info(data.cast());
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_KEYBOARD_CALLBACK => unsafe {
+                    ctx.keyboard_callback = (*data.cast::<retro_keyboard_callback>()).callback;
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS => unsafe {
+                    ctx.input_descriptors = read_input_descriptors(data.cast());
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+                    let pixfmt = unsafe { *(data as *const retro_pixel_format) };
+                    diagnostics::emit(&Diagnostic::PixelFormatNegotiated(pixfmt));
+                    ctx.image_depth = match pixfmt {
+                        retro_pixel_format::RETRO_PIXEL_FORMAT_0RGB1555 => 15,
+                        retro_pixel_format::RETRO_PIXEL_FORMAT_XRGB8888 => 32,
+                        retro_pixel_format::RETRO_PIXEL_FORMAT_RGB565 => 16,
+                        _ => panic!("Unsupported pixel format"),
+                    };
+                    ctx.pixfmt = pixfmt;
+                    true
+                }
+                RETRO_ENVIRONMENT_GET_SYSTEM_DIRECTORY | RETRO_ENVIRONMENT_GET_SAVE_DIRECTORY => unsafe {
+                    *(data.cast()) = ctx.core_path.as_ptr();
+                    true
+                },
+                RETRO_ENVIRONMENT_GET_CAN_DUPE => unsafe {
+                    *(data.cast()) = true;
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_MEMORY_MAPS => unsafe {
+                    let map: *const retro_memory_map = data.cast();
+                    let desc_slice = std::slice::from_raw_parts(
+                        (*map).descriptors,
+                        (*map).num_descriptors as usize,
+                    );
+                    // Don't know who owns map or how long it will last
+                    ctx.memory_map = Vec::new();
+                    // So we had better copy it
+                    ctx.memory_map.extend_from_slice(desc_slice);
+                    // (Implicitly we also want to drop the old one, which we did by reassigning)
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_GEOMETRY => unsafe {
+                    // Only the base size and aspect ratio may change; the maximum is fixed until SET_SYSTEM_AV_INFO
+                    let geometry = &*data.cast::<retro_game_geometry>();
+                    ctx.av_info.geometry.base_width = geometry.base_width;
+                    ctx.av_info.geometry.base_height = geometry.base_height;
+                    ctx.av_info.geometry.aspect_ratio = geometry.aspect_ratio;
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_SYSTEM_AV_INFO => unsafe {
+                    ctx.av_info = *data.cast::<retro_system_av_info>();
+                    true
+                },
+                RETRO_ENVIRONMENT_GET_PREFERRED_HW_RENDER => unsafe {
+                    *(data.cast()) = ctx.gfx.preferred_api() as c_uint;
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_HW_RENDER => unsafe {
+                    /* todo create or provide opengl context */
+                    let hw_render_cb: *mut retro_hw_render_callback = data.cast();
+                    ctx.gfx
+                        .prepare_hardware_context(ctx.av_info, hw_render_cb.as_mut().unwrap())
+                },
+                RETRO_ENVIRONMENT_GET_LOG_INTERFACE => unsafe {
+                    let log_cb: *mut retro_log_callback = data.cast();
+                    *log_cb = retro_log_callback {
+                        log: Some(retrors_log_print),
+                    };
+                    true
+                },
+                RETRO_ENVIRONMENT_GET_VARIABLE => unsafe {
+                    let var: *mut retro_variable = data.cast();
+                    let var = var.as_mut().unwrap();
+                    let key = CStr::from_ptr(var.key.cast()).to_str().unwrap();
+                    if let Some(value) = ctx.options.value_ptr(key) {
+                        var.value = value;
                         true
+                    } else {
+                        false
                     }
-                    RETRO_ENVIRONMENT_GET_SYSTEM_DIRECTORY
-                    | RETRO_ENVIRONMENT_GET_SAVE_DIRECTORY => unsafe {
-                        *(data.cast()) = ctx.core_path.as_ptr();
-                        true
-                    },
-                    RETRO_ENVIRONMENT_GET_CAN_DUPE => unsafe {
-                        *(data.cast()) = true;
-                        true
-                    },
-                    RETRO_ENVIRONMENT_SET_MEMORY_MAPS => unsafe {
-                        let map: *const retro_memory_map = data.cast();
-                        let desc_slice = std::slice::from_raw_parts(
-                            (*map).descriptors,
-                            (*map).num_descriptors as usize,
-                        );
-                        // Don't know who owns map or how long it will last
-                        ctx.memory_map = Vec::new();
-                        // So we had better copy it
-                        ctx.memory_map.extend_from_slice(desc_slice);
-                        // (Implicitly we also want to drop the old one, which we did by reassigning)
-                        true
-                    },
-                    RETRO_ENVIRONMENT_GET_PREFERRED_HW_RENDER => unsafe {
-                        *(data.cast()) = ctx.gfx.preferred_api() as c_uint;
-                        true
-                    },
-                    RETRO_ENVIRONMENT_SET_HW_RENDER => unsafe {
-                        /* todo create or provide opengl context */
-                        let hw_render_cb: *mut retro_hw_render_callback = data.cast();
-                        ctx.gfx
-                            .prepare_hardware_context(ctx.av_info, hw_render_cb.as_mut().unwrap())
-                    },
-                    RETRO_ENVIRONMENT_GET_LOG_INTERFACE => unsafe {
-                        let log_cb: *mut retro_log_callback = data.cast();
-                        *log_cb = retro_log_callback {
-                            log: Some(retrors_log_print),
-                        };
-                        true
-                    },
-                    RETRO_ENVIRONMENT_GET_VARIABLE => unsafe {
-                        let var: *mut retro_variable = data.cast();
-                        let var = var.as_mut().unwrap();
-                        let key = CStr::from_ptr(var.key.cast()).to_str().unwrap();
-                        #[allow(clippy::match_same_arms)]
-                        match key {
-                            "ppsspp_internal_resolution" => {
-                                var.value = c"480x272".as_ptr().cast();
-                                true
-                            },
-                            "ppsspp_backend" => {
-                                var.value = c"opengl".as_ptr().cast();
-                                true
-                            },
-                            "ppsspp_psp_model" => {
-                                var.value = c"psp_2000_3000".as_ptr().cast();
-                                true
-                            },
-                            "ppsspp_cache_iso" => { var.value =c"disabled".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address01" => { var.value =c"e".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address02" => { var.value =c"c".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address03" => { var.value =c"c".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address04" => { var.value =c"a".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address05" => { var.value =c"4".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address06" => { var.value =c"7".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address07" => { var.value =c"b".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address08" => { var.value =c"c".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address09" => { var.value =c"5".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address10" => { var.value =c"b".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address11" => { var.value =c"1".as_ptr().cast(); true },
-                            "ppsspp_change_mac_address12" => { var.value =c"d".as_ptr().cast(); true },
-                            _ => false,
-                        }
-                    },
-                    RETRO_ENVIRONMENT_SHUTDOWN => {
-                        ctx.gfx.destroy_context();
-                        true
-                    },
-                    _ => false,
+                },
+                RETRO_ENVIRONMENT_SET_VARIABLE => unsafe {
+                    // A null argument asks whether the frontend supports this call
+                    if let Some(var) = data.cast::<retro_variable>().as_ref()
+                        && !var.key.is_null()
+                        && !var.value.is_null()
+                    {
+                        let key = CStr::from_ptr(var.key).to_string_lossy();
+                        let value = CStr::from_ptr(var.value).to_string_lossy();
+                        ctx.options.set(&key, &value);
+                    }
+                    true
+                },
+                RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => unsafe {
+                    *(data.cast()) = ctx.options.take_updated();
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_VARIABLES => unsafe {
+                    ctx.options.set_variables(data.cast());
+                    true
+                },
+                RETRO_ENVIRONMENT_GET_CORE_OPTIONS_VERSION => unsafe {
+                    *(data.cast::<c_uint>()) = 2;
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_CORE_OPTIONS => unsafe {
+                    ctx.options.set_core_options(data.cast());
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_CORE_OPTIONS_INTL => unsafe {
+                    let intl: *const retro_core_options_intl = data.cast();
+                    ctx.options.set_core_options((*intl).us);
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_CORE_OPTIONS_V2 => unsafe {
+                    ctx.options.set_core_options_v2(data.cast());
+                    // Returning true tells the core we understand categories
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_CORE_OPTIONS_V2_INTL => unsafe {
+                    let intl: *const retro_core_options_v2_intl = data.cast();
+                    ctx.options.set_core_options_v2((*intl).us);
+                    true
+                },
+                RETRO_ENVIRONMENT_SET_CORE_OPTIONS_DISPLAY => unsafe {
+                    if let Some(disp) = data.cast::<retro_core_option_display>().as_ref()
+                        && !disp.key.is_null()
+                    {
+                        let key = CStr::from_ptr(disp.key).to_string_lossy();
+                        ctx.options.set_visible(&key, disp.visible);
+                    }
+                    true
+                },
+                RETRO_ENVIRONMENT_SHUTDOWN => {
+                    ctx.gfx.destroy_context();
+                    true
                 }
-            })
+                _ => false,
+            }
+        })
     });
-    result.unwrap_or(false)
+    result.ok().flatten().unwrap_or(false)
 }
 
 extern "C" fn callback_video_refresh(data: *const c_void, width: u32, height: u32, pitch: usize) {
     // Can't panic
     // context's framebuffer just points to the given data.  Seems to work OK for gym-retro.
     if !data.is_null() {
-        CTX.with_borrow_mut(|ctx| {
-            let ctx = ctx.as_mut().unwrap();
+        with_active_ctx(|ctx| {
             let pitch = if pitch == 0 {
                 width as usize * 4
             } else {
@@ -1036,10 +1620,11 @@ extern "C" fn callback_video_refresh(data: *const c_void, width: u32, height: u3
                             )));
                         }
                         ctx.pixfmt = retro_pixel_format::RETRO_PIXEL_FORMAT_XRGB8888;
-                        println!(
-                            "real size is {height} * {pitch} = {}",
-                            height as usize * pitch
-                        );
+                        diagnostics::emit(&Diagnostic::HardwareFramebufferAllocated {
+                            width,
+                            height,
+                            pitch,
+                        });
                         ctx.frame_ptr =
                             Box::leak(vec![255; height as usize * pitch].into_boxed_slice())
                                 .as_ptr()
@@ -1058,8 +1643,7 @@ extern "C" fn callback_video_refresh(data: *const c_void, width: u32, height: u3
 }
 extern "C" fn callback_audio_sample(left: i16, right: i16) {
     // Can't panic
-    CTX.with_borrow_mut(|ctx| {
-        let ctx = ctx.as_mut().unwrap();
+    with_active_ctx(|ctx| {
         let sample_buf = &mut ctx.audio_sample;
         sample_buf.push(left);
         sample_buf.push(right);
@@ -1067,46 +1651,113 @@ extern "C" fn callback_audio_sample(left: i16, right: i16) {
 }
 extern "C" fn callback_audio_sample_batch(data: *const i16, frames: usize) -> usize {
     // Can't panic
-    CTX.with_borrow_mut(|ctx| {
-        let ctx = ctx.as_mut().unwrap();
+    with_active_ctx(|ctx| {
         let sample_buf = &mut ctx.audio_sample;
         let slice = unsafe { std::slice::from_raw_parts(data, frames * 2) };
         sample_buf.extend_from_slice(slice);
         frames
     })
+    .unwrap_or(0)
 }
 
-extern "C" fn callback_input_poll() {}
+// Passes key events to the core's keyboard callback, if it registered one; call with the
+// emulator active
+unsafe fn send_key_events(callback: retro_keyboard_event_t, events: Vec<KeyEvent>) {
+    if let Some(callback) = callback {
+        for event in events {
+            #[allow(clippy::cast_possible_truncation)]
+            let modifiers = event.modifiers.0 as u16;
+            unsafe { callback(event.down, event.key.0, event.character, modifiers) };
+        }
+    }
+}
+
+extern "C" fn callback_input_poll() {
+    // Can't panic
+    with_active_ctx(|ctx| ctx.input_polled = true);
+}
 
 extern "C" fn callback_input_state(port: u32, device: u32, index: u32, id: u32) -> i16 {
     // Can't panic
-    if port > 1 || device != 1 || index != 0 {
-        // Unsupported port/device/index
-        // println!("Unsupported port/device/index {port}/{device}/{index}");
-        return 0;
-    }
-    let bitmask_enabled = (device == RETRO_DEVICE_JOYPAD) && (id == RETRO_DEVICE_ID_JOYPAD_MASK);
-    CTX.with_borrow(|ctx| {
-        let ctx = ctx.as_ref().unwrap();
+    with_active_ctx(|ctx| {
+        let query = InputQuery {
+            port,
+            device,
+            index,
+            id,
+        };
+        if !ctx.input_queries.contains(&query) {
+            ctx.input_queries.push(query);
+        }
         if let Some(cb) = &ctx.button_callback {
             cb(port, device, index, id)
-        } else if bitmask_enabled {
-            let port = port as usize;
-            i16::from(ctx.buttons[port])
+        } else if device & RETRO_DEVICE_MASK == RETRO_DEVICE_KEYBOARD {
+            // There's one keyboard, whichever port asks
+            i16::from(ctx.keyboard.is_pressed(retro_key(id)))
         } else {
-            let port = port as usize;
-            i16::from(ctx.buttons[port].get(id))
+            ctx.inputs
+                .get(port as usize)
+                .map_or(0, |input| input.state(device, index, id))
         }
     })
+    .unwrap_or(0)
+}
+
+// Called by `retrors_log_print` in c-src/logging.c once it has formatted the core's message
+#[unsafe(no_mangle)]
+extern "C" fn retrors_log_message(level: c_int, msg: *const c_char) {
+    // Can't panic
+    if msg.is_null() {
+        return;
+    }
+    let _ = panic::catch_unwind(|| {
+        let level = match level {
+            0 => retro_log_level::RETRO_LOG_DEBUG,
+            1 => retro_log_level::RETRO_LOG_INFO,
+            2 => retro_log_level::RETRO_LOG_WARN,
+            _ => retro_log_level::RETRO_LOG_ERROR,
+        };
+        let msg = unsafe { CStr::from_ptr(msg) }.to_string_lossy();
+        let msg = msg.trim_end_matches(['\n', '\r']);
+        let callback = with_active_ctx(|ctx| ctx.log_callback.clone()).flatten();
+        match callback {
+            Some(cb) => cb(level, msg),
+            None => default_log(level, msg),
+        }
+    });
+}
+
+#[cfg(feature = "log")]
+fn default_log(level: retro_log_level, msg: &str) {
+    let level = match level {
+        retro_log_level::RETRO_LOG_DEBUG => log::Level::Debug,
+        retro_log_level::RETRO_LOG_INFO => log::Level::Info,
+        retro_log_level::RETRO_LOG_WARN => log::Level::Warn,
+        _ => log::Level::Error,
+    };
+    log::log!(target: "retro_rs::core", level, "{msg}");
+}
+
+#[cfg(not(feature = "log"))]
+fn default_log(level: retro_log_level, msg: &str) {
+    let prefix = match level {
+        retro_log_level::RETRO_LOG_DEBUG => "DBG",
+        retro_log_level::RETRO_LOG_INFO => "INF",
+        retro_log_level::RETRO_LOG_WARN => "WRN",
+        _ => "ERR",
+    };
+    eprintln!("[{prefix}]: {msg}");
 }
 
 impl Drop for Emulator {
     fn drop(&mut self) {
-        unsafe {
-            (self.core.core.retro_unload_game)();
-            (self.core.core.retro_deinit)();
+        if let Err(err) = self.flush_sram() {
+            diagnostics::emit(&Diagnostic::SramFlushFailed(err.to_string()));
         }
-        CTX.with_borrow_mut(Option::take);
+        self.call_core(|core| unsafe {
+            (core.retro_unload_game)();
+            (core.retro_deinit)();
+        });
     }
 }
 
@@ -1143,6 +1794,139 @@ mod tests {
         );
         drop(emu);
     }
+    #[test]
+    fn missing_rom_is_an_error() {
+        // The ROM is read before the core is loaded, so no core is needed
+        let result = Emulator::try_create(
+            Path::new("cores/no_such_core_libretro"),
+            Path::new("roms/no_such_rom.nes"),
+        );
+        assert!(matches!(result, Err(RetroRsError::ROMIOError(_))));
+    }
+    fn test_context(log_callback: Option<LogCallback>) -> RefCell<EmulatorContext> {
+        RefCell::new(EmulatorContext::new(
+            CString::default(),
+            CoreOptions::default(),
+            Box::new(crate::SoftwareGfx::default()),
+            log_callback,
+        ))
+    }
+    #[test]
+    fn log_messages_go_to_the_active_emulator() {
+        let logged = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&logged);
+        let ctx = test_context(Some(Box::new(move |level, msg: &str| {
+            sink.borrow_mut().push((level, msg.to_owned()));
+        })));
+        // With no emulator calling into its core, messages go to default_log
+        retrors_log_message(1, c"unclaimed\n".as_ptr());
+        assert!(logged.borrow().is_empty());
+        let active = ActiveContext::enter(&ctx);
+        unsafe {
+            retrors_log_print(
+                retro_log_level::RETRO_LOG_WARN,
+                c"%d of %s\n".as_ptr(),
+                3 as c_int,
+                c"4".as_ptr(),
+            );
+        }
+        drop(active);
+        assert_eq!(
+            *logged.borrow(),
+            [(retro_log_level::RETRO_LOG_WARN, "3 of 4".to_owned())]
+        );
+    }
+    #[test]
+    fn inputs_reach_every_port() {
+        let ctx = test_context(None);
+        ctx.borrow_mut().inputs = vec![
+            Input::default(),
+            Input::default(),
+            Input::from(Buttons::new().a(true)),
+        ];
+        let active = ActiveContext::enter(&ctx);
+        let a = callback_input_state(2, RETRO_DEVICE_JOYPAD, 0, RETRO_DEVICE_ID_JOYPAD_A);
+        let b = callback_input_state(2, RETRO_DEVICE_JOYPAD, 0, RETRO_DEVICE_ID_JOYPAD_B);
+        let missing = callback_input_state(3, RETRO_DEVICE_JOYPAD, 0, RETRO_DEVICE_ID_JOYPAD_A);
+        drop(active);
+        assert_eq!((a, b, missing), (1, 0, 0));
+    }
+    #[test]
+    fn input_reads_are_tracked() {
+        let ctx = test_context(None);
+        let active = ActiveContext::enter(&ctx);
+        callback_input_poll();
+        drop(active);
+        // Polling without reading anything is still lag
+        assert!(ctx.borrow().input_polled);
+        assert!(ctx.borrow().is_lag_frame());
+
+        let active = ActiveContext::enter(&ctx);
+        callback_input_state(0, RETRO_DEVICE_JOYPAD, 0, RETRO_DEVICE_ID_JOYPAD_START);
+        callback_input_state(1, RETRO_DEVICE_ANALOG, 0, 0);
+        callback_input_state(0, RETRO_DEVICE_JOYPAD, 0, RETRO_DEVICE_ID_JOYPAD_START);
+        callback_input_state(1, RETRO_DEVICE_JOYPAD, 0, RETRO_DEVICE_ID_JOYPAD_MASK);
+        drop(active);
+        let ctx = ctx.borrow();
+        assert!(!ctx.is_lag_frame());
+        // Repeated reads are only listed once
+        assert_eq!(ctx.input_queries.len(), 3);
+        assert_eq!(
+            ctx.input_queries[1],
+            InputQuery {
+                port: 1,
+                device: RETRO_DEVICE_ANALOG,
+                index: 0,
+                id: 0
+            }
+        );
+        assert_eq!(ctx.queried_buttons(0), Buttons::new().start(true));
+        assert_eq!(ctx.queried_buttons(1), Buttons::from(-1));
+        assert_eq!(ctx.queried_buttons(2), Buttons::new());
+    }
+    #[test]
+    fn sram_round_trip() {
+        let path = std::env::temp_dir().join(format!("retro-rs-sram-{}.srm", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // A new file leaves the core's SRAM alone and isn't written until it changes
+        let mut sram = [1, 2, 3, 4];
+        let mut file = SramFile::open(path.clone(), &mut sram).unwrap();
+        assert_eq!(sram, [1, 2, 3, 4]);
+        assert!(!file.flush(&sram).unwrap());
+        assert!(!path.exists());
+        sram[0] = 9;
+        assert!(file.flush(&sram).unwrap());
+        assert!(!file.flush(&sram).unwrap());
+        // Reloading restores what was flushed, and a short file only fills the start
+        let mut reloaded = [0; 4];
+        SramFile::open(path.clone(), &mut reloaded).unwrap();
+        assert_eq!(reloaded, [9, 2, 3, 4]);
+        std::fs::write(&path, [7]).unwrap();
+        SramFile::open(path.clone(), &mut reloaded).unwrap();
+        assert_eq!(reloaded, [7, 2, 3, 4]);
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn two_instances_diverge() {
+        let core = Path::new("../../.config/retroarch/cores/fceumm_libretro");
+        let rom = Path::new("roms/mario.nes");
+        let mut walker = Emulator::create(core, rom);
+        let mut idler = Emulator::create(core, rom);
+        for i in 0..400 {
+            walker.run([
+                Buttons::new().start(i > 80 && i < 100).right(i >= 100),
+                Buttons::new(),
+            ]);
+            idler.run([Buttons::new(), Buttons::new()]);
+        }
+        assert_ne!(walker.system_ram_ref(), idler.system_ram_ref());
+        // A third instance alongside them sees neither one's state
+        let mut fresh = Emulator::create(core, rom);
+        for _ in 0..400 {
+            fresh.run([Buttons::new(), Buttons::new()]);
+        }
+        assert_eq!(fresh.system_ram_ref(), idler.system_ram_ref());
+    }
     #[cfg(feature = "use_image")]
     #[test]
     fn it_works() {
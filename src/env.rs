@@ -0,0 +1,603 @@
+use crate::buttons::Buttons;
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use crate::vec_emulator::{ObservationFormat, observation};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// A reinforcement learning environment in the style of OpenAI Gym.
+pub trait Env {
+    type Action;
+    type Observation;
+    /// Starts a new episode and returns its first observation.
+    /// # Errors
+    /// Whatever keeps the environment from restarting
+    fn reset(&mut self) -> Result<Self::Observation, RetroRsError>;
+    /// Takes one action and reports what came of it.
+    /// # Errors
+    /// Whatever keeps the environment from stepping
+    fn step(&mut self, action: Self::Action) -> Result<Step<Self::Observation>, RetroRsError>;
+}
+
+/// The outcome of [`Env::step`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step<O> {
+    pub observation: O,
+    pub reward: f64,
+    pub done: bool,
+    /// The value of every variable after the step
+    pub info: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableKind {
+    Unsigned,
+    Signed,
+    /// Binary-coded decimal, two digits per byte
+    Bcd,
+    /// One decimal digit in the low nibble of each byte
+    LowNibbleBcd,
+}
+
+/// How a variable's bytes are decoded, written in gym-retro's notation such as `|u1` or `>i2`:
+/// a byte order (`<` little, `>` big, `=` native, `|` for single bytes),
+/// a kind (`u` unsigned, `i` signed, `d` BCD or `n` low-nibble BCD) and a size in bytes from 1 to 8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VariableType {
+    pub endian: Endian,
+    pub kind: VariableKind,
+    pub size: usize,
+}
+
+impl FromStr for VariableType {
+    type Err = RetroRsError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || RetroRsError::EnvSpecError(format!("unsupported variable type {s:?}"));
+        let mut chars = s.chars();
+        let endian = match chars.next() {
+            Some('<' | '|') => Endian::Little,
+            Some('>') => Endian::Big,
+            Some('=') if cfg!(target_endian = "big") => Endian::Big,
+            Some('=') => Endian::Little,
+            _ => return Err(bad()),
+        };
+        let kind = match chars.next() {
+            Some('u') => VariableKind::Unsigned,
+            Some('i') => VariableKind::Signed,
+            Some('d') => VariableKind::Bcd,
+            Some('n') => VariableKind::LowNibbleBcd,
+            _ => return Err(bad()),
+        };
+        let size = chars.as_str().parse().map_err(|_| bad())?;
+        if !(1..=8).contains(&size) {
+            return Err(bad());
+        }
+        Ok(VariableType { endian, kind, size })
+    }
+}
+
+impl VariableType {
+    /// Decodes a value from the start of `bytes`, or `None` if there are too few.
+    #[must_use]
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn decode(self, bytes: &[u8]) -> Option<i64> {
+        // Most significant byte first
+        let mut ordered = [0; 8];
+        let ordered = &mut ordered[..self.size];
+        ordered.copy_from_slice(bytes.get(..self.size)?);
+        if self.endian == Endian::Little {
+            ordered.reverse();
+        }
+        let raw = ordered.iter().fold(0, |n: u64, b| (n << 8) | u64::from(*b));
+        let unused = 64 - 8 * self.size as u32;
+        Some(match self.kind {
+            VariableKind::Unsigned => raw as i64,
+            VariableKind::Signed => ((raw << unused) as i64) >> unused,
+            VariableKind::Bcd => ordered.iter().fold(0, |n, b| {
+                n * 100 + i64::from(b >> 4) * 10 + i64::from(b & 0xf)
+            }),
+            VariableKind::LowNibbleBcd => {
+                ordered.iter().fold(0, |n, b| n * 10 + i64::from(b & 0xf))
+            }
+        })
+    }
+}
+
+/// A value in the emulated system's memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Variable {
+    /// An address in the core's memory map, or an offset into system RAM if the core has no memory map
+    pub address: usize,
+    pub ty: VariableType,
+}
+
+impl Variable {
+    /// # Errors
+    /// [`RetroRsError::RAMCopySrcOutOfBoundsError`]: The variable runs past the end of memory
+    /// Others: See [`Emulator::memory_ref`]
+    pub fn read(&self, emu: &Emulator) -> Result<i64, RetroRsError> {
+        let bytes = if emu.memory_regions().is_empty() {
+            emu.system_ram_ref().get(self.address..).unwrap_or_default()
+        } else {
+            emu.memory_ref(self.address)?
+        };
+        self.ty
+            .decode(bytes)
+            .ok_or(RetroRsError::RAMCopySrcOutOfBoundsError)
+    }
+}
+
+/// The comparisons gym-retro scenarios use to test variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessOrEqual,
+    GreaterOrEqual,
+    Zero,
+    Nonzero,
+    Positive,
+    Negative,
+}
+
+impl FromStr for Comparison {
+    type Err = RetroRsError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "equal" => Comparison::Equal,
+            "not-equal" => Comparison::NotEqual,
+            "less-than" => Comparison::LessThan,
+            "greater-than" => Comparison::GreaterThan,
+            "less-or-equal" => Comparison::LessOrEqual,
+            "greater-or-equal" => Comparison::GreaterOrEqual,
+            "zero" => Comparison::Zero,
+            "nonzero" => Comparison::Nonzero,
+            "positive" => Comparison::Positive,
+            "negative" => Comparison::Negative,
+            _ => return Err(RetroRsError::EnvSpecError(format!("unknown op {s:?}"))),
+        })
+    }
+}
+
+impl Comparison {
+    /// Whether `value` compares to `reference` this way; the comparisons against zero ignore `reference`.
+    #[must_use]
+    pub fn test(self, value: i64, reference: i64) -> bool {
+        match self {
+            Comparison::Equal => value == reference,
+            Comparison::NotEqual => value != reference,
+            Comparison::LessThan => value < reference,
+            Comparison::GreaterThan => value > reference,
+            Comparison::LessOrEqual => value <= reference,
+            Comparison::GreaterOrEqual => value >= reference,
+            Comparison::Zero => value == 0,
+            Comparison::Nonzero => value != 0,
+            Comparison::Positive => value > 0,
+            Comparison::Negative => value < 0,
+        }
+    }
+}
+
+/// Which value of a variable a scenario term looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measurement {
+    /// The change since the previous step
+    Delta,
+    /// The current value
+    Absolute,
+}
+
+/// Rewards a variable's measured value: positive values are scaled by `reward`, negative ones by `penalty`.
+/// With an `op`, the value is 1 when the measurement compares to `reference` and 0 otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewardTerm {
+    pub variable: String,
+    pub measurement: Measurement,
+    pub op: Option<Comparison>,
+    pub reference: i64,
+    pub reward: f64,
+    pub penalty: f64,
+}
+
+/// Holds when a variable's measured value compares to `reference` by `op`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoneTerm {
+    pub variable: String,
+    pub measurement: Measurement,
+    pub op: Comparison,
+    pub reference: i64,
+}
+
+/// How an [`EnvSpec`]'s done terms combine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DoneCondition {
+    #[default]
+    Any,
+    All,
+}
+
+/// Named RAM variables and the reward and termination rules defined on them,
+/// in the spirit of gym-retro's `data.json` and `scenario.json`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvSpec {
+    pub variables: BTreeMap<String, Variable>,
+    pub reward: Vec<RewardTerm>,
+    /// The episode is done when any or all of these hold, by `done_condition`;
+    /// it is never done if there are none
+    pub done: Vec<DoneTerm>,
+    pub done_condition: DoneCondition,
+}
+
+impl EnvSpec {
+    /// Reads every variable from `emu`.
+    /// # Errors
+    /// See [`Variable::read`]
+    pub fn read_variables(&self, emu: &Emulator) -> Result<BTreeMap<String, i64>, RetroRsError> {
+        self.variables
+            .iter()
+            .map(|(name, var)| Ok((name.clone(), var.read(emu)?)))
+            .collect()
+    }
+    /// The reward for the variables going from `previous` to `current`.
+    /// Terms whose variables are missing from either give no reward.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn reward(&self, previous: &BTreeMap<String, i64>, current: &BTreeMap<String, i64>) -> f64 {
+        self.reward
+            .iter()
+            .map(|term| {
+                let Some(value) = measure(&term.variable, term.measurement, previous, current)
+                else {
+                    return 0.0;
+                };
+                let value = match term.op {
+                    Some(op) => i64::from(op.test(value, term.reference)),
+                    None => value,
+                } as f64;
+                if value > 0.0 {
+                    value * term.reward
+                } else {
+                    value * term.penalty
+                }
+            })
+            .sum()
+    }
+    /// Whether the episode is over after the variables go from `previous` to `current`.
+    /// Terms whose variables are missing from either don't hold.
+    #[must_use]
+    pub fn done(&self, previous: &BTreeMap<String, i64>, current: &BTreeMap<String, i64>) -> bool {
+        let holds = |term: &DoneTerm| {
+            measure(&term.variable, term.measurement, previous, current)
+                .is_some_and(|value| term.op.test(value, term.reference))
+        };
+        match self.done_condition {
+            DoneCondition::Any => self.done.iter().any(holds),
+            DoneCondition::All => !self.done.is_empty() && self.done.iter().all(holds),
+        }
+    }
+    /// Reads a spec from the text of a gym-retro style `data.json` and `scenario.json`.
+    /// See [`EnvSpec::from_scenario_json`] for the scenario's layout.
+    /// # Errors
+    /// [`RetroRsError::EnvSpecError`]: The JSON is malformed or refers to undefined variables
+    #[cfg(feature = "use_json")]
+    pub fn from_json(data: &str, scenario: &str) -> Result<Self, RetroRsError> {
+        Self::from_scenario_json(parse_variables_json(data)?, scenario)
+    }
+    /// Reads a spec for `variables` from the text of a gym-retro style `scenario.json`.
+    /// Its `reward` and `done` objects each hold `variables` mapping names to terms with an
+    /// optional `measurement` (`"delta"` or `"absolute"`), `op` and `reference`, plus `reward`
+    /// and `penalty` for reward terms. Reward terms default to measuring deltas and done terms
+    /// to absolute values. `done` may set its `condition` to `"any"` (the default) or `"all"`.
+    /// # Errors
+    /// [`RetroRsError::EnvSpecError`]: The JSON is malformed or refers to undefined variables
+    #[cfg(feature = "use_json")]
+    pub fn from_scenario_json(
+        variables: BTreeMap<String, Variable>,
+        scenario: &str,
+    ) -> Result<Self, RetroRsError> {
+        use serde_json::Value;
+        let scenario = parse_json(scenario)?;
+        let mut spec = EnvSpec {
+            variables,
+            ..EnvSpec::default()
+        };
+        let measurement = |term: &Value, default| match term.get("measurement") {
+            None => Ok(default),
+            Some(m) if m == "delta" => Ok(Measurement::Delta),
+            Some(m) if m == "absolute" => Ok(Measurement::Absolute),
+            Some(m) => Err(RetroRsError::EnvSpecError(format!(
+                "unknown measurement {m}"
+            ))),
+        };
+        let reference = |term: &Value| term.get("reference").and_then(Value::as_i64).unwrap_or(0);
+        for (name, term) in json_entries(&scenario, &["reward", "variables"])? {
+            let op = term.get("op").and_then(Value::as_str);
+            spec.reward.push(RewardTerm {
+                variable: spec.check_variable(name)?,
+                measurement: measurement(term, Measurement::Delta)?,
+                op: op.map(str::parse).transpose()?,
+                reference: reference(term),
+                reward: term.get("reward").and_then(Value::as_f64).unwrap_or(0.0),
+                penalty: term.get("penalty").and_then(Value::as_f64).unwrap_or(0.0),
+            });
+        }
+        for (name, term) in json_entries(&scenario, &["done", "variables"])? {
+            let op = json_field(term, name, "op", Value::as_str)?;
+            spec.done.push(DoneTerm {
+                variable: spec.check_variable(name)?,
+                measurement: measurement(term, Measurement::Absolute)?,
+                op: op.parse()?,
+                reference: reference(term),
+            });
+        }
+        spec.done_condition = match scenario.get("done").and_then(|d| d.get("condition")) {
+            None => DoneCondition::Any,
+            Some(c) if c == "any" => DoneCondition::Any,
+            Some(c) if c == "all" => DoneCondition::All,
+            Some(c) => {
+                return Err(RetroRsError::EnvSpecError(format!("unknown condition {c}")));
+            }
+        };
+        Ok(spec)
+    }
+    /// Reads a spec from gym-retro style `data.json` and `scenario.json` files, see [`EnvSpec::from_json`].
+    /// # Errors
+    /// [`RetroRsError::EnvSpecIOError`]: A file couldn't be read
+    /// [`RetroRsError::EnvSpecError`]: The JSON is malformed or refers to undefined variables
+    #[cfg(feature = "use_json")]
+    pub fn load(
+        data_path: &std::path::Path,
+        scenario_path: &std::path::Path,
+    ) -> Result<Self, RetroRsError> {
+        let read = |path| std::fs::read_to_string(path).map_err(RetroRsError::EnvSpecIOError);
+        Self::from_json(&read(data_path)?, &read(scenario_path)?)
+    }
+    #[cfg(feature = "use_json")]
+    fn check_variable(&self, name: &str) -> Result<String, RetroRsError> {
+        if self.variables.contains_key(name) {
+            Ok(name.to_owned())
+        } else {
+            Err(RetroRsError::EnvSpecError(format!(
+                "scenario uses undefined variable {name}"
+            )))
+        }
+    }
+}
+
+fn measure(
+    variable: &str,
+    measurement: Measurement,
+    previous: &BTreeMap<String, i64>,
+    current: &BTreeMap<String, i64>,
+) -> Option<i64> {
+    let cur = *current.get(variable)?;
+    match measurement {
+        Measurement::Absolute => Some(cur),
+        Measurement::Delta => Some(cur.saturating_sub(*previous.get(variable)?)),
+    }
+}
+
+#[cfg(feature = "use_json")]
+pub(crate) fn parse_json(text: &str) -> Result<serde_json::Value, RetroRsError> {
+    serde_json::from_str(text).map_err(|e| RetroRsError::EnvSpecError(e.to_string()))
+}
+
+// The variables in the `info` object of a gym-retro style `data.json`
+#[cfg(feature = "use_json")]
+pub(crate) fn parse_variables_json(data: &str) -> Result<BTreeMap<String, Variable>, RetroRsError> {
+    use serde_json::Value;
+    let data = parse_json(data)?;
+    json_entries(&data, &["info"])?
+        .map(|(name, var)| {
+            let address = json_field(var, name, "address", Value::as_u64)?;
+            let ty = json_field(var, name, "type", Value::as_str)?;
+            let var = Variable {
+                address: usize::try_from(address)?,
+                ty: ty.parse()?,
+            };
+            Ok((name.clone(), var))
+        })
+        .collect()
+}
+
+// The entries of the object at `path`, or none if some object along the path is missing
+#[cfg(feature = "use_json")]
+fn json_entries<'a>(
+    value: &'a serde_json::Value,
+    path: &[&str],
+) -> Result<impl Iterator<Item = (&'a String, &'a serde_json::Value)>, RetroRsError> {
+    let mut value = Some(value);
+    for key in path {
+        value = value.and_then(|v| v.get(key));
+    }
+    match value {
+        None => Ok(None.into_iter().flatten()),
+        Some(serde_json::Value::Object(map)) => Ok(Some(map.iter()).into_iter().flatten()),
+        Some(_) => Err(RetroRsError::EnvSpecError(format!(
+            "{} is not an object",
+            path.join(".")
+        ))),
+    }
+}
+
+#[cfg(feature = "use_json")]
+fn json_field<'a, T>(
+    value: &'a serde_json::Value,
+    name: &str,
+    key: &str,
+    get: impl FnOnce(&'a serde_json::Value) -> Option<T>,
+) -> Result<T, RetroRsError> {
+    value
+        .get(key)
+        .and_then(get)
+        .ok_or_else(|| RetroRsError::EnvSpecError(format!("{name} has no valid {key}")))
+}
+
+/// An [`Env`] playing a game on an [`Emulator`], rewarded and ended by an [`EnvSpec`].
+///
+/// Actions are the buttons held for one frame and observations are framebuffer copies.
+pub struct RetroEnv {
+    emu: Emulator,
+    spec: EnvSpec,
+    start_state: Option<Vec<u8>>,
+    format: ObservationFormat,
+    values: BTreeMap<String, i64>,
+}
+
+impl RetroEnv {
+    /// An environment which resets by resetting the core and observes in RGB888.
+    #[must_use]
+    pub fn new(emu: Emulator, spec: EnvSpec) -> Self {
+        RetroEnv {
+            emu,
+            spec,
+            start_state: None,
+            format: ObservationFormat::Rgb888,
+            values: BTreeMap::new(),
+        }
+    }
+    /// Makes [`Env::reset`] load `state`, as produced by [`Emulator::save`], instead of resetting the core.
+    #[must_use]
+    pub fn with_start_state(mut self, state: Vec<u8>) -> Self {
+        self.start_state = Some(state);
+        self
+    }
+    #[must_use]
+    pub fn with_observation_format(mut self, format: ObservationFormat) -> Self {
+        self.format = format;
+        self
+    }
+    #[must_use]
+    pub fn emulator(&self) -> &Emulator {
+        &self.emu
+    }
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emu
+    }
+    #[must_use]
+    pub fn spec(&self) -> &EnvSpec {
+        &self.spec
+    }
+    #[must_use]
+    pub fn into_emulator(self) -> Emulator {
+        self.emu
+    }
+    fn observe(&self) -> Result<Vec<u8>, RetroRsError> {
+        let (w, h) = self.emu.framebuffer_size();
+        let mut frame = vec![0; w * h * 3];
+        self.emu.copy_framebuffer_rgb888(&mut frame)?;
+        Ok(observation(frame, None, self.format))
+    }
+}
+
+impl Env for RetroEnv {
+    type Action = [Buttons; 2];
+    type Observation = Vec<u8>;
+    /// Loads the start state or resets the core. If that leaves no framebuffer to observe,
+    /// one frame is run with no buttons held.
+    /// # Errors
+    /// [`RetroRsError::StateLoadError`]: The core couldn't load the start state
+    /// Others: See [`Variable::read`]
+    fn reset(&mut self) -> Result<Vec<u8>, RetroRsError> {
+        match &self.start_state {
+            Some(state) if !self.emu.load(state) => return Err(RetroRsError::StateLoadError),
+            Some(_) => {}
+            None => self.emu.reset(),
+        }
+        if self.emu.peek_framebuffer(|_| ()).is_err() {
+            self.emu.run([Buttons::new(); 2]);
+        }
+        self.values = self.spec.read_variables(&self.emu)?;
+        self.observe()
+    }
+    /// Runs one frame with `action` held.
+    /// # Errors
+    /// See [`Variable::read`]
+    fn step(&mut self, action: [Buttons; 2]) -> Result<Step<Vec<u8>>, RetroRsError> {
+        self.emu.run(action);
+        let values = self.spec.read_variables(&self.emu)?;
+        let reward = self.spec.reward(&self.values, &values);
+        let done = self.spec.done(&self.values, &values);
+        self.values = values;
+        Ok(Step {
+            observation: self.observe()?,
+            reward,
+            done,
+            info: self.values.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variables_reward_and_done() {
+        let ty: VariableType = ">i2".parse().unwrap();
+        assert_eq!(ty.decode(&[0xff, 0xfe, 0x00]), Some(-2));
+        let ty: VariableType = "<u3".parse().unwrap();
+        assert_eq!(ty.decode(&[0x01, 0x02, 0x03]), Some(0x03_0201));
+        assert_eq!(ty.decode(&[0x01, 0x02]), None);
+        assert!("|f4".parse::<VariableType>().is_err());
+        assert!("<u9".parse::<VariableType>().is_err());
+        let ty: VariableType = "<d2".parse().unwrap();
+        assert_eq!(ty.decode(&[0x34, 0x12]), Some(1234));
+        let ty: VariableType = ">n3".parse().unwrap();
+        assert_eq!(ty.decode(&[0x01, 0x02, 0x03]), Some(123));
+
+        let spec = EnvSpec {
+            variables: BTreeMap::new(),
+            reward: vec![RewardTerm {
+                variable: "score".to_owned(),
+                measurement: Measurement::Delta,
+                op: None,
+                reference: 0,
+                reward: 0.5,
+                penalty: 2.0,
+            }],
+            done: vec![DoneTerm {
+                variable: "lives".to_owned(),
+                measurement: Measurement::Absolute,
+                op: Comparison::Zero,
+                reference: 0,
+            }],
+            done_condition: DoneCondition::Any,
+        };
+        let values = |score, lives| {
+            BTreeMap::from([("score".to_owned(), score), ("lives".to_owned(), lives)])
+        };
+        assert!((spec.reward(&values(10, 3), &values(30, 3)) - 10.0).abs() < f64::EPSILON);
+        assert!((spec.reward(&values(10, 3), &values(9, 3)) + 2.0).abs() < f64::EPSILON);
+        assert!(!spec.done(&values(0, 1), &values(0, 1)));
+        assert!(spec.done(&values(0, 1), &values(0, 0)));
+    }
+
+    #[cfg(feature = "use_json")]
+    #[test]
+    fn spec_from_json() {
+        let data = r#"{"info": {"lives": {"address": 1882, "type": "|u1"},
+                                "score": {"address": 2012, "type": ">u2"}}}"#;
+        let scenario = r#"{"reward": {"variables": {"score": {"reward": 1.0}}},
+                           "done": {"condition": "all", "variables": {
+                               "lives": {"op": "equal", "reference": 255},
+                               "score": {"op": "negative", "measurement": "delta"}}}}"#;
+        let spec = EnvSpec::from_json(data, scenario).unwrap();
+        assert_eq!(spec.variables["score"].address, 2012);
+        assert_eq!(spec.variables["score"].ty.endian, Endian::Big);
+        assert_eq!(spec.reward[0].penalty, 0.0);
+        assert_eq!(spec.done[0].op, Comparison::Equal);
+        assert_eq!(spec.done[1].measurement, Measurement::Delta);
+        assert_eq!(spec.done_condition, DoneCondition::All);
+        let scenario = r#"{"done": {"variables": {"time": {"op": "zero"}}}}"#;
+        assert!(EnvSpec::from_json(data, scenario).is_err());
+    }
+}
@@ -10,6 +10,39 @@ pub enum RetroRsError {
     RAMMapOutOfRangeError,
     RAMCopyCrossedRegionError,
     RAMCopyNotMappedIntoMemoryRegionError,
+    UnsupportedPlatformError,
+    InvalidPathError(std::path::PathBuf),
+    ROMIOError(std::io::Error),
+    CoreLoadError(libloading::Error),
+    CoreSymbolMissingError(String),
+    CoreCopyError(std::io::Error),
+    ContentRejectedError,
+    CoreOptionUnknownError(String),
+    CoreOptionInvalidValueError(String, String),
+    CoreOptionsFileError(std::io::Error),
+    GLError(&'static str, u32),
+    MovieStartError,
+    MovieMismatchError(String),
+    MovieIOError(std::io::Error),
+    MovieFormatError(String),
+    StateSaveError,
+    StateLoadError,
+    RewindEmptyError,
+    SavestateMismatchError(String),
+    SavestateIOError(std::io::Error),
+    SavestateFormatError(String),
+    SramIOError(std::io::Error),
+    RemoteIOError(std::io::Error),
+    RemoteCoreError(String),
+    RemoteWorkerExitedError(std::process::ExitStatus),
+    EmulatorThreadSpawnError(std::io::Error),
+    EmulatorThreadExitedError,
+    ObservationSizeError(usize, usize),
+    EnvSpecError(String),
+    EnvSpecIOError(std::io::Error),
+    RecorderIOError(std::io::Error),
+    AnimationIOError(std::io::Error),
+    AnimationEncodeError(String),
 }
 impl From<std::num::TryFromIntError> for RetroRsError {
     fn from(err: std::num::TryFromIntError) -> RetroRsError {
@@ -39,6 +72,84 @@ impl Display for RetroRsError {
             RetroRsError::RAMCopyNotMappedIntoMemoryRegionError => {
                 write!(f, "RAM copy doesn't start within a memory region")
             }
+            RetroRsError::UnsupportedPlatformError => write!(f, "Unsupported platform"),
+            RetroRsError::InvalidPathError(ref path) => {
+                write!(
+                    f,
+                    "Path can't be passed to a libretro core: {}",
+                    path.display()
+                )
+            }
+            RetroRsError::ROMIOError(ref err) => write!(f, "Couldn't read ROM: {err}"),
+            RetroRsError::CoreLoadError(ref err) => write!(f, "Couldn't load core: {err}"),
+            RetroRsError::CoreSymbolMissingError(ref name) => {
+                write!(f, "Core is missing libretro function {name}")
+            }
+            RetroRsError::CoreCopyError(ref err) => {
+                write!(f, "Couldn't copy core for another emulator instance: {err}")
+            }
+            RetroRsError::ContentRejectedError => write!(f, "Core failed to load the given ROM"),
+            RetroRsError::CoreOptionUnknownError(ref key) => {
+                write!(f, "Core did not declare an option {key}")
+            }
+            RetroRsError::CoreOptionInvalidValueError(ref key, ref value) => {
+                write!(f, "{value:?} is not a valid value for core option {key}")
+            }
+            RetroRsError::CoreOptionsFileError(ref err) => {
+                write!(f, "Couldn't access core options file: {err}")
+            }
+            RetroRsError::GLError(call, code) => {
+                write!(f, "OpenGL error {code:#x} after {call}")
+            }
+            RetroRsError::MovieStartError => {
+                write!(f, "Emulator is not in a state where the movie can start")
+            }
+            RetroRsError::MovieMismatchError(ref why) => write!(f, "Wrong movie: {why}"),
+            RetroRsError::MovieIOError(ref err) => write!(f, "Couldn't access movie: {err}"),
+            RetroRsError::MovieFormatError(ref why) => write!(f, "Malformed movie: {why}"),
+            RetroRsError::StateSaveError => write!(f, "Core failed to save its state"),
+            RetroRsError::StateLoadError => write!(f, "Core failed to load the given state"),
+            RetroRsError::RewindEmptyError => {
+                write!(f, "No states have been captured to rewind to")
+            }
+            RetroRsError::SavestateMismatchError(ref why) => write!(f, "Wrong savestate: {why}"),
+            RetroRsError::SavestateIOError(ref err) => {
+                write!(f, "Couldn't access savestate: {err}")
+            }
+            RetroRsError::SavestateFormatError(ref why) => {
+                write!(f, "Malformed savestate: {why}")
+            }
+            RetroRsError::SramIOError(ref err) => write!(f, "Couldn't access SRAM file: {err}"),
+            RetroRsError::RemoteIOError(ref err) => {
+                write!(f, "Couldn't communicate with emulator worker: {err}")
+            }
+            RetroRsError::RemoteCoreError(ref why) => write!(f, "Emulator worker failed: {why}"),
+            RetroRsError::RemoteWorkerExitedError(status) => {
+                write!(f, "Emulator worker exited: {status}")
+            }
+            RetroRsError::EmulatorThreadSpawnError(ref err) => {
+                write!(f, "Couldn't start emulator thread: {err}")
+            }
+            RetroRsError::EmulatorThreadExitedError => write!(f, "Emulator thread panicked"),
+            RetroRsError::ObservationSizeError(expected, actual) => {
+                write!(
+                    f,
+                    "Observations take {expected} bytes but the buffer holds {actual}"
+                )
+            }
+            RetroRsError::EnvSpecError(ref why) => write!(f, "Malformed environment spec: {why}"),
+            RetroRsError::EnvSpecIOError(ref err) => {
+                write!(f, "Couldn't read environment spec: {err}")
+            }
+            RetroRsError::RecorderIOError(ref err) => {
+                write!(f, "Couldn't write recording: {err}")
+            }
+            RetroRsError::AnimationIOError(ref err) => {
+                write!(f, "Couldn't write animation: {err}")
+            }
+            RetroRsError::AnimationEncodeError(ref why) => {
+                write!(f, "Couldn't encode animation: {why}")
+            }
         }
     }
 }
@@ -0,0 +1,50 @@
+//! The encoding shared by movie files, savestate files and worker messages: integers are
+//! little-endian, and strings are a `u32` byte length followed by UTF-8.
+use crate::error::RetroRsError;
+
+pub(crate) fn put_string(buf: &mut Vec<u8>, s: &str) -> Result<(), RetroRsError> {
+    buf.extend_from_slice(&u32::try_from(s.len())?.to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+// Takes fields off the front of some bytes, reporting malformed data with the format's own error
+pub(crate) struct ByteReader<'a> {
+    rest: &'a [u8],
+    error: fn(String) -> RetroRsError,
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8], error: fn(String) -> RetroRsError) -> Self {
+        ByteReader { rest: bytes, error }
+    }
+    pub(crate) fn error(&self, why: impl Into<String>) -> RetroRsError {
+        (self.error)(why.into())
+    }
+    // Whatever hasn't been taken yet
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        self.rest
+    }
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], RetroRsError> {
+        if self.rest.len() < n {
+            return Err(self.error("unexpected end of data"));
+        }
+        let (head, tail) = self.rest.split_at(n);
+        self.rest = tail;
+        Ok(head)
+    }
+    pub(crate) fn take_array<const N: usize>(&mut self) -> Result<[u8; N], RetroRsError> {
+        let mut arr = [0; N];
+        arr.copy_from_slice(self.take(N)?);
+        Ok(arr)
+    }
+    // A u64 length or offset
+    pub(crate) fn take_usize(&mut self) -> Result<usize, RetroRsError> {
+        Ok(usize::try_from(u64::from_le_bytes(self.take_array()?))?)
+    }
+    pub(crate) fn take_string(&mut self) -> Result<String, RetroRsError> {
+        let len = u32::from_le_bytes(self.take_array()?) as usize;
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| self.error("string is not UTF-8"))
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::RetroRsError;
 use rust_libretro_sys::{retro_hw_context_type, retro_hw_render_callback, retro_system_av_info};
 
 pub trait Gfx {
@@ -11,7 +12,12 @@ pub trait Gfx {
     fn destroy_context(&mut self) {}
     fn bind(&mut self) {}
     fn unbind(&mut self) {}
-    fn sync_framebuffer(&self, _fb: &mut [u8]) {}
+    /// Copies the rendered frame into `fb` for hardware-rendering backends.
+    /// # Errors
+    /// Backend-specific, e.g. [`RetroRsError::GLError`]
+    fn sync_framebuffer(&self, _fb: &mut [u8]) -> Result<(), RetroRsError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default)]
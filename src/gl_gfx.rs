@@ -1,15 +1,27 @@
+use crate::diagnostics::{self, Diagnostic};
+use crate::error::RetroRsError;
 use crate::gfx::Gfx;
 use rust_libretro_sys::{retro_hw_context_type, retro_hw_render_callback, retro_system_av_info};
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
 use surfman::{Connection, ContextAttributeFlags, ContextAttributes, GLVersion};
 use surfman::{SurfaceAccess, SurfaceType};
 
-static GFX: std::sync::Mutex<Option<GlGfxData>> = const { std::sync::Mutex::new(None) };
+type SharedGlGfxData = Rc<RefCell<Option<GlGfxData>>>;
+
+thread_local! {
+    // The backend of the emulator whose core is rendering on this thread, for the core's GL callbacks
+    static CURRENT: RefCell<Weak<RefCell<Option<GlGfxData>>>> = const { RefCell::new(Weak::new()) };
+}
+
+fn with_current<R>(f: impl FnOnce(&GlGfxData) -> R) -> Option<R> {
+    let data = CURRENT.with_borrow(Weak::upgrade)?;
+    let data = data.try_borrow().ok()?;
+    data.as_ref().map(f)
+}
 
 pub fn get_proc_address_r(proc: &str) -> *const std::ffi::c_void {
-    // dbg!(std::thread::current().id());
-    let ctx = GFX.lock().unwrap();
-    let ctx = ctx.as_ref().unwrap();
-    ctx.device.get_proc_address(&ctx.context, proc)
+    with_current(|ctx| ctx.device.get_proc_address(&ctx.context, proc)).unwrap_or(std::ptr::null())
 }
 pub unsafe extern "C" fn get_proc_address(
     proc: *const std::ffi::c_char,
@@ -21,9 +33,7 @@ pub unsafe extern "C" fn get_proc_address(
     }
 }
 pub unsafe extern "C" fn get_current_framebuffer() -> usize {
-    // dbg!(std::thread::current().id());
-    let ctx = GFX.lock().unwrap();
-    ctx.as_ref().map_or(0, |ctx| ctx.get_fbo())
+    with_current(GlGfxData::get_fbo).unwrap_or(0)
 }
 
 struct GlGfxData {
@@ -41,15 +51,15 @@ impl GlGfxData {
     fn create(w: i32, h: i32, version_major: u8, version_minor: u8) -> Option<Self> {
         // dbg!(std::thread::current().id());
         let Ok(connection) = Connection::new() else {
-            println!("Could not create connection");
+            diagnostics::emit(&Diagnostic::GlContextFailed("create connection"));
             return None;
         };
         let Ok(adapter) = connection.create_adapter() else {
-            println!("Could not obtain adapter");
+            diagnostics::emit(&Diagnostic::GlContextFailed("obtain adapter"));
             return None;
         };
         let Ok(mut device) = connection.create_device(&adapter) else {
-            println!("Could not create device");
+            diagnostics::emit(&Diagnostic::GlContextFailed("create device"));
             return None;
         };
         let attributes = ContextAttributes {
@@ -57,15 +67,15 @@ impl GlGfxData {
             flags: ContextAttributeFlags::DEPTH | ContextAttributeFlags::STENCIL,
         };
         let Ok(context_descriptor) = device.create_context_descriptor(&attributes) else {
-            println!("Failed to create context descriptor");
+            diagnostics::emit(&Diagnostic::GlContextFailed("create context descriptor"));
             return None;
         };
         let Ok(context) = device.create_context(&context_descriptor, None) else {
-            println!("Failed to create context");
+            diagnostics::emit(&Diagnostic::GlContextFailed("create context"));
             return None;
         };
         let Ok(()) = device.make_context_current(&context) else {
-            println!("Failed to make context current");
+            diagnostics::emit(&Diagnostic::GlContextFailed("make context current"));
             return None;
         };
         gl::load_with(|s| device.get_proc_address(&context, s));
@@ -143,23 +153,29 @@ impl GlGfxData {
             let _ = self.surface.insert(surf);
         }
     }
-    fn sync_framebuffer(&mut self, fb: &mut [u8]) {
-        println!("Fb size {}", fb.len());
+    fn sync_framebuffer(&mut self, fb: &mut [u8]) -> Result<(), RetroRsError> {
         unsafe {
             self.bind();
             let fbo = self.get_fbo();
-            println!("Existing err {:x}, fbo {fbo}", gl::GetError());
+            // Errors left over from the core's own rendering aren't failures of the read
+            let code = gl::GetError();
+            if code != gl::NO_ERROR {
+                diagnostics::emit(&Diagnostic::GlError {
+                    call: "core rendering",
+                    code,
+                });
+            }
             // gl::Flush();
             gl::BindFramebuffer(gl::FRAMEBUFFER, fbo as u32);
-            println!("glBF err {:x}", gl::GetError());
+            check_gl_error("glBindFramebuffer")?;
             gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
-            println!("glPSi err {:x}", gl::GetError());
+            check_gl_error("glPixelStorei")?;
             gl::PixelStorei(gl::PACK_ROW_LENGTH, 0);
-            println!("glPSi err {:x}", gl::GetError());
+            check_gl_error("glPixelStorei")?;
             gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
-            println!("glBB err {:x}", gl::GetError());
+            check_gl_error("glBindBuffer")?;
             gl::ReadBuffer(gl::BACK);
-            println!("glRB err {:x}", gl::GetError());
+            check_gl_error("glReadBuffer")?;
             gl::ReadPixels(
                 0,
                 0,
@@ -169,6 +185,7 @@ impl GlGfxData {
                 gl::UNSIGNED_BYTE,
                 fb.as_mut_ptr().cast(),
             );
+            check_gl_error("glReadPixels")?;
             for pix in fb.chunks_exact_mut(4) {
                 assert_eq!(pix.len(), 4);
                 pix.swap(0, 3);
@@ -189,51 +206,71 @@ impl GlGfxData {
                     .unwrap();
                 l0.swap_with_slice(l_n);
             }
-            println!("glReadPixels err {:x}", gl::GetError());
             // self.unbind();
         }
+        Ok(())
     }
 }
 
-unsafe impl Send for GlGfxData {}
+fn check_gl_error(call: &'static str) -> Result<(), RetroRsError> {
+    let code = unsafe { gl::GetError() };
+    if code == gl::NO_ERROR {
+        Ok(())
+    } else {
+        diagnostics::emit(&Diagnostic::GlError { call, code });
+        Err(RetroRsError::GLError(call, code))
+    }
+}
 
 impl Drop for GlGfxData {
     fn drop(&mut self) {
         self.unbind();
         self.destroy_surface();
         if let Err(e) = self.device.destroy_context(&mut self.context) {
-            println!("Error destroying context {e:?}");
+            diagnostics::emit(&Diagnostic::GlContextDestroyFailed(format!("{e:?}")));
         }
     }
 }
+/// Renders hardware cores with an offscreen OpenGL context owned by this backend,
+/// so each [`crate::Emulator`] needs its own `GlGfx`.
 #[derive(Default)]
 pub struct GlGfx {
+    data: SharedGlGfxData,
     context_reset: rust_libretro_sys::retro_hw_context_reset_t,
     context_destroy: rust_libretro_sys::retro_hw_context_reset_t,
 }
 
+impl GlGfx {
+    // Points the core's GL callbacks at this backend
+    fn make_current(&self) {
+        CURRENT.set(Rc::downgrade(&self.data));
+    }
+}
+
 impl Gfx for GlGfx {
     fn preferred_api(&self) -> retro_hw_context_type {
         retro_hw_context_type::RETRO_HW_CONTEXT_OPENGL
     }
     fn video_refresh(&mut self, w: u32, h: u32, _p: usize) {
-        let Ok(w) = i32::try_from(w) else {
-            println!("Bad width {w}");
+        let (Ok(w), Ok(h)) = (i32::try_from(w), i32::try_from(h)) else {
+            diagnostics::emit(&Diagnostic::InvalidFramebufferSize {
+                width: w,
+                height: h,
+            });
             return;
         };
-        let Ok(h) = i32::try_from(h) else {
-            println!("Bad height {h}");
+        self.make_current();
+        let mut data = self.data.borrow_mut();
+        let Some(ctx) = data.as_mut() else {
             return;
         };
-        let mut lock = GFX.lock().unwrap();
-        let ctx = lock.as_mut().unwrap();
         let changed = if ctx.w != w || ctx.h != h {
             ctx.set_dimensions(w, h);
             true
         } else {
             false
         };
-        drop(lock);
+        drop(data);
         if let Some(cb) = self.context_reset.as_ref()
             && changed
         {
@@ -253,22 +290,20 @@ impl Gfx for GlGfx {
         if ctype == retro_hw_context_type::RETRO_HW_CONTEXT_VULKAN
             || ctype == retro_hw_context_type::RETRO_HW_CONTEXT_DIRECT3D
         {
-            println!("Tried to load unsupported gfx context type {ctype:?}");
+            diagnostics::emit(&Diagnostic::UnsupportedHardwareContext(ctype));
             return false;
         }
         let w = i32::try_from(av.geometry.max_width).unwrap_or(-1);
         let h = i32::try_from(av.geometry.max_height).unwrap_or(-1);
-        let mut lock = GFX.lock().unwrap();
-        let ctx = GlGfxData::create(
-            w,
-            h,
-            cb.version_major.try_into().unwrap(),
-            cb.version_minor.try_into().unwrap(),
-        );
+        let major: u8 = cb.version_major.try_into().unwrap();
+        let minor: u8 = cb.version_minor.try_into().unwrap();
+        let ctx = GlGfxData::create(w, h, major, minor);
         let success = ctx.is_some();
-        let _ = std::mem::replace(&mut *lock, ctx);
-        drop(lock);
-        println!("Created ctx");
+        *self.data.borrow_mut() = ctx;
+        self.make_current();
+        if success {
+            diagnostics::emit(&Diagnostic::GlContextCreated { major, minor });
+        }
         cb.bottom_left_origin = false;
         cb.cache_context = true;
         cb.get_proc_address = Some(get_proc_address);
@@ -278,32 +313,30 @@ impl Gfx for GlGfx {
         success
     }
     fn destroy_context(&mut self) {
-        let mut lock = GFX.lock().unwrap();
-        let ctx = lock.as_mut().unwrap();
+        let mut data = self.data.borrow_mut();
+        let Some(ctx) = data.as_mut() else {
+            return;
+        };
         ctx.destroy_surface();
         if let Err(e) = ctx.device.destroy_context(&mut ctx.context) {
-            println!("Error destroying context {e:?}");
+            diagnostics::emit(&Diagnostic::GlContextDestroyFailed(format!("{e:?}")));
         }
     }
     fn bind(&mut self) {
-        let mut lock = GFX.lock().unwrap();
-        let ctx = lock.as_mut().unwrap();
-        ctx.bind();
+        self.make_current();
+        if let Some(ctx) = self.data.borrow_mut().as_mut() {
+            ctx.bind();
+        }
     }
     fn unbind(&mut self) {
-        let mut lock = GFX.lock().unwrap();
-        let ctx = lock.as_mut().unwrap();
-        ctx.unbind();
-    }
-    fn sync_framebuffer(&self, fb: &mut [u8]) {
-        let mut lock = GFX.lock().unwrap();
-        let ctx = lock.as_mut().unwrap();
-        ctx.sync_framebuffer(fb);
+        if let Some(ctx) = self.data.borrow_mut().as_mut() {
+            ctx.unbind();
+        }
     }
-}
-impl Drop for GlGfx {
-    fn drop(&mut self) {
-        let mut lock = GFX.lock().unwrap();
-        let _ctx = lock.take();
+    fn sync_framebuffer(&self, fb: &mut [u8]) -> Result<(), RetroRsError> {
+        match self.data.borrow_mut().as_mut() {
+            Some(ctx) => ctx.sync_framebuffer(fb),
+            None => Ok(()),
+        }
     }
 }
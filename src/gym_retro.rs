@@ -0,0 +1,218 @@
+//! Game integrations from gym-retro's dataset.
+//!
+//! An integration is a directory such as `SuperMarioBros-Nes` holding:
+//! - `data.json`: named RAM variables, read into [`Variable`]s
+//! - `scenario.json` and other scenario files: rewards and done conditions, read into [`EnvSpec`]s
+//! - `metadata.json`: optionally, the `default_state` to start episodes from
+//! - `*.state`: gzip-compressed savestates for the integration's core, which need the `use_deflate` feature
+//! - `rom.sha`: optionally, the SHA-1 hashes of the ROMs the integration was made for
+//!
+//! Lua scripted rewards aren't supported.
+use crate::emulator::Emulator;
+use crate::env::{EnvSpec, RetroEnv, Variable, parse_json, parse_variables_json};
+use crate::error::RetroRsError;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const DATA_FILE: &str = "data.json";
+const METADATA_FILE: &str = "metadata.json";
+const ROM_SHA_FILE: &str = "rom.sha";
+/// The scenario gym-retro uses when none is named.
+pub const DEFAULT_SCENARIO: &str = "scenario";
+
+/// A game integration directory, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct GameIntegration {
+    dir: PathBuf,
+    variables: BTreeMap<String, Variable>,
+    default_state: Option<String>,
+    rom_sha1: Vec<String>,
+}
+
+impl GameIntegration {
+    /// Reads an integration's variables and metadata; scenarios and states are read on demand.
+    /// # Errors
+    /// [`RetroRsError::EnvSpecIOError`]: `data.json` is missing or a file couldn't be read
+    /// [`RetroRsError::EnvSpecError`]: A file is malformed
+    pub fn open(dir: &Path) -> Result<Self, RetroRsError> {
+        let variables = parse_variables_json(&read_text(&dir.join(DATA_FILE))?)?;
+        let default_state = match read_optional_text(&dir.join(METADATA_FILE))? {
+            Some(text) => parse_json(&text)?
+                .get("default_state")
+                .and_then(|state| state.as_str())
+                .map(str::to_owned),
+            None => None,
+        };
+        let rom_sha1 = read_optional_text(&dir.join(ROM_SHA_FILE))?
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_ascii_lowercase)
+            .collect();
+        Ok(GameIntegration {
+            dir: dir.to_path_buf(),
+            variables,
+            default_state,
+            rom_sha1,
+        })
+    }
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+    #[must_use]
+    pub fn variables(&self) -> &BTreeMap<String, Variable> {
+        &self.variables
+    }
+    /// The state named by `metadata.json`, if any.
+    #[must_use]
+    pub fn default_state(&self) -> Option<&str> {
+        self.default_state.as_deref()
+    }
+    /// The lowercase hex SHA-1 hashes from `rom.sha`, empty if it's missing.
+    #[must_use]
+    pub fn rom_sha1(&self) -> &[String] {
+        &self.rom_sha1
+    }
+    /// Whether `emu`'s ROM is one this integration was made for, or the integration doesn't say.
+    #[must_use]
+    pub fn matches_rom(&self, emu: &Emulator) -> bool {
+        self.rom_sha1.is_empty() || self.rom_sha1.iter().any(|sha| sha == emu.rom_sha1())
+    }
+    /// The names of the integration's scenario files, without their `.json` extension.
+    /// # Errors
+    /// [`RetroRsError::EnvSpecIOError`]: The directory couldn't be listed
+    pub fn scenarios(&self) -> Result<Vec<String>, RetroRsError> {
+        let mut names = self.files_with_extension("json")?;
+        names.retain(|name| {
+            ![DATA_FILE, METADATA_FILE]
+                .iter()
+                .any(|file| file.strip_suffix(".json") == Some(name))
+        });
+        Ok(names)
+    }
+    /// Reads the scenario file `{name}.json` against the integration's variables.
+    /// # Errors
+    /// [`RetroRsError::EnvSpecIOError`]: The file couldn't be read
+    /// Others: See [`EnvSpec::from_scenario_json`]
+    pub fn scenario(&self, name: &str) -> Result<EnvSpec, RetroRsError> {
+        let text = read_text(&self.dir.join(format!("{name}.json")))?;
+        EnvSpec::from_scenario_json(self.variables.clone(), &text)
+    }
+    /// The names of the integration's states, without their `.state` extension.
+    /// # Errors
+    /// [`RetroRsError::EnvSpecIOError`]: The directory couldn't be listed
+    pub fn states(&self) -> Result<Vec<String>, RetroRsError> {
+        self.files_with_extension("state")
+    }
+    /// Reads and decompresses the state file `{name}.state`, ready for [`Emulator::load`].
+    /// # Errors
+    /// [`RetroRsError::EnvSpecIOError`]: The file couldn't be read
+    /// [`RetroRsError::EnvSpecError`]: The file is gzip-compressed but `use_deflate` is disabled, or is corrupt
+    pub fn state(&self, name: &str) -> Result<Vec<u8>, RetroRsError> {
+        let path = self.dir.join(format!("{name}.state"));
+        let bytes = std::fs::read(path).map_err(RetroRsError::EnvSpecIOError)?;
+        gunzip(bytes)
+    }
+    /// An environment for `emu` rewarded by `scenario`, whose episodes start from the named state,
+    /// or from the default state if `state` is `None`. Without either the core is reset instead.
+    /// # Errors
+    /// [`RetroRsError::EnvSpecError`]: `emu`'s ROM isn't one this integration was made for
+    /// Others: See [`GameIntegration::scenario`] and [`GameIntegration::state`]
+    pub fn env(
+        &self,
+        emu: Emulator,
+        scenario: &str,
+        state: Option<&str>,
+    ) -> Result<RetroEnv, RetroRsError> {
+        if !self.matches_rom(&emu) {
+            return Err(RetroRsError::EnvSpecError(format!(
+                "ROM {} is not one of {}'s",
+                emu.rom_sha1(),
+                self.dir.display()
+            )));
+        }
+        let spec = self.scenario(scenario)?;
+        let env = RetroEnv::new(emu, spec);
+        match state.or(self.default_state()) {
+            Some(state) => Ok(env.with_start_state(self.state(state)?)),
+            None => Ok(env),
+        }
+    }
+
+    fn files_with_extension(&self, extension: &str) -> Result<Vec<String>, RetroRsError> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.dir).map_err(RetroRsError::EnvSpecIOError)? {
+            let path = entry.map_err(RetroRsError::EnvSpecIOError)?.path();
+            if path.extension().is_some_and(|ext| ext == extension)
+                && let Some(stem) = path.file_stem().and_then(|stem| stem.to_str())
+            {
+                names.push(stem.to_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+}
+
+fn read_text(path: &Path) -> Result<String, RetroRsError> {
+    std::fs::read_to_string(path).map_err(RetroRsError::EnvSpecIOError)
+}
+
+fn read_optional_text(path: &Path) -> Result<Option<String>, RetroRsError> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(RetroRsError::EnvSpecIOError(err)),
+    }
+}
+
+// States are usually gzipped, but uncompressed ones are passed through
+fn gunzip(bytes: Vec<u8>) -> Result<Vec<u8>, RetroRsError> {
+    if !bytes.starts_with(&[0x1f, 0x8b]) {
+        return Ok(bytes);
+    }
+    #[cfg(feature = "use_deflate")]
+    {
+        use std::io::Read;
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut out)
+            .map_err(|err| RetroRsError::EnvSpecError(format!("corrupt state: {err}")))?;
+        Ok(out)
+    }
+    #[cfg(not(feature = "use_deflate"))]
+    Err(RetroRsError::EnvSpecError(
+        "gzip-compressed states need the use_deflate feature".to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integration_directory() {
+        let dir = std::env::temp_dir().join(format!("retro-rs-gym-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = r#"{"info": {"lives": {"address": 1882, "type": "|u1"},
+                                "score": {"address": 2012, "type": ">d3"}}}"#;
+        std::fs::write(dir.join(DATA_FILE), data).unwrap();
+        std::fs::write(dir.join(METADATA_FILE), r#"{"default_state": "Level1"}"#).unwrap();
+        std::fs::write(dir.join(ROM_SHA_FILE), "ABCDEF\n").unwrap();
+        let scenario = r#"{"reward": {"variables": {"score": {"reward": 1.0}}},
+                           "done": {"variables": {"lives": {"op": "zero"}}}}"#;
+        std::fs::write(dir.join("scenario.json"), scenario).unwrap();
+        std::fs::write(dir.join("Level1.state"), [1, 2, 3]).unwrap();
+
+        let game = GameIntegration::open(&dir).unwrap();
+        assert_eq!(game.default_state(), Some("Level1"));
+        assert_eq!(game.rom_sha1(), ["abcdef"]);
+        assert_eq!(game.scenarios().unwrap(), [DEFAULT_SCENARIO]);
+        assert_eq!(game.states().unwrap(), ["Level1"]);
+        assert_eq!(game.state("Level1").unwrap(), [1, 2, 3]);
+        let spec = game.scenario(DEFAULT_SCENARIO).unwrap();
+        assert_eq!(spec.variables.len(), 2);
+        assert_eq!(spec.reward[0].variable, "score");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,370 @@
+//! Typed state for the libretro input devices beyond the joypad, passed to [`Emulator::run_with_input`].
+//!
+//! Cores ask for whichever device they expect on a port, so one [`Input`] holds a value for
+//! every device and only the one the core reads matters. Axes and screen positions use the
+//! libretro range of `-0x7fff..=0x7fff`.
+//!
+//! [`Emulator::run_with_input`]: crate::Emulator::run_with_input
+use crate::buttons::Buttons;
+use crate::emulator::cstr_to_string;
+use rust_libretro_sys::{
+    RETRO_DEVICE_ANALOG, RETRO_DEVICE_ID_ANALOG_X, RETRO_DEVICE_ID_ANALOG_Y,
+    RETRO_DEVICE_ID_JOYPAD_MASK, RETRO_DEVICE_ID_LIGHTGUN_AUX_A, RETRO_DEVICE_ID_LIGHTGUN_AUX_B,
+    RETRO_DEVICE_ID_LIGHTGUN_AUX_C, RETRO_DEVICE_ID_LIGHTGUN_DPAD_DOWN,
+    RETRO_DEVICE_ID_LIGHTGUN_DPAD_LEFT, RETRO_DEVICE_ID_LIGHTGUN_DPAD_RIGHT,
+    RETRO_DEVICE_ID_LIGHTGUN_DPAD_UP, RETRO_DEVICE_ID_LIGHTGUN_IS_OFFSCREEN,
+    RETRO_DEVICE_ID_LIGHTGUN_RELOAD, RETRO_DEVICE_ID_LIGHTGUN_SCREEN_X,
+    RETRO_DEVICE_ID_LIGHTGUN_SCREEN_Y, RETRO_DEVICE_ID_LIGHTGUN_SELECT,
+    RETRO_DEVICE_ID_LIGHTGUN_START, RETRO_DEVICE_ID_LIGHTGUN_TRIGGER,
+    RETRO_DEVICE_ID_MOUSE_BUTTON_4, RETRO_DEVICE_ID_MOUSE_BUTTON_5,
+    RETRO_DEVICE_ID_MOUSE_HORIZ_WHEELDOWN, RETRO_DEVICE_ID_MOUSE_HORIZ_WHEELUP,
+    RETRO_DEVICE_ID_MOUSE_LEFT, RETRO_DEVICE_ID_MOUSE_MIDDLE, RETRO_DEVICE_ID_MOUSE_RIGHT,
+    RETRO_DEVICE_ID_MOUSE_WHEELDOWN, RETRO_DEVICE_ID_MOUSE_WHEELUP, RETRO_DEVICE_ID_MOUSE_X,
+    RETRO_DEVICE_ID_MOUSE_Y, RETRO_DEVICE_ID_POINTER_COUNT, RETRO_DEVICE_ID_POINTER_PRESSED,
+    RETRO_DEVICE_ID_POINTER_X, RETRO_DEVICE_ID_POINTER_Y, RETRO_DEVICE_INDEX_ANALOG_BUTTON,
+    RETRO_DEVICE_INDEX_ANALOG_LEFT, RETRO_DEVICE_INDEX_ANALOG_RIGHT, RETRO_DEVICE_JOYPAD,
+    RETRO_DEVICE_LIGHTGUN, RETRO_DEVICE_MASK, RETRO_DEVICE_MOUSE, RETRO_DEVICE_POINTER,
+    RETRO_DEVICE_TYPE_SHIFT,
+};
+use rust_libretro_sys::{
+    retro_controller_description, retro_controller_info, retro_input_descriptor,
+};
+
+/// The full deflection of an analog axis, and the value of a fully pressed analog button.
+pub const AXIS_MAX: i16 = 0x7fff;
+
+/// A core-specific variant of a base device, as libretro's `RETRO_DEVICE_SUBCLASS` macro builds them,
+/// e.g. snes9x's multitap is `subclass(RETRO_DEVICE_JOYPAD, 1)`. Cores list theirs in their controller info.
+#[must_use]
+pub const fn subclass(base: u32, id: u32) -> u32 {
+    ((id + 1) << RETRO_DEVICE_TYPE_SHIFT) | base
+}
+
+/// Everything held on one controller port.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Input {
+    pub buttons: Buttons,
+    pub analog: Analog,
+    pub mouse: Mouse,
+    pub lightgun: Lightgun,
+    pub pointer: Pointer,
+}
+
+impl From<Buttons> for Input {
+    fn from(buttons: Buttons) -> Self {
+        Input {
+            buttons,
+            ..Input::default()
+        }
+    }
+}
+
+impl Input {
+    /// The value `retro_input_state_t` reports for `device`, `index` and `id` on this port.
+    #[must_use]
+    pub fn state(&self, device: u32, index: u32, id: u32) -> i16 {
+        // Subclassed devices like RETRO_DEVICE_SUBCLASS(JOYPAD, 1) read like their base class
+        match device & RETRO_DEVICE_MASK {
+            RETRO_DEVICE_JOYPAD if index == 0 => match id {
+                RETRO_DEVICE_ID_JOYPAD_MASK => i16::from(self.buttons),
+                0..16 => i16::from(self.buttons.get(id)),
+                _ => 0,
+            },
+            RETRO_DEVICE_ANALOG => self.analog.state(self.buttons, index, id),
+            RETRO_DEVICE_MOUSE if index == 0 => self.mouse.state(id),
+            RETRO_DEVICE_LIGHTGUN if index == 0 => self.lightgun.state(id),
+            RETRO_DEVICE_POINTER => self.pointer.state(index, id),
+            _ => 0,
+        }
+    }
+}
+
+/// The two thumbsticks of e.g. a DualShock or N64 pad; positive `y` is down.
+/// Analog button pressure is reported as fully pressed or released, from [`Input::buttons`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Analog {
+    pub left_x: i16,
+    pub left_y: i16,
+    pub right_x: i16,
+    pub right_y: i16,
+}
+
+impl Analog {
+    fn state(self, buttons: Buttons, index: u32, id: u32) -> i16 {
+        match (index, id) {
+            (RETRO_DEVICE_INDEX_ANALOG_LEFT, RETRO_DEVICE_ID_ANALOG_X) => self.left_x,
+            (RETRO_DEVICE_INDEX_ANALOG_LEFT, RETRO_DEVICE_ID_ANALOG_Y) => self.left_y,
+            (RETRO_DEVICE_INDEX_ANALOG_RIGHT, RETRO_DEVICE_ID_ANALOG_X) => self.right_x,
+            (RETRO_DEVICE_INDEX_ANALOG_RIGHT, RETRO_DEVICE_ID_ANALOG_Y) => self.right_y,
+            (RETRO_DEVICE_INDEX_ANALOG_BUTTON, 0..16) if buttons.get(id) => AXIS_MAX,
+            _ => 0,
+        }
+    }
+}
+
+/// A mouse, which reports how far it moved since the last frame rather than where it is.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Mouse {
+    pub dx: i16,
+    pub dy: i16,
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+    pub button_4: bool,
+    pub button_5: bool,
+    pub wheel_up: bool,
+    pub wheel_down: bool,
+    pub horiz_wheel_up: bool,
+    pub horiz_wheel_down: bool,
+}
+
+impl Mouse {
+    fn state(self, id: u32) -> i16 {
+        match id {
+            RETRO_DEVICE_ID_MOUSE_X => self.dx,
+            RETRO_DEVICE_ID_MOUSE_Y => self.dy,
+            RETRO_DEVICE_ID_MOUSE_LEFT => i16::from(self.left),
+            RETRO_DEVICE_ID_MOUSE_RIGHT => i16::from(self.right),
+            RETRO_DEVICE_ID_MOUSE_MIDDLE => i16::from(self.middle),
+            RETRO_DEVICE_ID_MOUSE_BUTTON_4 => i16::from(self.button_4),
+            RETRO_DEVICE_ID_MOUSE_BUTTON_5 => i16::from(self.button_5),
+            RETRO_DEVICE_ID_MOUSE_WHEELUP => i16::from(self.wheel_up),
+            RETRO_DEVICE_ID_MOUSE_WHEELDOWN => i16::from(self.wheel_down),
+            RETRO_DEVICE_ID_MOUSE_HORIZ_WHEELUP => i16::from(self.horiz_wheel_up),
+            RETRO_DEVICE_ID_MOUSE_HORIZ_WHEELDOWN => i16::from(self.horiz_wheel_down),
+            _ => 0,
+        }
+    }
+}
+
+/// A light gun such as the NES Zapper or Super Scope, aimed at a point on the screen.
+/// The deprecated relative `X`/`Y` IDs always read 0.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Lightgun {
+    pub screen_x: i16,
+    pub screen_y: i16,
+    /// Aimed off the screen, as games ask players to do to reload.
+    pub offscreen: bool,
+    pub trigger: bool,
+    pub reload: bool,
+    pub aux_a: bool,
+    pub aux_b: bool,
+    pub aux_c: bool,
+    pub start: bool,
+    pub select: bool,
+    pub dpad_up: bool,
+    pub dpad_down: bool,
+    pub dpad_left: bool,
+    pub dpad_right: bool,
+}
+
+impl Lightgun {
+    fn state(self, id: u32) -> i16 {
+        match id {
+            RETRO_DEVICE_ID_LIGHTGUN_SCREEN_X => self.screen_x,
+            RETRO_DEVICE_ID_LIGHTGUN_SCREEN_Y => self.screen_y,
+            RETRO_DEVICE_ID_LIGHTGUN_IS_OFFSCREEN => i16::from(self.offscreen),
+            RETRO_DEVICE_ID_LIGHTGUN_TRIGGER => i16::from(self.trigger),
+            RETRO_DEVICE_ID_LIGHTGUN_RELOAD => i16::from(self.reload),
+            RETRO_DEVICE_ID_LIGHTGUN_AUX_A => i16::from(self.aux_a),
+            RETRO_DEVICE_ID_LIGHTGUN_AUX_B => i16::from(self.aux_b),
+            RETRO_DEVICE_ID_LIGHTGUN_AUX_C => i16::from(self.aux_c),
+            RETRO_DEVICE_ID_LIGHTGUN_START => i16::from(self.start),
+            RETRO_DEVICE_ID_LIGHTGUN_SELECT => i16::from(self.select),
+            RETRO_DEVICE_ID_LIGHTGUN_DPAD_UP => i16::from(self.dpad_up),
+            RETRO_DEVICE_ID_LIGHTGUN_DPAD_DOWN => i16::from(self.dpad_down),
+            RETRO_DEVICE_ID_LIGHTGUN_DPAD_LEFT => i16::from(self.dpad_left),
+            RETRO_DEVICE_ID_LIGHTGUN_DPAD_RIGHT => i16::from(self.dpad_right),
+            _ => 0,
+        }
+    }
+}
+
+/// A single touch on a touchscreen, or an absolute pointer such as a stylus.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Pointer {
+    pub x: i16,
+    pub y: i16,
+    pub pressed: bool,
+}
+
+impl Pointer {
+    fn state(self, index: u32, id: u32) -> i16 {
+        // Only one touch is tracked, so later indices are never pressed
+        match (index, id) {
+            (_, RETRO_DEVICE_ID_POINTER_COUNT) => i16::from(self.pressed),
+            (0, RETRO_DEVICE_ID_POINTER_X) => self.x,
+            (0, RETRO_DEVICE_ID_POINTER_Y) => self.y,
+            (0, RETRO_DEVICE_ID_POINTER_PRESSED) => i16::from(self.pressed),
+            _ => 0,
+        }
+    }
+}
+
+/// A device a core accepts on a port, from its `RETRO_ENVIRONMENT_SET_CONTROLLER_INFO`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ControllerDescription {
+    /// A name for players, like "Multitap" or "SNES Mouse".
+    pub name: String,
+    /// The value to pass to [`Emulator::set_controller_port_device`](crate::Emulator::set_controller_port_device).
+    pub device: u32,
+}
+
+/// What a button or axis does in the loaded game, from the core's `RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InputDescriptor {
+    pub port: u32,
+    pub device: u32,
+    pub index: u32,
+    pub id: u32,
+    /// A label for players, like "Jump" or "Left Analog X".
+    pub description: String,
+}
+
+/// One input a core read, as passed to `retro_input_state_t`; see [`Emulator::input_queries`](crate::Emulator::input_queries).
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct InputQuery {
+    pub port: u32,
+    pub device: u32,
+    pub index: u32,
+    pub id: u32,
+}
+
+// Reads the array of ports ending in one with no types
+pub(crate) unsafe fn read_controller_info(
+    mut info: *const retro_controller_info,
+) -> Vec<Vec<ControllerDescription>> {
+    let mut ports = Vec::new();
+    while let Some(port) = unsafe { info.as_ref() }
+        && !port.types.is_null()
+    {
+        let types: &[retro_controller_description] =
+            unsafe { std::slice::from_raw_parts(port.types, port.num_types as usize) };
+        ports.push(
+            types
+                .iter()
+                .map(|ty| ControllerDescription {
+                    name: cstr_to_string(ty.desc),
+                    device: ty.id,
+                })
+                .collect(),
+        );
+        info = unsafe { info.add(1) };
+    }
+    ports
+}
+
+// Reads the array of descriptors ending in one with no description
+pub(crate) unsafe fn read_input_descriptors(
+    mut desc: *const retro_input_descriptor,
+) -> Vec<InputDescriptor> {
+    let mut descriptors = Vec::new();
+    while let Some(d) = unsafe { desc.as_ref() }
+        && !d.description.is_null()
+    {
+        descriptors.push(InputDescriptor {
+            port: d.port,
+            device: d.device,
+            index: d.index,
+            id: d.id,
+            description: cstr_to_string(d.description),
+        });
+        desc = unsafe { desc.add(1) };
+    }
+    descriptors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_states() {
+        let input = Input {
+            buttons: Buttons::new().a(true),
+            analog: Analog {
+                left_x: -AXIS_MAX,
+                ..Analog::default()
+            },
+            mouse: Mouse {
+                dx: 5,
+                left: true,
+                ..Mouse::default()
+            },
+            ..Input::default()
+        };
+        let a = rust_libretro_sys::RETRO_DEVICE_ID_JOYPAD_A;
+        assert_eq!(input.state(RETRO_DEVICE_JOYPAD, 0, a), 1);
+        // A subclassed joypad
+        assert_eq!(input.state(subclass(RETRO_DEVICE_JOYPAD, 1), 0, a), 1);
+        assert_eq!(
+            input.state(
+                RETRO_DEVICE_ANALOG,
+                RETRO_DEVICE_INDEX_ANALOG_LEFT,
+                RETRO_DEVICE_ID_ANALOG_X
+            ),
+            -AXIS_MAX
+        );
+        assert_eq!(
+            input.state(RETRO_DEVICE_ANALOG, RETRO_DEVICE_INDEX_ANALOG_BUTTON, a),
+            AXIS_MAX
+        );
+        assert_eq!(
+            input.state(RETRO_DEVICE_MOUSE, 0, RETRO_DEVICE_ID_MOUSE_X),
+            5
+        );
+        assert_eq!(
+            input.state(RETRO_DEVICE_MOUSE, 0, RETRO_DEVICE_ID_MOUSE_LEFT),
+            1
+        );
+        assert_eq!(
+            input.state(RETRO_DEVICE_LIGHTGUN, 0, RETRO_DEVICE_ID_LIGHTGUN_TRIGGER),
+            0
+        );
+    }
+
+    #[test]
+    fn descriptor_arrays() {
+        let jump = c"Jump";
+        let descriptors = [
+            retro_input_descriptor {
+                port: 0,
+                device: RETRO_DEVICE_JOYPAD,
+                index: 0,
+                id: rust_libretro_sys::RETRO_DEVICE_ID_JOYPAD_B,
+                description: jump.as_ptr(),
+            },
+            retro_input_descriptor {
+                port: 0,
+                device: 0,
+                index: 0,
+                id: 0,
+                description: std::ptr::null(),
+            },
+        ];
+        let read = unsafe { read_input_descriptors(descriptors.as_ptr()) };
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].description, "Jump");
+
+        let types = [retro_controller_description {
+            desc: c"Multitap".as_ptr(),
+            id: subclass(RETRO_DEVICE_JOYPAD, 1),
+        }];
+        let info = [
+            retro_controller_info {
+                types: types.as_ptr(),
+                num_types: 1,
+            },
+            retro_controller_info {
+                types: std::ptr::null(),
+                num_types: 0,
+            },
+        ];
+        let ports = unsafe { read_controller_info(info.as_ptr()) };
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0][0].name, "Multitap");
+        assert_eq!(ports[0][0].device, 0x201);
+    }
+}
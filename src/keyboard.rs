@@ -0,0 +1,273 @@
+//! The keyboard of computer cores like DOSBox, VICE and Fuse.
+//!
+//! Unlike the devices in [`crate::input`], there is one keyboard per emulator rather than per
+//! port. Key presses are queued with [`Emulator::key_down`], [`Emulator::key_up`] and
+//! [`Emulator::type_text`], then applied at the start of each [`Emulator::run`]: the keyboard's
+//! state changes for `RETRO_DEVICE_KEYBOARD` queries, and each event is passed to the callback
+//! the core registered with `RETRO_ENVIRONMENT_SET_KEYBOARD_CALLBACK`, if any.
+//!
+//! [`Emulator::key_down`]: crate::Emulator::key_down
+//! [`Emulator::key_up`]: crate::Emulator::key_up
+//! [`Emulator::type_text`]: crate::Emulator::type_text
+//! [`Emulator::run`]: crate::Emulator::run
+use rust_libretro_sys::{retro_key, retro_mod};
+use std::collections::VecDeque;
+
+// Enough bits for every key up to RETROK_LAST
+const KEY_WORDS: usize = 6;
+
+// Unshifted keys for the shifted symbols of a US keyboard
+const SHIFTED_SYMBOLS: &str = "~!@#$%^&*()_+{}|:\"<>?";
+const UNSHIFTED_SYMBOLS: &str = "`1234567890-=[]\\;',./";
+
+// A key pressed or released, as delivered to the core's keyboard callback
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct KeyEvent {
+    pub(crate) down: bool,
+    pub(crate) key: retro_key,
+    // The UTF-32 character the key types, or 0 if it types none or is released
+    pub(crate) character: u32,
+    // The modifier keys held once the event is applied
+    pub(crate) modifiers: retro_mod,
+}
+
+// Which keys are held, and the events still to be applied, one batch per frame
+#[derive(Default, Debug)]
+pub(crate) struct Keyboard {
+    pressed: [u64; KEY_WORDS],
+    // Each key's press or release, as requested, and the character it types
+    pending: VecDeque<Vec<(retro_key, bool, u32)>>,
+}
+
+impl Keyboard {
+    pub(crate) fn is_pressed(&self, key: retro_key) -> bool {
+        let key = key.0 as usize;
+        key < KEY_WORDS * 64 && self.pressed[key / 64] & (1 << (key % 64)) != 0
+    }
+    pub(crate) fn modifiers(&self) -> retro_mod {
+        [
+            (
+                retro_key::RETROK_LSHIFT,
+                retro_key::RETROK_RSHIFT,
+                retro_mod::RETROKMOD_SHIFT,
+            ),
+            (
+                retro_key::RETROK_LCTRL,
+                retro_key::RETROK_RCTRL,
+                retro_mod::RETROKMOD_CTRL,
+            ),
+            (
+                retro_key::RETROK_LALT,
+                retro_key::RETROK_RALT,
+                retro_mod::RETROKMOD_ALT,
+            ),
+            (
+                retro_key::RETROK_LMETA,
+                retro_key::RETROK_RMETA,
+                retro_mod::RETROKMOD_META,
+            ),
+        ]
+        .into_iter()
+        .filter(|(left, right, _)| self.is_pressed(*left) || self.is_pressed(*right))
+        .fold(retro_mod::RETROKMOD_NONE, |mods, (_, _, m)| mods | m)
+    }
+    pub(crate) fn pending_frames(&self) -> usize {
+        self.pending.len()
+    }
+    // Queues a press or release for the next frame
+    pub(crate) fn queue(&mut self, key: retro_key, down: bool) {
+        let character = if down {
+            key_character(key, !self.shift_held_after(1).is_empty())
+        } else {
+            0
+        };
+        if self.pending.is_empty() {
+            self.pending.push_back(Vec::new());
+        }
+        self.pending[0].push((key, down, character));
+    }
+    // Queues each character's keystroke after anything already queued, holding each key for
+    // `hold_frames` frames and leaving a frame between keys. If shift will already be held, it's
+    // released around unshifted characters instead of pressed around shifted ones.
+    pub(crate) fn queue_text(&mut self, text: &str, hold_frames: usize) {
+        let held = self.shift_held_after(self.pending.len());
+        for ch in text.chars() {
+            let (key, shift) = char_key(ch);
+            // Shift keys to toggle while this character is typed, and whether they're held around it
+            let (toggled, down) = match (shift, held.is_empty()) {
+                (true, true) => (vec![retro_key::RETROK_LSHIFT], false),
+                (false, false) => (held.clone(), true),
+                _ => (Vec::new(), false),
+            };
+            let mut press: Vec<_> = toggled.iter().map(|k| (*k, !down, 0)).collect();
+            press.push((key, true, u32::from(ch)));
+            self.pending.push_back(press);
+            for _ in 1..hold_frames {
+                self.pending.push_back(Vec::new());
+            }
+            let mut release = vec![(key, false, 0)];
+            release.extend(toggled.iter().map(|k| (*k, down, 0)));
+            self.pending.push_back(release);
+        }
+    }
+    // Applies the next frame's events, returning them for the core's callback
+    pub(crate) fn next_frame(&mut self) -> Vec<KeyEvent> {
+        let Some(batch) = self.pending.pop_front() else {
+            return Vec::new();
+        };
+        batch
+            .into_iter()
+            .map(|(key, down, character)| {
+                self.set(key, down);
+                KeyEvent {
+                    down,
+                    key,
+                    character,
+                    modifiers: self.modifiers(),
+                }
+            })
+            .collect()
+    }
+    // Drops the queued events and releases every held key, returning the releases for the
+    // core's callback
+    pub(crate) fn release_all(&mut self) -> Vec<KeyEvent> {
+        self.pending.clear();
+        let held: Vec<_> = (0..KEY_WORDS * 64)
+            .filter_map(|key| u32::try_from(key).ok().map(retro_key))
+            .filter(|key| self.is_pressed(*key))
+            .map(|key| (key, false, 0))
+            .collect();
+        self.pending.push_back(held);
+        self.next_frame()
+    }
+
+    fn set(&mut self, key: retro_key, down: bool) {
+        let key = key.0 as usize;
+        if key < KEY_WORDS * 64 {
+            let bit = 1 << (key % 64);
+            if down {
+                self.pressed[key / 64] |= bit;
+            } else {
+                self.pressed[key / 64] &= !bit;
+            }
+        }
+    }
+    // The shift keys which will be held once the next `frames` batches are applied
+    fn shift_held_after(&self, frames: usize) -> Vec<retro_key> {
+        let shift = [retro_key::RETROK_LSHIFT, retro_key::RETROK_RSHIFT];
+        let mut held = shift.map(|key| self.is_pressed(key));
+        for (key, down, _) in self.pending.iter().take(frames).flatten() {
+            if let Some(i) = shift.iter().position(|shift| shift == key) {
+                held[i] = *down;
+            }
+        }
+        shift
+            .into_iter()
+            .zip(held)
+            .filter(|(_, held)| *held)
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
+
+// The key that types `ch` on a US keyboard and whether it needs shift; other characters are
+// sent as RETROK_UNKNOWN, for cores which only look at the event's character
+fn char_key(ch: char) -> (retro_key, bool) {
+    match ch {
+        '\n' | '\r' => (retro_key::RETROK_RETURN, false),
+        '\t' => (retro_key::RETROK_TAB, false),
+        '\u{8}' => (retro_key::RETROK_BACKSPACE, false),
+        '\u{1b}' => (retro_key::RETROK_ESCAPE, false),
+        'A'..='Z' => (retro_key(u32::from(ch.to_ascii_lowercase())), true),
+        _ => match SHIFTED_SYMBOLS.find(ch) {
+            Some(i) => (retro_key(u32::from(UNSHIFTED_SYMBOLS.as_bytes()[i])), true),
+            None if ch == ' ' || ch.is_ascii_graphic() => (retro_key(u32::from(ch)), false),
+            None => (retro_key::RETROK_UNKNOWN, false),
+        },
+    }
+}
+
+// The character a key types on a US keyboard, or 0
+fn key_character(key: retro_key, shift: bool) -> u32 {
+    let Some(ch) = u8::try_from(key.0)
+        .ok()
+        .map(char::from)
+        .filter(|ch| *ch == ' ' || ch.is_ascii_graphic())
+    else {
+        return 0;
+    };
+    let ch = match UNSHIFTED_SYMBOLS.find(ch) {
+        Some(i) if shift => char::from(SHIFTED_SYMBOLS.as_bytes()[i]),
+        _ if shift => ch.to_ascii_uppercase(),
+        _ => ch,
+    };
+    u32::from(ch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_text() {
+        let mut keyboard = Keyboard::default();
+        keyboard.queue_text("A!", 2);
+        // Press, hold and release for each character
+        assert_eq!(keyboard.pending_frames(), 6);
+        let press = keyboard.next_frame();
+        assert_eq!(press.len(), 2);
+        assert_eq!(press[1].key, retro_key::RETROK_a);
+        assert_eq!(press[1].character, u32::from('A'));
+        assert_eq!(press[1].modifiers, retro_mod::RETROKMOD_SHIFT);
+        assert!(keyboard.is_pressed(retro_key::RETROK_a));
+        assert!(keyboard.next_frame().is_empty());
+        keyboard.next_frame();
+        assert!(!keyboard.is_pressed(retro_key::RETROK_a));
+        assert_eq!(keyboard.modifiers(), retro_mod::RETROKMOD_NONE);
+        assert_eq!(keyboard.next_frame()[1].key, retro_key(u32::from(b'1')));
+
+        keyboard.release_all();
+        keyboard.queue(retro_key::RETROK_LSHIFT, true);
+        keyboard.queue(retro_key(u32::from(b'/')), true);
+        assert_eq!(keyboard.next_frame()[1].character, u32::from('?'));
+    }
+
+    #[test]
+    fn held_shift_is_left_alone() {
+        let mut keyboard = Keyboard::default();
+        keyboard.queue(retro_key::RETROK_RSHIFT, true);
+        keyboard.queue_text("B", 1);
+        keyboard.next_frame();
+        // Only the letter is pressed and released, so the user's shift stays held
+        assert_eq!(keyboard.next_frame().len(), 1);
+        assert_eq!(keyboard.next_frame().len(), 1);
+        assert_eq!(keyboard.modifiers(), retro_mod::RETROKMOD_SHIFT);
+
+        // A lowercase letter lets go of shift while it's typed, so polling cores see it unshifted
+        keyboard.queue_text("b", 1);
+        let press = keyboard.next_frame();
+        assert_eq!(press[0].key, retro_key::RETROK_RSHIFT);
+        assert!(!press[0].down);
+        assert_eq!(press[1].character, u32::from('b'));
+        assert_eq!(press[1].modifiers, retro_mod::RETROKMOD_NONE);
+        assert!(!keyboard.is_pressed(retro_key::RETROK_RSHIFT));
+        let release = keyboard.next_frame();
+        assert_eq!(release[1].key, retro_key::RETROK_RSHIFT);
+        assert!(release[1].down);
+        assert_eq!(keyboard.modifiers(), retro_mod::RETROKMOD_SHIFT);
+
+        keyboard.queue(retro_key::RETROK_a, true);
+        keyboard.queue_text("CD", 1);
+        keyboard.next_frame();
+        let released = keyboard.release_all();
+        assert_eq!(
+            released.iter().map(|e| (e.key, e.down)).collect::<Vec<_>>(),
+            [
+                (retro_key::RETROK_a, false),
+                (retro_key::RETROK_RSHIFT, false)
+            ]
+        );
+        assert_eq!(released[1].modifiers, retro_mod::RETROKMOD_NONE);
+        assert_eq!(keyboard.pending_frames(), 0);
+    }
+}
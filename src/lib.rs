@@ -1,13 +1,53 @@
+mod audio;
+pub use audio::{AudioBuffer, AudioChannels};
 mod buttons;
 pub use buttons::Buttons;
+#[cfg(feature = "use_capi")]
+mod capi;
+mod core_library;
+mod core_options;
+pub use core_options::{
+    CoreOption, CoreOptionCategory, CoreOptionValue, format_core_options, parse_core_options,
+    read_core_options_file, write_core_options_file,
+};
+mod diagnostics;
+pub use diagnostics::{Diagnostic, DiagnosticHook, set_diagnostic_hook};
 mod emulator;
-pub use emulator::Emulator;
+pub use emulator::{ButtonCallback, Emulator, EmulatorBuilder, LogCallback, MemoryRegion};
+mod env;
+pub use env::{
+    Comparison, DoneCondition, DoneTerm, Endian, Env, EnvSpec, Measurement, RetroEnv, RewardTerm,
+    Step, Variable, VariableKind, VariableType,
+};
 mod error;
 pub use error::*;
+mod format;
 mod gfx;
 pub use gfx::{Gfx, SoftwareGfx};
+pub mod input;
+pub use input::{ControllerDescription, Input, InputDescriptor, InputQuery};
+#[cfg(feature = "use_json")]
+pub mod gym_retro;
+mod keyboard;
+pub mod movie;
 pub mod pixels;
+#[cfg(feature = "python")]
+mod python;
+mod recorder;
+pub use recorder::{Recorder, VideoFormat};
+mod remote;
+pub use remote::{RemoteCoreInfo, RemoteEmulator, run_worker};
+mod rewind;
 pub use libloading::Symbol;
+pub use rewind::RewindBuffer;
+mod savestate;
+pub use savestate::{Compression, Savestate, Thumbnail};
+mod vec_emulator;
+pub use vec_emulator::{ObservationFormat, VecEmulator};
+#[cfg(feature = "use_image")]
+mod animation;
+#[cfg(feature = "use_image")]
+pub use animation::{AnimationFormat, EpisodeExporter};
 #[cfg(feature = "use_image")]
 mod fb_to_image;
 #[cfg(feature = "use_image")]
@@ -0,0 +1,385 @@
+//! Input movies: the per-frame [`Buttons`] fed to [`Emulator::run`], enough to replay a session exactly.
+//!
+//! # File format
+//! All integers are little-endian; strings are a `u32` byte length followed by UTF-8.
+//! ```text
+//! magic        8 bytes   "RRSMOVIE"
+//! version      u32       1
+//! core_name    string    library_name from retro_get_system_info
+//! core_version string    library_version from retro_get_system_info
+//! rom_sha1     string    lowercase hex SHA-1 of the ROM file
+//! start        u8        0: power-on, 1: savestate
+//! state        u64 length + bytes, only present for a savestate start
+//! frame_count  u64
+//! frames       frame_count × (flags: u8, port 0: i16, port 1: i16)
+//! ```
+//! Bit 0 of a frame's flags means [`Emulator::reset`] was called just before that frame ran;
+//! the port values are the [`Buttons`] bitmasks.
+//!
+//! Movies from other emulators can be converted with [`fm2`] (FCEUX) and [`bk2`] (`BizHawk`).
+pub mod bk2;
+pub mod fm2;
+
+use crate::buttons::Buttons;
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use crate::format::{ByteReader, put_string};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RRSMOVIE";
+const VERSION: u32 = 1;
+const FLAG_RESET: u8 = 1;
+
+/// Where a movie's first frame begins.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MovieStart {
+    /// A freshly created emulator which hasn't run any frames.
+    PowerOn,
+    /// The serialized state from [`Emulator::save`].
+    Savestate(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MovieFrame {
+    /// Whether the emulator was reset before this frame ran.
+    pub reset: bool,
+    pub buttons: [Buttons; 2],
+}
+
+impl MovieFrame {
+    /// Resets the emulator if this frame calls for it, then runs it with this frame's inputs.
+    pub fn run(&self, emu: &mut Emulator) {
+        if self.reset {
+            emu.reset();
+        }
+        emu.run(self.buttons);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Movie {
+    pub core_name: String,
+    pub core_version: String,
+    pub rom_sha1: String,
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    /// An empty movie which starts from `emu`'s current state.
+    /// # Errors
+    /// [`RetroRsError::MovieStartError`]: `from_power_on` is set but `emu` has already run frames, or its state couldn't be saved
+    pub fn new(emu: &Emulator, from_power_on: bool) -> Result<Self, RetroRsError> {
+        let start = if from_power_on {
+            if emu.frame_count() != 0 {
+                return Err(RetroRsError::MovieStartError);
+            }
+            MovieStart::PowerOn
+        } else {
+            let mut state = vec![0; emu.save_size()];
+            if !emu.save(&mut state) {
+                return Err(RetroRsError::MovieStartError);
+            }
+            MovieStart::Savestate(state)
+        };
+        Ok(Movie {
+            core_name: emu.get_library_name(),
+            core_version: emu.get_library_version(),
+            rom_sha1: emu.rom_sha1().to_owned(),
+            start,
+            frames: Vec::new(),
+        })
+    }
+    /// Checks that `emu` is running the same core and ROM this movie was recorded with.
+    /// # Errors
+    /// [`RetroRsError::MovieMismatchError`]: The core name or ROM hash differs
+    pub fn check(&self, emu: &Emulator) -> Result<(), RetroRsError> {
+        let core_name = emu.get_library_name();
+        if core_name != self.core_name {
+            return Err(RetroRsError::MovieMismatchError(format!(
+                "movie was recorded with core {}, not {core_name}",
+                self.core_name
+            )));
+        }
+        if emu.rom_sha1() != self.rom_sha1 {
+            return Err(RetroRsError::MovieMismatchError(format!(
+                "movie was recorded with ROM {}, not {}",
+                self.rom_sha1,
+                emu.rom_sha1()
+            )));
+        }
+        Ok(())
+    }
+    /// Checks `emu` against the movie and puts it into the movie's starting state.
+    /// # Errors
+    /// [`RetroRsError::MovieMismatchError`]: See [`Movie::check`]
+    /// [`RetroRsError::MovieStartError`]: The movie starts at power-on but `emu` has already run frames, or the starting savestate couldn't be loaded
+    pub fn prepare(&self, emu: &mut Emulator) -> Result<(), RetroRsError> {
+        self.check(emu)?;
+        let ok = match &self.start {
+            MovieStart::PowerOn => emu.frame_count() == 0,
+            MovieStart::Savestate(state) => emu.load(state),
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(RetroRsError::MovieStartError)
+        }
+    }
+    /// Prepares `emu` and runs every frame of the movie, calling `after_frame` with each frame's index.
+    /// # Errors
+    /// See [`Movie::prepare`]
+    pub fn replay(
+        &self,
+        emu: &mut Emulator,
+        mut after_frame: impl FnMut(usize, &mut Emulator),
+    ) -> Result<(), RetroRsError> {
+        self.prepare(emu)?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            frame.run(emu);
+            after_frame(i, emu);
+        }
+        Ok(())
+    }
+
+    /// # Errors
+    /// [`RetroRsError::MovieIOError`]: Writing failed
+    pub fn write(&self, mut w: impl Write) -> Result<(), RetroRsError> {
+        let mut buf = Vec::with_capacity(64 + self.frames.len() * 5);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        for s in [&self.core_name, &self.core_version, &self.rom_sha1] {
+            put_string(&mut buf, s)?;
+        }
+        match &self.start {
+            MovieStart::PowerOn => buf.push(0),
+            MovieStart::Savestate(state) => {
+                buf.push(1);
+                buf.extend_from_slice(&(state.len() as u64).to_le_bytes());
+                buf.extend_from_slice(state);
+            }
+        }
+        buf.extend_from_slice(&(self.frames.len() as u64).to_le_bytes());
+        for frame in &self.frames {
+            buf.push(if frame.reset { FLAG_RESET } else { 0 });
+            for buttons in frame.buttons {
+                buf.extend_from_slice(&i16::from(buttons).to_le_bytes());
+            }
+        }
+        w.write_all(&buf).map_err(RetroRsError::MovieIOError)
+    }
+    /// # Errors
+    /// [`RetroRsError::MovieIOError`]: Reading failed
+    /// [`RetroRsError::MovieFormatError`]: The data is not a movie in a supported version of the format
+    pub fn read(mut r: impl Read) -> Result<Self, RetroRsError> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)
+            .map_err(RetroRsError::MovieIOError)?;
+        let mut data = ByteReader::new(&bytes, RetroRsError::MovieFormatError);
+        if data.take(MAGIC.len())? != MAGIC {
+            return Err(data.error("not a retro-rs movie"));
+        }
+        let version = u32::from_le_bytes(data.take_array()?);
+        if version != VERSION {
+            return Err(data.error(format!("unsupported version {version}")));
+        }
+        let core_name = data.take_string()?;
+        let core_version = data.take_string()?;
+        let rom_sha1 = data.take_string()?;
+        let start = match data.take_array::<1>()?[0] {
+            0 => MovieStart::PowerOn,
+            1 => {
+                let len = data.take_usize()?;
+                MovieStart::Savestate(data.take(len)?.to_vec())
+            }
+            other => return Err(data.error(format!("unknown start kind {other}"))),
+        };
+        let frame_count = data.take_usize()?;
+        // The count is untrusted, so it mustn't overflow
+        if frame_count.checked_mul(5) != Some(data.rest().len()) {
+            return Err(data.error(format!("expected {frame_count} frames")));
+        }
+        let frames = data
+            .rest()
+            .chunks_exact(5)
+            .map(|f| MovieFrame {
+                reset: f[0] & FLAG_RESET != 0,
+                buttons: [
+                    Buttons::from(i16::from_le_bytes([f[1], f[2]])),
+                    Buttons::from(i16::from_le_bytes([f[3], f[4]])),
+                ],
+            })
+            .collect();
+        Ok(Movie {
+            core_name,
+            core_version,
+            rom_sha1,
+            start,
+            frames,
+        })
+    }
+    /// # Errors
+    /// See [`Movie::write`]
+    pub fn save_file(&self, path: &Path) -> Result<(), RetroRsError> {
+        let file = std::fs::File::create(path).map_err(RetroRsError::MovieIOError)?;
+        self.write(std::io::BufWriter::new(file))
+    }
+    /// # Errors
+    /// See [`Movie::read`]
+    pub fn load_file(path: &Path) -> Result<Self, RetroRsError> {
+        let file = std::fs::File::open(path).map_err(RetroRsError::MovieIOError)?;
+        Self::read(std::io::BufReader::new(file))
+    }
+}
+
+/// Something in a movie from another emulator which can't be replayed exactly.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MovieNote {
+    /// A soft reset, replayed with [`Emulator::reset`].
+    SoftReset,
+    /// A hard reset (power cycle), approximated with [`Emulator::reset`].
+    HardReset,
+    /// A command retro-rs has no equivalent for, e.g. a disk swap; it is ignored.
+    UnsupportedCommand(String),
+    /// Input on a device or button retro-rs has no equivalent for; it is ignored.
+    UnsupportedInput(String),
+}
+
+/// Inputs converted from another emulator's movie format.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ImportedMovie {
+    /// The movie's header as key/value pairs, in file order.
+    pub header: Vec<(String, String)>,
+    pub frames: Vec<MovieFrame>,
+    /// Frame indices paired with what couldn't be converted exactly on that frame.
+    pub notes: Vec<(usize, MovieNote)>,
+}
+
+impl ImportedMovie {
+    /// The first header value for `key`, if any.
+    #[must_use]
+    pub fn header_value(&self, key: &str) -> Option<&str> {
+        self.header
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+    /// Whether every frame converted exactly, apart from soft resets.
+    #[must_use]
+    pub fn is_exact(&self) -> bool {
+        self.notes
+            .iter()
+            .all(|(_, note)| *note == MovieNote::SoftReset)
+    }
+    /// Wraps the frames in a [`Movie`] which starts at power-on of `emu`'s core and ROM.
+    #[must_use]
+    pub fn to_movie(&self, emu: &Emulator) -> Movie {
+        Movie {
+            core_name: emu.get_library_name(),
+            core_version: emu.get_library_version(),
+            rom_sha1: emu.rom_sha1().to_owned(),
+            start: MovieStart::PowerOn,
+            frames: self.frames.clone(),
+        }
+    }
+}
+
+/// Runs an [`Emulator`] while appending each frame's inputs to a [`Movie`].
+pub struct MovieRecorder<'a> {
+    emu: &'a mut Emulator,
+    movie: Movie,
+    reset_pending: bool,
+}
+
+impl<'a> MovieRecorder<'a> {
+    /// # Errors
+    /// See [`Movie::new`]
+    pub fn new(emu: &'a mut Emulator, from_power_on: bool) -> Result<Self, RetroRsError> {
+        let movie = Movie::new(emu, from_power_on)?;
+        Ok(MovieRecorder {
+            emu,
+            movie,
+            reset_pending: false,
+        })
+    }
+    pub fn run(&mut self, inputs: [Buttons; 2]) {
+        let frame = MovieFrame {
+            reset: std::mem::take(&mut self.reset_pending),
+            buttons: inputs,
+        };
+        // The reset already happened in `reset`, so just run
+        self.emu.run(frame.buttons);
+        self.movie.frames.push(frame);
+    }
+    /// Resets the emulator and marks the next recorded frame as following a reset.
+    pub fn reset(&mut self) {
+        self.emu.reset();
+        self.reset_pending = true;
+    }
+    #[must_use]
+    pub fn emulator(&self) -> &Emulator {
+        self.emu
+    }
+    #[must_use]
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+    #[must_use]
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movie_round_trip() {
+        let movie = Movie {
+            core_name: "FCEUmm".to_owned(),
+            core_version: "(SVN) 1234".to_owned(),
+            rom_sha1: "ea343f4e445a9050d4b4fbac2c77d0693b1d0922".to_owned(),
+            start: MovieStart::Savestate(vec![1, 2, 3]),
+            frames: vec![
+                MovieFrame::default(),
+                MovieFrame {
+                    reset: true,
+                    buttons: [
+                        Buttons::new().start(true),
+                        Buttons::new().a(true).left(true),
+                    ],
+                },
+            ],
+        };
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+        assert_eq!(Movie::read(bytes.as_slice()).unwrap(), movie);
+        bytes.pop();
+        assert!(matches!(
+            Movie::read(bytes.as_slice()),
+            Err(RetroRsError::MovieFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_frame_count() {
+        let movie = Movie {
+            core_name: String::new(),
+            core_version: String::new(),
+            rom_sha1: String::new(),
+            start: MovieStart::PowerOn,
+            frames: Vec::new(),
+        };
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+        // The frame count is the last thing in a movie without frames
+        let count_at = bytes.len() - 8;
+        bytes[count_at..].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            Movie::read(bytes.as_slice()),
+            Err(RetroRsError::MovieFormatError(_))
+        ));
+    }
+}
@@ -0,0 +1,385 @@
+//! `BizHawk` `.bk2` movies.
+//!
+//! A `.bk2` file is a zip archive; its `Header.txt` and `Input Log.txt` entries can be converted
+//! with [`parse`] and [`format_input_log`] without the `use_zip` feature, which is only needed by
+//! [`read_file`] and [`write_file`]. Digital gamepad buttons on P1 and P2 are converted, along with
+//! the `Reset` and `Power` buttons; other controls are reported as [`MovieNote`]s.
+use super::{ImportedMovie, MovieFrame, MovieNote};
+use crate::buttons::Buttons;
+use crate::error::RetroRsError;
+use std::fmt::Write;
+#[cfg(feature = "use_zip")]
+use std::path::Path;
+
+#[cfg(feature = "use_zip")]
+const HEADER_ENTRY: &str = "Header.txt";
+#[cfg(feature = "use_zip")]
+const INPUT_LOG_ENTRY: &str = "Input Log.txt";
+
+/// The consoles whose `BizHawk` input logs can be written by [`format_input_log`].
+/// Any system's log can be read; its layout comes from the log itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bk2System {
+    Nes,
+    Snes,
+}
+
+impl Bk2System {
+    /// The `Platform` value in `Header.txt`.
+    #[must_use]
+    pub fn platform(self) -> &'static str {
+        match self {
+            Bk2System::Nes => "NES",
+            Bk2System::Snes => "SNES",
+        }
+    }
+    // Button names and mnemonics of one player's group, in log order
+    fn pad(self) -> &'static [(&'static str, char)] {
+        match self {
+            Bk2System::Nes => &[
+                ("Up", 'U'),
+                ("Down", 'D'),
+                ("Left", 'L'),
+                ("Right", 'R'),
+                ("Start", 'S'),
+                ("Select", 's'),
+                ("B", 'B'),
+                ("A", 'A'),
+            ],
+            Bk2System::Snes => &[
+                ("Up", 'U'),
+                ("Down", 'D'),
+                ("Left", 'L'),
+                ("Right", 'R'),
+                ("Select", 's'),
+                ("Start", 'S'),
+                ("Y", 'Y'),
+                ("B", 'B'),
+                ("X", 'X'),
+                ("A", 'A'),
+                ("L", 'l'),
+                ("R", 'r'),
+            ],
+        }
+    }
+    fn log_key(self) -> String {
+        let mut key = "LogKey:#Reset|Power|".to_owned();
+        for player in 1..=2 {
+            for (i, (name, _)) in self.pad().iter().enumerate() {
+                let group = if i == 0 { "#" } else { "" };
+                let _ = write!(key, "{group}P{player} {name}|");
+            }
+        }
+        key
+    }
+}
+
+fn button_setter(name: &str) -> Option<fn(Buttons, bool) -> Buttons> {
+    Some(match name {
+        "Up" => Buttons::up,
+        "Down" => Buttons::down,
+        "Left" => Buttons::left,
+        "Right" => Buttons::right,
+        "Start" => Buttons::start,
+        "Select" => Buttons::select,
+        "A" => Buttons::a,
+        "B" => Buttons::b,
+        "X" => Buttons::x,
+        "Y" => Buttons::y,
+        "L" => Buttons::l1,
+        "R" => Buttons::r1,
+        _ => return None,
+    })
+}
+
+fn button_getter(name: &str) -> fn(Buttons) -> bool {
+    match name {
+        "Up" => Buttons::get_up,
+        "Down" => Buttons::get_down,
+        "Left" => Buttons::get_left,
+        "Right" => Buttons::get_right,
+        "Start" => Buttons::get_start,
+        "Select" => Buttons::get_select,
+        "A" => Buttons::get_a,
+        "B" => Buttons::get_b,
+        "X" => Buttons::get_x,
+        "Y" => Buttons::get_y,
+        "L" => Buttons::get_l1,
+        "R" => Buttons::get_r1,
+        _ => unreachable!("{name} is not a pad button"),
+    }
+}
+
+/// Converts the contents of a movie's `Header.txt` and `Input Log.txt`.
+/// # Errors
+/// [`RetroRsError::MovieFormatError`]: The input log has no `LogKey` or a frame doesn't match it
+pub fn parse(header: &str, input_log: &str) -> Result<ImportedMovie, RetroRsError> {
+    let mut movie = ImportedMovie::default();
+    for line in header.lines() {
+        let line = line.trim_end_matches('\r');
+        if !line.trim().is_empty() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            movie.header.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    let mut groups: Option<Vec<Vec<&str>>> = None;
+    let mut in_input = false;
+    for line in input_log.lines() {
+        let line = line.trim_end_matches('\r');
+        match line {
+            "[Input]" => in_input = true,
+            "[/Input]" => in_input = false,
+            _ if !in_input => {}
+            _ if line.starts_with("LogKey:") => {
+                groups = Some(parse_log_key(&line["LogKey:".len()..]));
+            }
+            _ if line.starts_with('|') => {
+                let Some(groups) = &groups else {
+                    return Err(RetroRsError::MovieFormatError(
+                        "input log has no LogKey".to_owned(),
+                    ));
+                };
+                parse_frame(line, groups, &mut movie)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(movie)
+}
+
+// "#Reset|Power|#P1 Up|P1 Down|" has the groups [Reset, Power] and [P1 Up, P1 Down]
+fn parse_log_key(key: &str) -> Vec<Vec<&str>> {
+    let mut groups: Vec<Vec<&str>> = Vec::new();
+    for name in key.split('|').filter(|n| !n.is_empty()) {
+        if let Some(name) = name.strip_prefix('#') {
+            groups.push(vec![name]);
+        } else if let Some(group) = groups.last_mut() {
+            group.push(name);
+        } else {
+            groups.push(vec![name]);
+        }
+    }
+    groups
+}
+
+fn parse_frame(
+    line: &str,
+    groups: &[Vec<&str>],
+    movie: &mut ImportedMovie,
+) -> Result<(), RetroRsError> {
+    let frame_idx = movie.frames.len();
+    let fields: Vec<&str> = line.split('|').collect();
+    // "|..|UDLRSsBA|" splits into an empty first and last field
+    if fields.len() != groups.len() + 2 {
+        return Err(RetroRsError::MovieFormatError(format!(
+            "frame {frame_idx} has {} fields but the LogKey has {} groups",
+            fields.len().saturating_sub(2),
+            groups.len()
+        )));
+    }
+    let mut frame = MovieFrame::default();
+    for (field, group) in fields[1..].iter().zip(groups) {
+        if field.chars().count() != group.len() {
+            // Analog values are written as comma-separated numbers
+            if field.chars().any(|c| !matches!(c, ' ' | '0' | ',' | '.')) {
+                movie.notes.push((
+                    frame_idx,
+                    MovieNote::UnsupportedInput(format!("{}: {}", group.join("|"), field.trim())),
+                ));
+            }
+            continue;
+        }
+        for (c, name) in field.chars().zip(group) {
+            let pressed = c != '.' && c != ' ';
+            if !pressed {
+                continue;
+            }
+            let (port, button) = match name.split_once(' ') {
+                Some(("P1", button)) => (Some(0), button),
+                Some(("P2", button)) => (Some(1), button),
+                _ => (None, *name),
+            };
+            match (port, button, button_setter(button)) {
+                (Some(port), _, Some(set)) => frame.buttons[port] = set(frame.buttons[port], true),
+                (None, "Reset", _) => {
+                    frame.reset = true;
+                    movie.notes.push((frame_idx, MovieNote::SoftReset));
+                }
+                (None, "Power", _) => {
+                    frame.reset = true;
+                    movie.notes.push((frame_idx, MovieNote::HardReset));
+                }
+                _ => movie
+                    .notes
+                    .push((frame_idx, MovieNote::UnsupportedInput((*name).to_owned()))),
+            }
+        }
+    }
+    movie.frames.push(frame);
+    Ok(())
+}
+
+/// Formats a `Header.txt` for `system`.
+/// `header` entries are written after the required ones and may override them, e.g. `SHA1`.
+#[must_use]
+pub fn format_header(system: Bk2System, header: &[(String, String)]) -> String {
+    let mut text = String::new();
+    let defaults = [
+        ("MovieVersion", "BizHawk v2.0.0"),
+        ("Platform", system.platform()),
+        ("rerecordCount", "0"),
+    ];
+    // Writing to a String can't fail
+    for (key, value) in defaults {
+        if !header.iter().any(|(k, _)| k == key) {
+            let _ = writeln!(text, "{key} {value}");
+        }
+    }
+    for (key, value) in header {
+        let _ = writeln!(text, "{key} {value}");
+    }
+    text
+}
+
+/// Formats frames as an `Input Log.txt` with two of `system`'s gamepads.
+#[must_use]
+pub fn format_input_log(system: Bk2System, frames: &[MovieFrame]) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "[Input]");
+    let _ = writeln!(text, "{}", system.log_key());
+    for frame in frames {
+        text.push('|');
+        text.push_str(if frame.reset { "r." } else { ".." });
+        for buttons in frame.buttons {
+            text.push('|');
+            for (name, mnemonic) in system.pad() {
+                text.push(if button_getter(name)(buttons) {
+                    *mnemonic
+                } else {
+                    '.'
+                });
+            }
+        }
+        text.push_str("|\n");
+    }
+    let _ = writeln!(text, "[/Input]");
+    text
+}
+
+#[cfg(feature = "use_zip")]
+fn zip_error(err: zip::result::ZipError) -> RetroRsError {
+    match err {
+        zip::result::ZipError::Io(err) => RetroRsError::MovieIOError(err),
+        err => RetroRsError::MovieFormatError(err.to_string()),
+    }
+}
+
+/// # Errors
+/// [`RetroRsError::MovieIOError`]: The file couldn't be read
+/// [`RetroRsError::MovieFormatError`]: The file is not a zip archive with a header and input log
+/// Others: See [`parse`]
+#[cfg(feature = "use_zip")]
+pub fn read_file(path: &Path) -> Result<ImportedMovie, RetroRsError> {
+    use std::io::Read;
+    let file = std::fs::File::open(path).map_err(RetroRsError::MovieIOError)?;
+    let mut archive = zip::ZipArchive::new(std::io::BufReader::new(file)).map_err(zip_error)?;
+    let mut read_entry = |name: &str| -> Result<String, RetroRsError> {
+        let mut text = String::new();
+        archive
+            .by_name(name)
+            .map_err(zip_error)?
+            .read_to_string(&mut text)
+            .map_err(RetroRsError::MovieIOError)?;
+        Ok(text)
+    };
+    let header = read_entry(HEADER_ENTRY)?;
+    let input_log = read_entry(INPUT_LOG_ENTRY)?;
+    parse(&header, &input_log)
+}
+
+/// # Errors
+/// [`RetroRsError::MovieIOError`]: The file couldn't be written
+#[cfg(feature = "use_zip")]
+pub fn write_file(
+    path: &Path,
+    system: Bk2System,
+    frames: &[MovieFrame],
+    header: &[(String, String)],
+) -> Result<(), RetroRsError> {
+    use std::io::Write;
+    let file = std::fs::File::create(path).map_err(RetroRsError::MovieIOError)?;
+    let mut archive = zip::ZipWriter::new(std::io::BufWriter::new(file));
+    let options = zip::write::SimpleFileOptions::default();
+    for (name, text) in [
+        (HEADER_ENTRY, format_header(system, header)),
+        (INPUT_LOG_ENTRY, format_input_log(system, frames)),
+    ] {
+        archive.start_file(name, options).map_err(zip_error)?;
+        archive
+            .write_all(text.as_bytes())
+            .map_err(RetroRsError::MovieIOError)?;
+    }
+    archive
+        .finish()
+        .map_err(zip_error)?
+        .flush()
+        .map_err(RetroRsError::MovieIOError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "[Input]\n\
+                       LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|#P1 Zapper X|\n\
+                       |..|........|........|  0,|\n\
+                       |r.|U...S...|.......A|  0,|\n\
+                       |..|...R...A|........| 12,|\n\
+                       [/Input]\n";
+
+    #[test]
+    fn parse_bk2() {
+        let movie = parse("MovieVersion BizHawk v2.0.0\nPlatform NES\n", LOG).unwrap();
+        assert_eq!(movie.header_value("Platform"), Some("NES"));
+        assert_eq!(movie.frames.len(), 3);
+        assert!(movie.frames[1].reset);
+        assert_eq!(
+            movie.frames[1].buttons[0],
+            Buttons::new().up(true).start(true)
+        );
+        assert_eq!(movie.frames[1].buttons[1], Buttons::new().a(true));
+        assert_eq!(
+            movie.frames[2].buttons[0],
+            Buttons::new().right(true).a(true)
+        );
+        assert_eq!(
+            movie.notes,
+            vec![
+                (1, MovieNote::SoftReset),
+                (
+                    2,
+                    MovieNote::UnsupportedInput("P1 Zapper X: 12,".to_owned())
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn bk2_round_trip() {
+        let frames = vec![
+            MovieFrame {
+                reset: false,
+                buttons: [Buttons::new().l1(true).y(true), Buttons::new().select(true)],
+            },
+            MovieFrame {
+                reset: true,
+                buttons: [Buttons::new(), Buttons::new().x(true)],
+            },
+        ];
+        let header = format_header(Bk2System::Snes, &[]);
+        let movie = parse(&header, &format_input_log(Bk2System::Snes, &frames)).unwrap();
+        assert_eq!(movie.header_value("Platform"), Some("SNES"));
+        assert_eq!(movie.frames, frames);
+        assert!(movie.is_exact());
+    }
+}
@@ -0,0 +1,238 @@
+//! FCEUX `.fm2` text movies.
+//!
+//! Only the text input log is supported (not `binary 1` movies). Gamepads on ports 0 and 1 are
+//! converted; Four Score pads, the Zapper and the expansion port are reported as [`MovieNote`]s.
+use super::{ImportedMovie, MovieFrame, MovieNote};
+use crate::buttons::Buttons;
+use crate::error::RetroRsError;
+use std::fmt::Write;
+use std::path::Path;
+
+type ButtonSetter = fn(Buttons, bool) -> Buttons;
+
+// The order of buttons in an FM2 gamepad field
+const PAD_BUTTONS: [(char, ButtonSetter); 8] = [
+    ('R', Buttons::right),
+    ('L', Buttons::left),
+    ('D', Buttons::down),
+    ('U', Buttons::up),
+    ('T', Buttons::start),
+    ('S', Buttons::select),
+    ('B', Buttons::b),
+    ('A', Buttons::a),
+];
+const PAD_GETTERS: [fn(Buttons) -> bool; 8] = [
+    Buttons::get_right,
+    Buttons::get_left,
+    Buttons::get_down,
+    Buttons::get_up,
+    Buttons::get_start,
+    Buttons::get_select,
+    Buttons::get_b,
+    Buttons::get_a,
+];
+
+const CMD_SOFT_RESET: u32 = 1;
+const CMD_HARD_RESET: u32 = 2;
+const CMD_FDS_INSERT: u32 = 4;
+const CMD_FDS_SELECT: u32 = 8;
+const CMD_VS_INSERT_COIN: u32 = 16;
+
+/// # Errors
+/// [`RetroRsError::MovieFormatError`]: The text is not a text-mode FM2 movie
+#[allow(clippy::missing_panics_doc)]
+pub fn parse(text: &str) -> Result<ImportedMovie, RetroRsError> {
+    let mut movie = ImportedMovie::default();
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with('|') {
+            parse_frame(line, &mut movie)?;
+        } else if !line.trim().is_empty() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            movie.header.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    if movie.header_value("binary") == Some("1") {
+        return Err(RetroRsError::MovieFormatError(
+            "binary FM2 movies are not supported".to_owned(),
+        ));
+    }
+    if movie.header_value("version").is_none() {
+        return Err(RetroRsError::MovieFormatError(
+            "FM2 header has no version".to_owned(),
+        ));
+    }
+    Ok(movie)
+}
+
+fn parse_frame(line: &str, movie: &mut ImportedMovie) -> Result<(), RetroRsError> {
+    let frame_idx = movie.frames.len();
+    let fields: Vec<&str> = line.split('|').collect();
+    // "|commands|port0|port1|port2|" splits into an empty first and last field
+    if fields.len() < 4 {
+        return Err(RetroRsError::MovieFormatError(format!(
+            "frame {frame_idx} has too few fields"
+        )));
+    }
+    let commands: u32 = fields[1].trim().parse().map_err(|_| {
+        RetroRsError::MovieFormatError(format!("frame {frame_idx} has a bad command field"))
+    })?;
+    let mut frame = MovieFrame::default();
+    if commands & CMD_SOFT_RESET != 0 {
+        frame.reset = true;
+        movie.notes.push((frame_idx, MovieNote::SoftReset));
+    }
+    if commands & CMD_HARD_RESET != 0 {
+        frame.reset = true;
+        movie.notes.push((frame_idx, MovieNote::HardReset));
+    }
+    for (bit, name) in [
+        (CMD_FDS_INSERT, "FDS disk insert"),
+        (CMD_FDS_SELECT, "FDS disk side select"),
+        (CMD_VS_INSERT_COIN, "VS insert coin"),
+    ] {
+        if commands & bit != 0 {
+            movie
+                .notes
+                .push((frame_idx, MovieNote::UnsupportedCommand(name.to_owned())));
+        }
+    }
+    // Everything between the command field and the trailing empty field is a port
+    let ports = &fields[2..fields.len() - 1];
+    for (port, field) in ports.iter().enumerate() {
+        if port < 2 && field.chars().count() == PAD_BUTTONS.len() {
+            frame.buttons[port] = parse_pad(field);
+        } else if field.chars().any(|c| c != '.' && c != ' ' && c != '0') {
+            movie.notes.push((
+                frame_idx,
+                MovieNote::UnsupportedInput(format!("port {port}: {field}")),
+            ));
+        }
+    }
+    movie.frames.push(frame);
+    Ok(())
+}
+
+fn parse_pad(field: &str) -> Buttons {
+    field
+        .chars()
+        .zip(PAD_BUTTONS)
+        .fold(Buttons::new(), |buttons, (c, (_, set))| {
+            set(buttons, c != '.' && c != ' ')
+        })
+}
+
+fn format_pad(buttons: Buttons) -> String {
+    PAD_BUTTONS
+        .iter()
+        .zip(PAD_GETTERS)
+        .map(|((c, _), get)| if get(buttons) { *c } else { '.' })
+        .collect()
+}
+
+/// Formats frames as an FM2 movie with two gamepads.
+/// `header` entries are written after the required ones and may override them, e.g. `romFilename`.
+#[must_use]
+pub fn format(frames: &[MovieFrame], header: &[(String, String)]) -> String {
+    let mut text = String::new();
+    let defaults = [
+        ("version", "3"),
+        ("emuVersion", "22020"),
+        ("rerecordCount", "0"),
+        ("palFlag", "0"),
+        ("guid", "00000000-0000-0000-0000-000000000000"),
+        ("fourscore", "0"),
+        ("microphone", "0"),
+        ("port0", "1"),
+        ("port1", "1"),
+        ("port2", "0"),
+        ("FDS", "0"),
+        ("NewPPU", "0"),
+    ];
+    // Writing to a String can't fail
+    for (key, value) in defaults {
+        if !header.iter().any(|(k, _)| k == key) {
+            let _ = writeln!(text, "{key} {value}");
+        }
+    }
+    for (key, value) in header {
+        let _ = writeln!(text, "{key} {value}");
+    }
+    for frame in frames {
+        let commands = if frame.reset { CMD_SOFT_RESET } else { 0 };
+        let _ = writeln!(
+            text,
+            "|{commands}|{}|{}||",
+            format_pad(frame.buttons[0]),
+            format_pad(frame.buttons[1])
+        );
+    }
+    text
+}
+
+/// # Errors
+/// [`RetroRsError::MovieIOError`]: The file couldn't be read
+/// Others: See [`parse`]
+pub fn read_file(path: &Path) -> Result<ImportedMovie, RetroRsError> {
+    let text = std::fs::read_to_string(path).map_err(RetroRsError::MovieIOError)?;
+    parse(&text)
+}
+
+/// # Errors
+/// [`RetroRsError::MovieIOError`]: The file couldn't be written
+pub fn write_file(
+    path: &Path,
+    frames: &[MovieFrame],
+    header: &[(String, String)],
+) -> Result<(), RetroRsError> {
+    std::fs::write(path, format(frames, header)).map_err(RetroRsError::MovieIOError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "version 3\nemuVersion 22020\nromFilename smb\nport0 1\nport1 1\nport2 0\n\
+                          |0|........|........||\n\
+                          |1|...UT...|........||\n\
+                          |2|R......A|.L......||\n\
+                          |4|........|........||\n";
+
+    #[test]
+    fn parse_fm2() {
+        let movie = parse(SAMPLE).unwrap();
+        assert_eq!(movie.header_value("romFilename"), Some("smb"));
+        assert_eq!(movie.frames.len(), 4);
+        assert_eq!(
+            movie.frames[1].buttons[0],
+            Buttons::new().up(true).start(true)
+        );
+        assert!(movie.frames[1].reset);
+        assert_eq!(
+            movie.frames[2].buttons[0],
+            Buttons::new().right(true).a(true)
+        );
+        assert_eq!(movie.frames[2].buttons[1], Buttons::new().left(true));
+        assert_eq!(
+            movie.notes,
+            vec![
+                (1, MovieNote::SoftReset),
+                (2, MovieNote::HardReset),
+                (
+                    3,
+                    MovieNote::UnsupportedCommand("FDS disk insert".to_owned())
+                )
+            ]
+        );
+        assert!(!movie.is_exact());
+    }
+
+    #[test]
+    fn fm2_round_trip() {
+        let frames = parse(SAMPLE).unwrap().frames;
+        let written = format(&frames, &[("romFilename".to_owned(), "smb".to_owned())]);
+        let reread = parse(&written).unwrap();
+        assert_eq!(reread.frames, frames);
+        assert_eq!(reread.header_value("romFilename"), Some("smb"));
+    }
+}
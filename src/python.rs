@@ -0,0 +1,321 @@
+//! The `retro_rs` Python extension module, built with the `python` feature (e.g. by `maturin develop`).
+//!
+//! Framebuffer, audio and RAM accessors copy into fresh NumPy arrays. RAM is copied rather than
+//! viewed because cores may move or free it on a reset, a state load or a new memory map, which
+//! Python can't be stopped from reading past; write it back with the `write_*` methods. Errors
+//! are raised as `retro_rs.RetroRsError`.
+use crate::buttons::Buttons;
+use crate::emulator::{Emulator, MemoryRegion};
+use crate::error::RetroRsError;
+use crate::savestate::{Compression, Savestate};
+use numpy::{PyArray1, PyArray2, PyArray3, PyArrayMethods};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::path::PathBuf;
+
+create_exception!(retro_rs, PyRetroRsError, PyException);
+
+impl From<RetroRsError> for PyErr {
+    fn from(err: RetroRsError) -> Self {
+        PyRetroRsError::new_err(err.to_string())
+    }
+}
+
+// Button names in libretro joypad ID order
+const BUTTON_NAMES: [&str; 16] = [
+    "b", "y", "select", "start", "up", "down", "left", "right", "a", "x", "l1", "r1", "l2", "r2",
+    "l3", "r3",
+];
+
+fn button_id(name: &str) -> PyResult<u32> {
+    BUTTON_NAMES
+        .iter()
+        .position(|b| *b == name)
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or_else(|| pyo3::exceptions::PyValueError::new_err(format!("no button named {name}")))
+}
+
+/// Joypad buttons held on one port, e.g. `Buttons("a", "right")`.
+#[pyclass(name = "Buttons", module = "retro_rs", eq, frozen, from_py_object)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct PyButtons(Buttons);
+
+#[pymethods]
+impl PyButtons {
+    #[new]
+    #[pyo3(signature = (*pressed))]
+    fn new(pressed: Vec<String>) -> PyResult<Self> {
+        pressed
+            .iter()
+            .try_fold(PyButtons(Buttons::new()), |buttons, name| {
+                buttons.set(name, true)
+            })
+    }
+    #[staticmethod]
+    fn from_bits(bits: i16) -> Self {
+        PyButtons(Buttons::from(bits))
+    }
+    #[getter]
+    fn bits(&self) -> i16 {
+        i16::from(self.0)
+    }
+    fn get(&self, name: &str) -> PyResult<bool> {
+        Ok(self.0.get(button_id(name)?))
+    }
+    /// A copy with the named button pressed or released.
+    #[pyo3(signature = (name, pressed = true))]
+    fn set(&self, name: &str, pressed: bool) -> PyResult<Self> {
+        let bit = 1 << button_id(name)?;
+        let bits = i16::from(self.0);
+        Ok(PyButtons(Buttons::from(if pressed {
+            bits | bit
+        } else {
+            bits & !bit
+        })))
+    }
+    fn pressed(&self) -> Vec<&'static str> {
+        (0..16)
+            .filter(|id| self.0.get(*id))
+            .map(|id| BUTTON_NAMES[id as usize])
+            .collect()
+    }
+    fn __repr__(&self) -> String {
+        let pressed: Vec<String> = self.pressed().iter().map(|b| format!("{b:?}")).collect();
+        format!("Buttons({})", pressed.join(", "))
+    }
+}
+
+/// A region of the core's memory map, as from `Emulator.memory_regions()`.
+#[pyclass(name = "MemoryRegion", module = "retro_rs", frozen, get_all)]
+struct PyMemoryRegion {
+    flags: u64,
+    len: usize,
+    start: usize,
+    offset: usize,
+    name: String,
+    select: usize,
+    disconnect: usize,
+}
+
+impl From<MemoryRegion> for PyMemoryRegion {
+    fn from(mr: MemoryRegion) -> Self {
+        PyMemoryRegion {
+            flags: mr.flags,
+            len: mr.len,
+            start: mr.start,
+            offset: mr.offset,
+            name: mr.name,
+            select: mr.select,
+            disconnect: mr.disconnect,
+        }
+    }
+}
+
+// Lets the emulator into a closure run without the GIL; it never leaves the calling thread
+struct GilReleased<T>(T);
+unsafe impl<T> Send for GilReleased<T> {}
+
+/// A libretro core running a game; see the Rust `Emulator` for details.
+#[pyclass(name = "Emulator", module = "retro_rs", unsendable)]
+struct PyEmulator {
+    emu: Emulator,
+}
+
+// Copies `bytes` into `ram` from `offset`
+fn write_ram(ram: &mut [u8], offset: usize, bytes: &[u8]) -> Result<(), RetroRsError> {
+    let dest = offset
+        .checked_add(bytes.len())
+        .and_then(|end| ram.get_mut(offset..end))
+        .ok_or(RetroRsError::RAMCopySrcOutOfBoundsError)?;
+    dest.copy_from_slice(bytes);
+    Ok(())
+}
+
+#[pymethods]
+impl PyEmulator {
+    /// Loads a core and ROM, setting the given core options before the game loads.
+    #[new]
+    #[pyo3(signature = (core_path, rom_path, options = None, sram_path = None))]
+    fn new(
+        core_path: PathBuf,
+        rom_path: PathBuf,
+        options: Option<Vec<(String, String)>>,
+        sram_path: Option<PathBuf>,
+    ) -> PyResult<Self> {
+        let mut builder = Emulator::builder(&core_path, &rom_path);
+        for (key, value) in options.unwrap_or_default() {
+            builder = builder.option(&key, &value);
+        }
+        if let Some(path) = sram_path {
+            builder = builder.sram_path(&path);
+        }
+        Ok(PyEmulator {
+            emu: builder.build()?,
+        })
+    }
+    /// Runs one frame with the buttons held on ports 0 and 1, without holding the GIL.
+    #[pyo3(signature = (p0 = None, p1 = None))]
+    fn run(&mut self, py: Python<'_>, p0: Option<PyButtons>, p1: Option<PyButtons>) {
+        let inputs = [p0, p1].map(|b| b.map_or_else(Buttons::new, |b| b.0));
+        let emu = GilReleased(&mut self.emu);
+        py.detach(move || {
+            let emu = emu;
+            emu.0.run(inputs);
+        });
+    }
+    fn reset(&mut self) {
+        self.emu.reset();
+    }
+    #[getter]
+    fn frame_count(&self) -> u64 {
+        self.emu.frame_count()
+    }
+    #[getter]
+    fn rom_sha1(&self) -> &str {
+        self.emu.rom_sha1()
+    }
+    #[getter]
+    fn library_name(&self) -> String {
+        self.emu.get_library_name()
+    }
+    #[getter]
+    fn library_version(&self) -> String {
+        self.emu.get_library_version()
+    }
+    #[getter]
+    fn fps(&self) -> f64 {
+        self.emu.get_video_fps()
+    }
+    #[getter]
+    fn sample_rate(&self) -> f64 {
+        self.emu.get_audio_sample_rate()
+    }
+    #[getter]
+    fn aspect_ratio(&self) -> f32 {
+        self.emu.get_aspect_ratio()
+    }
+    /// `(width, height)` of the last frame.
+    #[getter]
+    fn framebuffer_size(&self) -> (usize, usize) {
+        self.emu.framebuffer_size()
+    }
+    /// A `(height, width, 3)` array of the last frame.
+    fn framebuffer_rgb888<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let (w, h) = self.emu.framebuffer_size();
+        let arr = PyArray3::zeros(py, [h, w, 3], false);
+        // Nothing else can see the array yet
+        self.emu
+            .copy_framebuffer_rgb888(unsafe { arr.as_slice_mut() }?)?;
+        Ok(arr)
+    }
+    /// A `(height, width, 4)` array of the last frame.
+    fn framebuffer_rgba8888<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let (w, h) = self.emu.framebuffer_size();
+        let arr = PyArray3::zeros(py, [h, w, 4], false);
+        self.emu
+            .copy_framebuffer_rgba8888(unsafe { arr.as_slice_mut() }?)?;
+        Ok(arr)
+    }
+    /// A `(height, width)` array of the last frame with one RGB332 byte per pixel.
+    fn framebuffer_rgb332<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<u8>>> {
+        let (w, h) = self.emu.framebuffer_size();
+        let arr = PyArray2::zeros(py, [h, w], false);
+        self.emu
+            .copy_framebuffer_rgb332(unsafe { arr.as_slice_mut() }?)?;
+        Ok(arr)
+    }
+    /// The last frame's interleaved stereo samples.
+    fn audio_sample<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<i16>> {
+        self.emu
+            .peek_audio_sample(|samples| PyArray1::from_slice(py, samples))
+    }
+    /// A copy of system RAM.
+    fn system_ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u8>> {
+        PyArray1::from_slice(py, self.emu.system_ram_ref())
+    }
+    /// Overwrites system RAM from `offset` with `data`.
+    fn write_system_ram(&mut self, offset: usize, data: &[u8]) -> PyResult<()> {
+        Ok(write_ram(self.emu.system_ram_mut(), offset, data)?)
+    }
+    /// A copy of video RAM.
+    fn video_ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u8>> {
+        PyArray1::from_slice(py, self.emu.video_ram_ref())
+    }
+    /// A copy of battery-backed save RAM.
+    fn save_ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u8>> {
+        PyArray1::from_slice(py, self.emu.save_ram())
+    }
+    /// Overwrites save RAM from `offset` with `data`.
+    fn write_save_ram(&mut self, offset: usize, data: &[u8]) -> PyResult<()> {
+        Ok(write_ram(self.emu.save_ram_mut(), offset, data)?)
+    }
+    fn memory_regions(&self) -> Vec<PyMemoryRegion> {
+        self.emu
+            .memory_regions()
+            .into_iter()
+            .map(PyMemoryRegion::from)
+            .collect()
+    }
+    /// A copy of the core's memory map from address `start` to the end of its region.
+    fn memory<'py>(&self, py: Python<'py>, start: usize) -> PyResult<Bound<'py, PyArray1<u8>>> {
+        Ok(PyArray1::from_slice(py, self.emu.memory_ref(start)?))
+    }
+    fn set_core_option(&mut self, key: &str, value: &str) -> PyResult<()> {
+        Ok(self.emu.set_core_option(key, value)?)
+    }
+    fn get_core_option(&self, key: &str) -> Option<String> {
+        self.emu.get_core_option(key)
+    }
+    /// The core's serialized state.
+    fn save<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let mut saved = true;
+        let bytes = PyBytes::new_with(py, self.emu.save_size(), |buf| {
+            saved = self.emu.save(buf);
+            Ok(())
+        })?;
+        if saved {
+            Ok(bytes)
+        } else {
+            Err(RetroRsError::StateSaveError.into())
+        }
+    }
+    fn load(&mut self, state: &[u8]) -> PyResult<()> {
+        if self.emu.load(state) {
+            Ok(())
+        } else {
+            Err(RetroRsError::StateLoadError.into())
+        }
+    }
+    /// Writes a savestate file; `compression` is `"none"`, `"deflate"` or `"zstd"`.
+    #[pyo3(signature = (path, thumbnail = false, compression = "none"))]
+    fn save_state(&self, path: PathBuf, thumbnail: bool, compression: &str) -> PyResult<()> {
+        let compression = match compression {
+            "none" => Compression::None,
+            "deflate" => Compression::Deflate,
+            "zstd" => Compression::Zstd,
+            other => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "unknown compression {other}"
+                )));
+            }
+        };
+        Savestate::capture(&self.emu, thumbnail)?.save_file(&path, compression)?;
+        Ok(())
+    }
+    /// Loads a savestate file after checking it was made with this core and ROM.
+    fn load_state(&mut self, path: PathBuf) -> PyResult<()> {
+        Savestate::load_file(&path)?.restore(&mut self.emu)?;
+        Ok(())
+    }
+}
+
+#[pymodule(name = "retro_rs")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyButtons>()?;
+    m.add_class::<PyMemoryRegion>()?;
+    m.add_class::<PyEmulator>()?;
+    m.add("RetroRsError", m.py().get_type::<PyRetroRsError>())?;
+    Ok(())
+}
@@ -0,0 +1,326 @@
+//! Headless capture of an emulator's video and audio for muxing offline, e.g.
+//! `ffmpeg -i run.y4m -i run.wav -c:v libx264 -c:a aac run.mp4`.
+use crate::diagnostics::{self, Diagnostic};
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// How [`Recorder`] writes video frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// A YUV4MPEG2 stream with 4:4:4 BT.601 frames, which carries its own size and frame rate.
+    Y4m,
+    /// Bare RGB888 frames of [`Recorder::video_size`], e.g. for
+    /// `ffmpeg -f rawvideo -pix_fmt rgb24 -s WxH -r FPS -i run.rgb`.
+    RawRgb24,
+}
+
+/// Appends each frame's video and audio to files; call [`Recorder::record`] after every
+/// [`Emulator::run`] and [`Recorder::finish`] when done.
+///
+/// Video is written at [`Emulator::get_video_fps`] onto a fixed canvas the size of
+/// [`Emulator::max_framebuffer_size`] (or the first frame, if the core doesn't say), with smaller
+/// frames centered on black so geometry changes mid-run don't break the stream. Audio is written
+/// as 16-bit stereo PCM WAV at [`Emulator::get_audio_sample_rate`]. Headers are written on the
+/// first recorded frame.
+pub struct Recorder {
+    video: Option<VideoStream>,
+    audio: Option<WavStream>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    /// A recorder which writes nothing until given a video or audio file.
+    #[must_use]
+    pub fn new() -> Self {
+        Recorder {
+            video: None,
+            audio: None,
+        }
+    }
+    /// Writes video frames to a new file at `path`.
+    /// # Errors
+    /// [`RetroRsError::RecorderIOError`]: The file couldn't be created
+    pub fn video(mut self, path: &Path, format: VideoFormat) -> Result<Self, RetroRsError> {
+        self.video = Some(VideoStream {
+            out: create(path)?,
+            format,
+            canvas: None,
+            skipped: 0,
+            frame: Vec::new(),
+            pixels: Vec::new(),
+        });
+        Ok(self)
+    }
+    /// Writes audio samples to a new WAV file at `path`.
+    /// # Errors
+    /// [`RetroRsError::RecorderIOError`]: The file couldn't be created
+    pub fn audio(mut self, path: &Path) -> Result<Self, RetroRsError> {
+        self.audio = Some(WavStream {
+            out: create(path)?,
+            started: false,
+            data_len: 0,
+        });
+        Ok(self)
+    }
+    /// `(width, height)` of the video canvas, or `None` until the core draws or reports its size.
+    #[must_use]
+    pub fn video_size(&self) -> Option<(usize, usize)> {
+        self.video.as_ref().and_then(|video| video.canvas)
+    }
+    /// Appends `emu`'s last frame and its audio. Frames recorded before the core has drawn
+    /// anything or said how big it draws are written as black once the canvas size is known, so
+    /// video and audio stay in step.
+    /// # Errors
+    /// [`RetroRsError::RecorderIOError`]: A file couldn't be written
+    /// Others: See [`Emulator::copy_framebuffer_rgb888`]
+    pub fn record(&mut self, emu: &Emulator) -> Result<(), RetroRsError> {
+        if let Some(video) = &mut self.video {
+            video.record(emu)?;
+        }
+        if let Some(audio) = &mut self.audio {
+            audio.record(emu).map_err(RetroRsError::RecorderIOError)?;
+        }
+        Ok(())
+    }
+    /// Flushes both files and fills in the WAV header's lengths.
+    /// # Errors
+    /// [`RetroRsError::RecorderIOError`]: A file couldn't be written
+    pub fn finish(mut self) -> Result<(), RetroRsError> {
+        self.close().map_err(RetroRsError::RecorderIOError)
+    }
+
+    fn close(&mut self) -> std::io::Result<()> {
+        if let Some(mut video) = self.video.take() {
+            video.out.flush()?;
+        }
+        if let Some(mut audio) = self.audio.take() {
+            audio.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            diagnostics::emit(&Diagnostic::RecorderFinishFailed(err.to_string()));
+        }
+    }
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, RetroRsError> {
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(RetroRsError::RecorderIOError)
+}
+
+struct VideoStream {
+    out: BufWriter<File>,
+    format: VideoFormat,
+    canvas: Option<(usize, usize)>,
+    // Frames recorded before the canvas size was known
+    skipped: usize,
+    // The core's frame as RGB888
+    frame: Vec<u8>,
+    // The canvas as Y4M planes
+    pixels: Vec<u8>,
+}
+
+impl VideoStream {
+    fn record(&mut self, emu: &Emulator) -> Result<(), RetroRsError> {
+        let (mut width, mut height) = emu.framebuffer_size();
+        self.frame.resize(width * height * 3, 0);
+        match emu.copy_framebuffer_rgb888(&mut self.frame) {
+            Ok(()) => {}
+            Err(RetroRsError::NoFramebufferError) => (width, height) = (0, 0),
+            Err(err) => return Err(err),
+        }
+        let (canvas_width, canvas_height) = match self.canvas {
+            Some(canvas) => canvas,
+            None => {
+                let (max_width, max_height) = emu.max_framebuffer_size();
+                let canvas = if max_width == 0 || max_height == 0 {
+                    (width, height)
+                } else {
+                    (max_width, max_height)
+                };
+                // Nothing to size the stream by until the core draws
+                if canvas.0 == 0 || canvas.1 == 0 {
+                    self.skipped += 1;
+                    return Ok(());
+                }
+                if self.format == VideoFormat::Y4m {
+                    write_y4m_header(&mut self.out, canvas, emu.get_video_fps())
+                        .map_err(RetroRsError::RecorderIOError)?;
+                }
+                self.canvas = Some(canvas);
+                let black = vec![0; canvas.0 * canvas.1 * 3];
+                for _ in 0..std::mem::take(&mut self.skipped) {
+                    self.write_frame(&black)
+                        .map_err(RetroRsError::RecorderIOError)?;
+                }
+                canvas
+            }
+        };
+        let canvas = place(
+            &self.frame[..width * height * 3],
+            (width, height),
+            (canvas_width, canvas_height),
+        );
+        self.write_frame(&canvas)
+            .map_err(RetroRsError::RecorderIOError)
+    }
+    // Writes an RGB888 frame the size of the canvas
+    fn write_frame(&mut self, canvas: &[u8]) -> std::io::Result<()> {
+        match self.format {
+            VideoFormat::Y4m => {
+                rgb888_to_yuv444(canvas, &mut self.pixels);
+                self.out.write_all(b"FRAME\n")?;
+                self.out.write_all(&self.pixels)
+            }
+            VideoFormat::RawRgb24 => self.out.write_all(canvas),
+        }
+    }
+}
+
+fn write_y4m_header(
+    out: &mut impl Write,
+    (width, height): (usize, usize),
+    fps: f64,
+) -> std::io::Result<()> {
+    // Frame rates like the NES's 60.0988 need a fractional rate
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let fps_milli = ((fps * 1000.0).round() as u64).max(1);
+    writeln!(
+        out,
+        "YUV4MPEG2 W{width} H{height} F{fps_milli}:1000 Ip A1:1 C444"
+    )
+}
+
+// Centers an RGB888 frame on a black canvas, cropping whatever doesn't fit
+pub(crate) fn place(
+    frame: &[u8],
+    (width, height): (usize, usize),
+    (canvas_width, canvas_height): (usize, usize),
+) -> Vec<u8> {
+    let mut canvas = vec![0; canvas_width * canvas_height * 3];
+    let copy_width = width.min(canvas_width);
+    let copy_height = height.min(canvas_height);
+    let (left, top) = (
+        (canvas_width - copy_width) / 2,
+        (canvas_height - copy_height) / 2,
+    );
+    for row in 0..copy_height {
+        let src = row * width * 3;
+        let dst = ((top + row) * canvas_width + left) * 3;
+        canvas[dst..dst + copy_width * 3].copy_from_slice(&frame[src..src + copy_width * 3]);
+    }
+    canvas
+}
+
+// Studio-range BT.601, written as Y, then Cb, then Cr planes
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn rgb888_to_yuv444(rgb: &[u8], out: &mut Vec<u8>) {
+    let pixels = rgb.len() / 3;
+    out.clear();
+    out.resize(pixels * 3, 0);
+    let (y_plane, chroma) = out.split_at_mut(pixels);
+    let (cb_plane, cr_plane) = chroma.split_at_mut(pixels);
+    for (i, px) in rgb.chunks_exact(3).enumerate() {
+        let (r, g, b) = (i32::from(px[0]), i32::from(px[1]), i32::from(px[2]));
+        y_plane[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        cb_plane[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        cr_plane[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+}
+
+struct WavStream {
+    out: BufWriter<File>,
+    started: bool,
+    data_len: u64,
+}
+
+const WAV_HEADER_LEN: u64 = 44;
+
+impl WavStream {
+    fn record(&mut self, emu: &Emulator) -> std::io::Result<()> {
+        if !self.started {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let rate = emu.get_audio_sample_rate().round() as u32;
+            write_wav_header(&mut self.out, rate, 0)?;
+            self.started = true;
+        }
+        emu.peek_audio_sample(|samples| {
+            for sample in samples {
+                self.out.write_all(&sample.to_le_bytes())?;
+            }
+            self.data_len += samples.len() as u64 * 2;
+            Ok(())
+        })
+    }
+    fn finish(&mut self) -> std::io::Result<()> {
+        if self.started {
+            // WAV can't describe more than 4GiB of samples; players read to the end of the file anyway
+            let data_len = u32::try_from(self.data_len).unwrap_or(u32::MAX);
+            self.out.seek(SeekFrom::Start(4))?;
+            self.out
+                .write_all(&data_len.saturating_add(36).to_le_bytes())?;
+            self.out.seek(SeekFrom::Start(WAV_HEADER_LEN - 4))?;
+            self.out.write_all(&data_len.to_le_bytes())?;
+            self.out.seek(SeekFrom::End(0))?;
+        }
+        self.out.flush()
+    }
+}
+
+fn write_wav_header(out: &mut impl Write, sample_rate: u32, data_len: u32) -> std::io::Result<()> {
+    const CHANNELS: u16 = 2;
+    const BITS: u16 = 16;
+    let block_align = CHANNELS * BITS / 8;
+    out.write_all(b"RIFF")?;
+    out.write_all(&data_len.saturating_add(36).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16_u32.to_le_bytes())?;
+    // PCM
+    out.write_all(&1_u16.to_le_bytes())?;
+    out.write_all(&CHANNELS.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&BITS.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_and_placement() {
+        let mut wav = Vec::new();
+        write_wav_header(&mut wav, 44100, 8).unwrap();
+        assert_eq!(wav.len() as u64, WAV_HEADER_LEN);
+        assert_eq!(&wav[4..8], &44_u32.to_le_bytes());
+        assert_eq!(&wav[28..32], &(44100_u32 * 4).to_le_bytes());
+
+        let mut y4m = Vec::new();
+        write_y4m_header(&mut y4m, (256, 240), 60.0988).unwrap();
+        assert_eq!(y4m, b"YUV4MPEG2 W256 H240 F60099:1000 Ip A1:1 C444\n");
+
+        // A white 1x1 frame in the middle of a 3x1 canvas
+        let canvas = place(&[255, 255, 255], (1, 1), (3, 1));
+        assert_eq!(canvas, [0, 0, 0, 255, 255, 255, 0, 0, 0]);
+        let mut yuv = Vec::new();
+        rgb888_to_yuv444(&canvas, &mut yuv);
+        assert_eq!(yuv, [16, 235, 16, 128, 128, 128, 128, 128, 128]);
+    }
+}
@@ -0,0 +1,597 @@
+//! Emulators hosted in worker processes, for running many cores in parallel or isolating crashes.
+//!
+//! A worker is any executable which calls [`run_worker`]; this crate builds one as the
+//! `retro-rs-worker` binary. [`RemoteEmulator`] spawns a worker and exchanges messages with it
+//! over the worker's stdin and stdout. Each message is a `u32` little-endian length followed by
+//! that many bytes: a request is an opcode and its arguments, a response is a status byte
+//! (0 for success, 1 for an error message, or a code for one of the errors [`RemoteEmulator`]
+//! reports as itself) and the request's results.
+use crate::buttons::Buttons;
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use crate::format::{ByteReader, put_string};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitCode, Stdio};
+
+const OP_CREATE: u8 = 0;
+const OP_RUN: u8 = 1;
+const OP_RESET: u8 = 2;
+const OP_SAVE: u8 = 3;
+const OP_LOAD: u8 = 4;
+const OP_FRAMEBUFFER_SIZE: u8 = 5;
+const OP_FRAMEBUFFER_RGBA8888: u8 = 6;
+const OP_FRAMEBUFFER_RGB888: u8 = 7;
+const OP_RAM: u8 = 8;
+const OP_WRITE_SYSTEM_RAM: u8 = 9;
+const OP_MEMORY: u8 = 10;
+const OP_AUDIO_SAMPLE: u8 = 11;
+const OP_INFO: u8 = 12;
+const OP_SET_CORE_OPTION: u8 = 13;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+const STATUS_STATE_SAVE: u8 = 2;
+const STATUS_STATE_LOAD: u8 = 3;
+const STATUS_NO_FRAMEBUFFER: u8 = 4;
+const STATUS_RAM_OUT_OF_BOUNDS: u8 = 5;
+
+const RAM_SYSTEM: u8 = 0;
+const RAM_SAVE: u8 = 1;
+const RAM_VIDEO: u8 = 2;
+
+/// Fixed facts about a worker's core, fetched once when it starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteCoreInfo {
+    pub library_name: String,
+    pub library_version: String,
+    pub fps: f64,
+    pub sample_rate: f64,
+    pub aspect_ratio: f32,
+}
+
+/// An [`Emulator`] running in a worker process.
+///
+/// Unlike [`Emulator`] this is [`Send`] and [`Sync`], and any number can run in one process.
+/// Every call is a round trip to the worker, so each method returns a [`Result`]; if the worker
+/// exits, e.g. because its core crashed, calls fail with [`RetroRsError::RemoteWorkerExitedError`].
+/// The worker is killed when this is dropped.
+pub struct RemoteEmulator {
+    child: Child,
+    to_worker: BufWriter<ChildStdin>,
+    from_worker: BufReader<ChildStdout>,
+    info: RemoteCoreInfo,
+    frame_count: u64,
+}
+
+impl RemoteEmulator {
+    /// Starts the worker executable at `worker` and loads a core and ROM in it.
+    /// # Errors
+    /// See [`RemoteEmulator::spawn_command`]
+    pub fn spawn(worker: &Path, core_path: &Path, rom_path: &Path) -> Result<Self, RetroRsError> {
+        Self::spawn_command(Command::new(worker), core_path, rom_path, &[])
+    }
+    /// Starts a worker with `command`, which may set arguments or environment variables, and loads
+    /// a core and ROM in it with the given core options set before the game loads.
+    /// The command's stdin and stdout are replaced by the message pipes; stderr is inherited.
+    /// # Errors
+    /// [`RetroRsError::RemoteIOError`]: The worker couldn't be started or talked to
+    /// [`RetroRsError::RemoteWorkerExitedError`]: The worker exited while loading the core
+    /// [`RetroRsError::RemoteCoreError`]: The worker couldn't create the emulator, e.g. the core failed to load
+    pub fn spawn_command(
+        mut command: Command,
+        core_path: &Path,
+        rom_path: &Path,
+        options: &[(String, String)],
+    ) -> Result<Self, RetroRsError> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(RetroRsError::RemoteIOError)?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            let _ = child.kill();
+            return Err(RetroRsError::RemoteIOError(io::Error::other(
+                "worker has no stdin or stdout",
+            )));
+        };
+        let mut emu = RemoteEmulator {
+            child,
+            to_worker: BufWriter::new(stdin),
+            from_worker: BufReader::new(stdout),
+            info: RemoteCoreInfo {
+                library_name: String::new(),
+                library_version: String::new(),
+                fps: 0.0,
+                sample_rate: 0.0,
+                aspect_ratio: 0.0,
+            },
+            frame_count: 0,
+        };
+        let mut msg = Message::op(OP_CREATE);
+        msg.put_path(core_path)?;
+        msg.put_path(rom_path)?;
+        msg.put_u32(u32::try_from(options.len())?);
+        for (key, value) in options {
+            msg.put_str(key)?;
+            msg.put_str(value)?;
+        }
+        emu.call(&msg)?;
+        let info = emu.call(&Message::op(OP_INFO))?;
+        let mut info = ByteReader::new(&info, protocol_error);
+        emu.info = RemoteCoreInfo {
+            library_name: info.take_string()?,
+            library_version: info.take_string()?,
+            fps: f64::from_le_bytes(info.take_array()?),
+            sample_rate: f64::from_le_bytes(info.take_array()?),
+            aspect_ratio: f32::from_le_bytes(info.take_array()?),
+        };
+        Ok(emu)
+    }
+
+    /// # Errors
+    /// [`RetroRsError::RemoteIOError`], [`RetroRsError::RemoteWorkerExitedError`]: The worker is unreachable
+    pub fn run(&mut self, inputs: [Buttons; 2]) -> Result<(), RetroRsError> {
+        let mut msg = Message::op(OP_RUN);
+        for buttons in inputs {
+            msg.bytes
+                .extend_from_slice(&i16::from(buttons).to_le_bytes());
+        }
+        self.call(&msg)?;
+        self.frame_count += 1;
+        Ok(())
+    }
+    /// # Errors
+    /// See [`RemoteEmulator::run`]
+    pub fn reset(&mut self) -> Result<(), RetroRsError> {
+        self.call(&Message::op(OP_RESET)).map(drop)
+    }
+    /// How many times [`RemoteEmulator::run`] has succeeded.
+    #[must_use]
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+    #[must_use]
+    pub fn info(&self) -> &RemoteCoreInfo {
+        &self.info
+    }
+    /// The core's serialized state, as from [`Emulator::save`].
+    /// # Errors
+    /// [`RetroRsError::StateSaveError`]: The core couldn't save its state
+    /// Others: See [`RemoteEmulator::run`]
+    pub fn save(&mut self) -> Result<Vec<u8>, RetroRsError> {
+        self.call(&Message::op(OP_SAVE))
+    }
+    /// # Errors
+    /// [`RetroRsError::StateLoadError`]: The core couldn't load the state
+    /// Others: See [`RemoteEmulator::run`]
+    pub fn load(&mut self, state: &[u8]) -> Result<(), RetroRsError> {
+        let mut msg = Message::op(OP_LOAD);
+        msg.bytes.extend_from_slice(state);
+        self.call(&msg).map(drop)
+    }
+    /// # Errors
+    /// See [`RemoteEmulator::run`]
+    pub fn framebuffer_size(&mut self) -> Result<(usize, usize), RetroRsError> {
+        let reply = self.call(&Message::op(OP_FRAMEBUFFER_SIZE))?;
+        let mut reply = ByteReader::new(&reply, protocol_error);
+        let w = u64::from_le_bytes(reply.take_array()?);
+        let h = u64::from_le_bytes(reply.take_array()?);
+        Ok((usize::try_from(w)?, usize::try_from(h)?))
+    }
+    /// Copies the framebuffer into `slice` as in [`Emulator::copy_framebuffer_rgba8888`].
+    /// # Errors
+    /// [`RetroRsError::NoFramebufferError`]: The emulator has not created a framebuffer
+    /// [`RetroRsError::RAMCopyDestTooSmallError`]: `slice` is smaller than the framebuffer
+    /// Others: See [`RemoteEmulator::run`]
+    pub fn copy_framebuffer_rgba8888(&mut self, slice: &mut [u8]) -> Result<(), RetroRsError> {
+        self.copy_framebuffer(OP_FRAMEBUFFER_RGBA8888, slice)
+    }
+    /// Copies the framebuffer into `slice` as in [`Emulator::copy_framebuffer_rgb888`].
+    /// # Errors
+    /// See [`RemoteEmulator::copy_framebuffer_rgba8888`]
+    pub fn copy_framebuffer_rgb888(&mut self, slice: &mut [u8]) -> Result<(), RetroRsError> {
+        self.copy_framebuffer(OP_FRAMEBUFFER_RGB888, slice)
+    }
+    fn copy_framebuffer(&mut self, op: u8, slice: &mut [u8]) -> Result<(), RetroRsError> {
+        let reply = self.call(&Message::op(op))?;
+        let dest = slice
+            .get_mut(..reply.len())
+            .ok_or(RetroRsError::RAMCopyDestTooSmallError)?;
+        dest.copy_from_slice(&reply);
+        Ok(())
+    }
+    /// A copy of [`Emulator::system_ram_ref`].
+    /// # Errors
+    /// See [`RemoteEmulator::run`]
+    pub fn system_ram(&mut self) -> Result<Vec<u8>, RetroRsError> {
+        self.ram(RAM_SYSTEM)
+    }
+    /// A copy of [`Emulator::save_ram`].
+    /// # Errors
+    /// See [`RemoteEmulator::run`]
+    pub fn save_ram(&mut self) -> Result<Vec<u8>, RetroRsError> {
+        self.ram(RAM_SAVE)
+    }
+    /// A copy of [`Emulator::video_ram_ref`].
+    /// # Errors
+    /// See [`RemoteEmulator::run`]
+    pub fn video_ram(&mut self) -> Result<Vec<u8>, RetroRsError> {
+        self.ram(RAM_VIDEO)
+    }
+    fn ram(&mut self, which: u8) -> Result<Vec<u8>, RetroRsError> {
+        let mut msg = Message::op(OP_RAM);
+        msg.bytes.push(which);
+        self.call(&msg)
+    }
+    /// Overwrites system RAM from `offset`, as through [`Emulator::system_ram_mut`].
+    /// # Errors
+    /// [`RetroRsError::RAMCopySrcOutOfBoundsError`]: The bytes don't fit in system RAM
+    /// Others: See [`RemoteEmulator::run`]
+    pub fn write_system_ram(&mut self, offset: usize, bytes: &[u8]) -> Result<(), RetroRsError> {
+        let mut msg = Message::op(OP_WRITE_SYSTEM_RAM);
+        msg.put_u64(offset as u64);
+        msg.bytes.extend_from_slice(bytes);
+        self.call(&msg).map(drop)
+    }
+    /// Up to `len` bytes from the core's memory map starting at `start`, as from [`Emulator::memory_ref`].
+    /// # Errors
+    /// [`RetroRsError::RemoteCoreError`]: The address isn't mapped
+    /// Others: See [`RemoteEmulator::run`]
+    pub fn memory(&mut self, start: usize, len: usize) -> Result<Vec<u8>, RetroRsError> {
+        let mut msg = Message::op(OP_MEMORY);
+        msg.put_u64(start as u64);
+        msg.put_u64(len as u64);
+        self.call(&msg)
+    }
+    /// The audio samples produced by the last frame, as from [`Emulator::peek_audio_sample`].
+    /// # Errors
+    /// See [`RemoteEmulator::run`]
+    pub fn audio_sample(&mut self) -> Result<Vec<i16>, RetroRsError> {
+        let reply = self.call(&Message::op(OP_AUDIO_SAMPLE))?;
+        Ok(reply
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect())
+    }
+    /// # Errors
+    /// [`RetroRsError::RemoteCoreError`]: The core rejected the option, see [`Emulator::set_core_option`]
+    /// Others: See [`RemoteEmulator::run`]
+    pub fn set_core_option(&mut self, key: &str, value: &str) -> Result<(), RetroRsError> {
+        let mut msg = Message::op(OP_SET_CORE_OPTION);
+        msg.put_str(key)?;
+        msg.put_str(value)?;
+        self.call(&msg).map(drop)
+    }
+    /// Whether the worker process is still running.
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    // The reply's results, after its status byte
+    fn call(&mut self, msg: &Message) -> Result<Vec<u8>, RetroRsError> {
+        let sent = write_message(&mut self.to_worker, &msg.bytes);
+        let reply = sent.and_then(|()| read_message(&mut self.from_worker));
+        match reply {
+            Ok(Some(reply)) => read_reply(reply),
+            Ok(None) => Err(self.exited()),
+            Err(err) if is_disconnect(&err) => Err(self.exited()),
+            Err(err) => Err(RetroRsError::RemoteIOError(err)),
+        }
+    }
+    fn exited(&mut self) -> RetroRsError {
+        match self.child.wait() {
+            Ok(status) => RetroRsError::RemoteWorkerExitedError(status),
+            Err(err) => RetroRsError::RemoteIOError(err),
+        }
+    }
+}
+
+impl Drop for RemoteEmulator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// The errors RemoteEmulator reports as themselves have their own status, so only they are mapped back
+fn write_reply(result: Result<Vec<u8>, RetroRsError>) -> Vec<u8> {
+    let status = match &result {
+        Ok(_) => STATUS_OK,
+        Err(RetroRsError::StateSaveError) => STATUS_STATE_SAVE,
+        Err(RetroRsError::StateLoadError) => STATUS_STATE_LOAD,
+        Err(RetroRsError::NoFramebufferError) => STATUS_NO_FRAMEBUFFER,
+        Err(RetroRsError::RAMCopySrcOutOfBoundsError) => STATUS_RAM_OUT_OF_BOUNDS,
+        Err(_) => STATUS_ERROR,
+    };
+    let mut reply = Message::op(status);
+    match result {
+        Ok(results) => reply.bytes.extend_from_slice(&results),
+        Err(err) if status == STATUS_ERROR => {
+            // An error message too long for a u32 length can't happen in practice
+            let _ = reply.put_str(&err.to_string());
+        }
+        Err(_) => {}
+    }
+    reply.bytes
+}
+
+// The results of a successful reply
+fn read_reply(mut reply: Vec<u8>) -> Result<Vec<u8>, RetroRsError> {
+    let mut fields = ByteReader::new(&reply, protocol_error);
+    match fields.take_array::<1>()?[0] {
+        STATUS_OK => {
+            reply.remove(0);
+            Ok(reply)
+        }
+        STATUS_ERROR => Err(RetroRsError::RemoteCoreError(fields.take_string()?)),
+        STATUS_STATE_SAVE => Err(RetroRsError::StateSaveError),
+        STATUS_STATE_LOAD => Err(RetroRsError::StateLoadError),
+        STATUS_NO_FRAMEBUFFER => Err(RetroRsError::NoFramebufferError),
+        STATUS_RAM_OUT_OF_BOUNDS => Err(RetroRsError::RAMCopySrcOutOfBoundsError),
+        other => Err(fields.error(format!("unknown status {other}"))),
+    }
+}
+
+fn protocol_error(why: String) -> RetroRsError {
+    RetroRsError::RemoteIOError(io::Error::new(io::ErrorKind::InvalidData, why))
+}
+
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+    )
+}
+
+// A request or response body being built
+struct Message {
+    bytes: Vec<u8>,
+}
+
+impl Message {
+    fn op(op: u8) -> Self {
+        Message { bytes: vec![op] }
+    }
+    fn put_u32(&mut self, n: u32) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+    fn put_u64(&mut self, n: u64) {
+        self.bytes.extend_from_slice(&n.to_le_bytes());
+    }
+    fn put_str(&mut self, s: &str) -> Result<(), RetroRsError> {
+        put_string(&mut self.bytes, s)
+    }
+    fn put_path(&mut self, path: &Path) -> Result<(), RetroRsError> {
+        let s = path
+            .to_str()
+            .ok_or_else(|| RetroRsError::InvalidPathError(path.to_path_buf()))?;
+        self.put_str(s)
+    }
+}
+
+fn write_message(w: &mut impl Write, body: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(body.len()).map_err(io::Error::other)?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(body)?;
+    w.flush()
+}
+
+// None at a clean end of stream between messages
+fn read_message(r: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let mut body = vec![0; u32::from_le_bytes(len) as usize];
+    r.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// The main loop of a worker process for [`RemoteEmulator`]: serves requests on stdin and stdout until stdin closes.
+///
+/// Anything the core prints to stdout is redirected to stderr on Unix so it can't corrupt the
+/// message stream. Call this from a binary's `main` and return its exit code.
+#[must_use]
+pub fn run_worker() -> ExitCode {
+    let (input, output) = match worker_pipes() {
+        Ok(pipes) => pipes,
+        Err(err) => {
+            eprintln!("retro-rs worker: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut input = BufReader::new(input);
+    let mut output = BufWriter::new(output);
+    let mut emu: Option<Emulator> = None;
+    loop {
+        let body = match read_message(&mut input) {
+            Ok(Some(body)) => body,
+            Ok(None) => return ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("retro-rs worker: {err}");
+                return ExitCode::FAILURE;
+            }
+        };
+        let reply = write_reply(handle_request(&mut emu, &body));
+        if write_message(&mut output, &reply).is_err() {
+            return ExitCode::FAILURE;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn worker_pipes() -> io::Result<(std::fs::File, std::fs::File)> {
+    use std::os::fd::FromRawFd;
+    // Keep the real stdout for messages and point fd 1 at stderr for the core's own output
+    let out_fd = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if out_fd < 0 || unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let output = unsafe { std::fs::File::from_raw_fd(out_fd) };
+    let in_fd = unsafe { libc::dup(libc::STDIN_FILENO) };
+    if in_fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let input = unsafe { std::fs::File::from_raw_fd(in_fd) };
+    Ok((input, output))
+}
+
+#[cfg(not(unix))]
+fn worker_pipes() -> io::Result<(io::Stdin, io::Stdout)> {
+    Ok((io::stdin(), io::stdout()))
+}
+
+fn handle_request(emu: &mut Option<Emulator>, body: &[u8]) -> Result<Vec<u8>, RetroRsError> {
+    let mut msg = ByteReader::new(body, protocol_error);
+    let op = msg.take_array::<1>()?[0];
+    if op == OP_CREATE {
+        let core = PathBuf::from(msg.take_string()?);
+        let rom = PathBuf::from(msg.take_string()?);
+        let mut builder = Emulator::builder(&core, &rom);
+        let count = u32::from_le_bytes(msg.take_array()?);
+        for _ in 0..count {
+            let key = msg.take_string()?;
+            let value = msg.take_string()?;
+            builder = builder.option(&key, &value);
+        }
+        // Drop any previous emulator first so its core library can be reused
+        *emu = None;
+        *emu = Some(builder.build()?);
+        return Ok(Vec::new());
+    }
+    let Some(emu) = emu.as_mut() else {
+        return Err(RetroRsError::RemoteCoreError(
+            "no emulator has been created".to_owned(),
+        ));
+    };
+    let mut reply = Message { bytes: Vec::new() };
+    match op {
+        OP_RUN => {
+            let p0 = i16::from_le_bytes(msg.take_array()?);
+            let p1 = i16::from_le_bytes(msg.take_array()?);
+            emu.run([Buttons::from(p0), Buttons::from(p1)]);
+        }
+        OP_RESET => emu.reset(),
+        OP_SAVE => {
+            reply.bytes = vec![0; emu.save_size()];
+            if !emu.save(&mut reply.bytes) {
+                return Err(RetroRsError::StateSaveError);
+            }
+        }
+        OP_LOAD => {
+            if !emu.load(msg.rest()) {
+                return Err(RetroRsError::StateLoadError);
+            }
+        }
+        OP_FRAMEBUFFER_SIZE => {
+            let (w, h) = emu.framebuffer_size();
+            reply.put_u64(w as u64);
+            reply.put_u64(h as u64);
+        }
+        OP_FRAMEBUFFER_RGBA8888 | OP_FRAMEBUFFER_RGB888 => {
+            let (w, h) = emu.framebuffer_size();
+            if op == OP_FRAMEBUFFER_RGBA8888 {
+                reply.bytes = vec![0; w * h * 4];
+                emu.copy_framebuffer_rgba8888(&mut reply.bytes)?;
+            } else {
+                reply.bytes = vec![0; w * h * 3];
+                emu.copy_framebuffer_rgb888(&mut reply.bytes)?;
+            }
+        }
+        OP_RAM => {
+            reply.bytes = match msg.take_array::<1>()?[0] {
+                RAM_SYSTEM => emu.system_ram_ref().to_vec(),
+                RAM_SAVE => emu.save_ram().to_vec(),
+                RAM_VIDEO => emu.video_ram_ref().to_vec(),
+                other => {
+                    return Err(RetroRsError::RemoteCoreError(format!(
+                        "unknown RAM region {other}"
+                    )));
+                }
+            };
+        }
+        OP_WRITE_SYSTEM_RAM => {
+            let offset = msg.take_usize()?;
+            let bytes = msg.rest();
+            let ram = emu.system_ram_mut();
+            let dest = offset
+                .checked_add(bytes.len())
+                .and_then(|end| ram.get_mut(offset..end))
+                .ok_or(RetroRsError::RAMCopySrcOutOfBoundsError)?;
+            dest.copy_from_slice(bytes);
+        }
+        OP_MEMORY => {
+            let start = msg.take_usize()?;
+            let len = msg.take_usize()?;
+            let mem = emu.memory_ref(start)?;
+            reply.bytes = mem[..len.min(mem.len())].to_vec();
+        }
+        OP_AUDIO_SAMPLE => {
+            reply.bytes = emu.peek_audio_sample(|samples| {
+                samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+            });
+        }
+        OP_INFO => {
+            reply.put_str(&emu.get_library_name())?;
+            reply.put_str(&emu.get_library_version())?;
+            reply
+                .bytes
+                .extend_from_slice(&emu.get_video_fps().to_le_bytes());
+            reply
+                .bytes
+                .extend_from_slice(&emu.get_audio_sample_rate().to_le_bytes());
+            reply
+                .bytes
+                .extend_from_slice(&emu.get_aspect_ratio().to_le_bytes());
+        }
+        OP_SET_CORE_OPTION => {
+            let key = msg.take_string()?;
+            let value = msg.take_string()?;
+            emu.set_core_option(&key, &value)?;
+        }
+        other => {
+            return Err(RetroRsError::RemoteCoreError(format!(
+                "unknown request {other}"
+            )));
+        }
+    }
+    Ok(reply.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_framing() {
+        let mut msg = Message::op(OP_SET_CORE_OPTION);
+        msg.put_str("fceumm_region").unwrap();
+        msg.put_str("NTSC").unwrap();
+        let mut pipe = Vec::new();
+        write_message(&mut pipe, &msg.bytes).unwrap();
+        write_message(&mut pipe, &[]).unwrap();
+        let mut r = pipe.as_slice();
+        let body = read_message(&mut r).unwrap().unwrap();
+        let mut read = ByteReader::new(&body, protocol_error);
+        assert_eq!(read.take_array::<1>().unwrap(), [OP_SET_CORE_OPTION]);
+        assert_eq!(read.take_string().unwrap(), "fceumm_region");
+        assert_eq!(read.take_string().unwrap(), "NTSC");
+        assert!(read.take_array::<1>().is_err());
+        assert_eq!(read_message(&mut r).unwrap(), Some(Vec::new()));
+        assert_eq!(read_message(&mut r).unwrap(), None);
+
+        assert_eq!(read_reply(write_reply(Ok(vec![7]))).unwrap(), [7]);
+        assert!(matches!(
+            read_reply(write_reply(Err(RetroRsError::StateLoadError))),
+            Err(RetroRsError::StateLoadError)
+        ));
+        // Other errors keep the worker's message
+        let err = read_reply(write_reply(Err(RetroRsError::ContentRejectedError))).unwrap_err();
+        assert!(
+            matches!(err, RetroRsError::RemoteCoreError(msg) if msg == RetroRsError::ContentRejectedError.to_string())
+        );
+    }
+}
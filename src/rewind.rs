@@ -0,0 +1,215 @@
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use std::collections::VecDeque;
+
+// Unchanged runs shorter than this are folded into the surrounding changed bytes,
+// since a new chunk header costs 8 bytes
+const MIN_GAP: usize = 8;
+
+/// A bounded history of savestates for stepping an [`Emulator`] backwards.
+///
+/// The newest state is kept whole and each older one is stored as a delta against the state
+/// after it, so consecutive states which differ in a few bytes cost little more than those bytes.
+/// When the history outgrows its memory budget, the oldest states are dropped first.
+pub struct RewindBuffer {
+    interval: u64,
+    budget: usize,
+    newest: Option<(u64, Vec<u8>)>,
+    // Oldest first; each delta rebuilds its state from the state after it
+    deltas: VecDeque<(u64, Vec<u8>)>,
+    delta_bytes: usize,
+}
+
+impl RewindBuffer {
+    /// A buffer which captures a state every `interval` frames and holds about `budget` bytes of history.
+    /// The newest state is always kept, even if it alone is larger than the budget.
+    /// # Panics
+    /// If `interval` is 0
+    #[must_use]
+    pub fn new(interval: u64, budget: usize) -> Self {
+        assert!(interval > 0, "rewind interval must be at least one frame");
+        RewindBuffer {
+            interval,
+            budget,
+            newest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+    /// Captures `emu`'s state if at least the interval has passed since the newest captured state.
+    /// States captured after `emu`'s current frame, e.g. before a rewind, are discarded first.
+    /// Returns whether a state was captured.
+    /// # Errors
+    /// [`RetroRsError::StateSaveError`]: The core couldn't save its state
+    pub fn capture(&mut self, emu: &Emulator) -> Result<bool, RetroRsError> {
+        let frame = emu.frame_count();
+        self.truncate_after(frame);
+        if let Some((newest_frame, _)) = &self.newest
+            && frame < newest_frame + self.interval
+        {
+            return Ok(false);
+        }
+        self.capture_now(emu)?;
+        Ok(true)
+    }
+    /// Captures `emu`'s state regardless of the interval, replacing any state captured at or after its current frame.
+    /// # Errors
+    /// [`RetroRsError::StateSaveError`]: The core couldn't save its state
+    pub fn capture_now(&mut self, emu: &Emulator) -> Result<(), RetroRsError> {
+        let frame = emu.frame_count();
+        self.truncate_after(frame);
+        if self.newest.as_ref().is_some_and(|(f, _)| *f == frame) {
+            self.step_back();
+        }
+        let mut state = vec![0; emu.save_size()];
+        if !emu.save(&mut state) {
+            return Err(RetroRsError::StateSaveError);
+        }
+        if let Some((prev_frame, prev_state)) = self.newest.take() {
+            let delta = encode_delta(&prev_state, &state);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back((prev_frame, delta));
+        }
+        self.newest = Some((frame, state));
+        while self.memory_used() > self.budget
+            && let Some((_, delta)) = self.deltas.pop_front()
+        {
+            self.delta_bytes -= delta.len();
+        }
+        Ok(())
+    }
+    /// Loads the newest captured state from at least `frames` frames before `emu`'s current frame,
+    /// or the oldest captured state if none is that old, and discards the states after it.
+    /// `emu`'s frame count is set to the restored state's and returned.
+    /// # Errors
+    /// [`RetroRsError::RewindEmptyError`]: No state has been captured
+    /// [`RetroRsError::StateLoadError`]: The core couldn't load the state
+    pub fn rewind(&mut self, emu: &mut Emulator, frames: u64) -> Result<u64, RetroRsError> {
+        let target = emu.frame_count().saturating_sub(frames);
+        while self.newest.as_ref().is_some_and(|(f, _)| *f > target) && !self.deltas.is_empty() {
+            self.step_back();
+        }
+        let (frame, state) = self.newest.as_ref().ok_or(RetroRsError::RewindEmptyError)?;
+        if !emu.load(state) {
+            return Err(RetroRsError::StateLoadError);
+        }
+        emu.set_frame_count(*frame);
+        Ok(*frame)
+    }
+    /// The frame counts of the captured states, oldest first.
+    pub fn frames(&self) -> impl Iterator<Item = u64> + '_ {
+        self.deltas
+            .iter()
+            .map(|(f, _)| *f)
+            .chain(self.newest.as_ref().map(|(f, _)| *f))
+    }
+    /// How many states are held.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.deltas.len() + usize::from(self.newest.is_some())
+    }
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+    /// Bytes of state data held, which [`RewindBuffer::capture`] keeps within the budget where possible.
+    #[must_use]
+    pub fn memory_used(&self) -> usize {
+        self.delta_bytes + self.newest.as_ref().map_or(0, |(_, state)| state.len())
+    }
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    fn truncate_after(&mut self, frame: u64) {
+        while self.newest.as_ref().is_some_and(|(f, _)| *f > frame) {
+            self.step_back();
+        }
+    }
+    // Replaces the newest state with the one before it, or empties the buffer
+    fn step_back(&mut self) {
+        let Some((_, newest_state)) = self.newest.take() else {
+            return;
+        };
+        if let Some((frame, delta)) = self.deltas.pop_back() {
+            self.delta_bytes -= delta.len();
+            self.newest = Some((frame, apply_delta(&newest_state, &delta)));
+        }
+    }
+}
+
+// Delta layout: the older state's length as a u64, then chunks of
+// (u32 count of unchanged bytes since the last chunk, u32 length, bytes of the older state)
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    fn push_u32(out: &mut Vec<u8>, n: usize) {
+        #[allow(clippy::cast_possible_truncation)]
+        out.extend_from_slice(&(n as u32).to_le_bytes());
+    }
+    let same = |i: usize| newer.get(i) == Some(&older[i]);
+    let mut out = Vec::new();
+    out.extend_from_slice(&(older.len() as u64).to_le_bytes());
+    let mut last_end = 0;
+    let mut i = 0;
+    while i < older.len() {
+        if same(i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i;
+        while i < older.len() && i - end < MIN_GAP {
+            if !same(i) {
+                end = i + 1;
+            }
+            i += 1;
+        }
+        push_u32(&mut out, start - last_end);
+        push_u32(&mut out, end - start);
+        out.extend_from_slice(&older[start..end]);
+        last_end = end;
+        i = end;
+    }
+    out
+}
+
+fn apply_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let read_u32 = |at: usize| u32::from_le_bytes(delta[at..at + 4].try_into().unwrap()) as usize;
+    let len = usize::try_from(u64::from_le_bytes(delta[..8].try_into().unwrap())).unwrap();
+    let mut older = newer[..len.min(newer.len())].to_vec();
+    older.resize(len, 0);
+    let mut pos = 0;
+    let mut at = 8;
+    while at < delta.len() {
+        pos += read_u32(at);
+        let n = read_u32(at + 4);
+        at += 8;
+        older[pos..pos + n].copy_from_slice(&delta[at..at + n]);
+        pos += n;
+        at += n;
+    }
+    older
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_round_trip() {
+        let newer: Vec<u8> = (0..200).map(|i| (i % 251) as u8).collect();
+        let mut older = newer.clone();
+        older[3] = 0xff;
+        older[5] = 0xfe;
+        older[100..120].fill(7);
+        older.truncate(180);
+        let delta = encode_delta(&older, &newer);
+        assert!(delta.len() < 8 + 2 * 8 + 30);
+        assert_eq!(apply_delta(&newer, &delta), older);
+        // A longer older state pulls its tail from the delta
+        let delta = encode_delta(&newer, &older);
+        assert_eq!(apply_delta(&older, &delta), newer);
+        assert_eq!(encode_delta(&newer, &newer).len(), 8);
+    }
+}
@@ -0,0 +1,350 @@
+//! Savestate files: the bytes from [`Emulator::save`] along with where they came from.
+//!
+//! # File format
+//! Integers and strings are encoded as in [movie files](crate::movie#file-format).
+//! ```text
+//! magic        8 bytes   "RRSSTATE"
+//! version      u32       1
+//! core_name    string    library_name from retro_get_system_info
+//! core_version string    library_version from retro_get_system_info
+//! rom_sha1     string    lowercase hex SHA-1 of the ROM file
+//! frame_count  u64       Emulator::frame_count when the state was saved
+//! compression  u8        0: none, 1: deflate (zlib), 2: zstd
+//! body         the rest of the file, compressed as above:
+//!   thumb_w    u32       0 if there is no thumbnail
+//!   thumb_h    u32
+//!   thumbnail  thumb_w * thumb_h * 3 bytes of RGB888
+//!   state      the remaining bytes
+//! ```
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use crate::format::{ByteReader, put_string};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RRSSTATE";
+const VERSION: u32 = 1;
+
+/// How the body of a savestate file is compressed.
+/// Every kind can be named, but [`Compression::Deflate`] needs the `use_deflate` feature and
+/// [`Compression::Zstd`] needs `use_zstd` to actually read or write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    fn tag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+            Compression::Zstd => 2,
+        }
+    }
+    fn from_tag(tag: u8) -> Result<Self, RetroRsError> {
+        match tag {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            2 => Ok(Compression::Zstd),
+            other => Err(RetroRsError::SavestateFormatError(format!(
+                "unknown compression {other}"
+            ))),
+        }
+    }
+    fn compress(self, body: Vec<u8>) -> Result<Vec<u8>, RetroRsError> {
+        match self {
+            Compression::None => Ok(body),
+            #[cfg(feature = "use_deflate")]
+            Compression::Deflate => {
+                let mut enc =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                enc.write_all(&body)
+                    .map_err(RetroRsError::SavestateIOError)?;
+                enc.finish().map_err(RetroRsError::SavestateIOError)
+            }
+            #[cfg(feature = "use_zstd")]
+            Compression::Zstd => {
+                zstd::encode_all(body.as_slice(), 0).map_err(RetroRsError::SavestateIOError)
+            }
+            #[allow(unreachable_patterns)]
+            other => Err(other.unsupported()),
+        }
+    }
+    fn decompress(self, body: &[u8]) -> Result<Vec<u8>, RetroRsError> {
+        match self {
+            Compression::None => Ok(body.to_vec()),
+            #[cfg(feature = "use_deflate")]
+            Compression::Deflate => {
+                let mut out = Vec::new();
+                flate2::read::ZlibDecoder::new(body)
+                    .read_to_end(&mut out)
+                    .map_err(|err| RetroRsError::SavestateFormatError(err.to_string()))?;
+                Ok(out)
+            }
+            #[cfg(feature = "use_zstd")]
+            Compression::Zstd => zstd::decode_all(body)
+                .map_err(|err| RetroRsError::SavestateFormatError(err.to_string())),
+            #[allow(unreachable_patterns)]
+            other => Err(other.unsupported()),
+        }
+    }
+    #[allow(dead_code)]
+    fn unsupported(self) -> RetroRsError {
+        let feature = if self == Compression::Zstd {
+            "use_zstd"
+        } else {
+            "use_deflate"
+        };
+        RetroRsError::SavestateFormatError(format!(
+            "{self:?} compression needs the {feature} feature"
+        ))
+    }
+}
+
+/// A small RGB888 picture of the screen when a state was saved.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    /// `width * height` pixels, three bytes each, in rows from the top.
+    pub rgb: Vec<u8>,
+}
+
+/// A savestate which knows the core, ROM and frame it was saved at.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Savestate {
+    pub core_name: String,
+    pub core_version: String,
+    pub rom_sha1: String,
+    pub frame_count: u64,
+    pub thumbnail: Option<Thumbnail>,
+    /// The bytes written by [`Emulator::save`].
+    pub state: Vec<u8>,
+}
+
+impl Savestate {
+    /// Saves `emu`'s current state, along with a copy of its framebuffer if `thumbnail` is set
+    /// and a frame has been drawn.
+    /// # Panics
+    /// If `thumbnail` is set and the core's pixel format is not supported for reads
+    /// # Errors
+    /// [`RetroRsError::StateSaveError`]: The core couldn't save its state
+    pub fn capture(emu: &Emulator, thumbnail: bool) -> Result<Self, RetroRsError> {
+        let mut state = vec![0; emu.save_size()];
+        if !emu.save(&mut state) {
+            return Err(RetroRsError::StateSaveError);
+        }
+        let thumbnail = if thumbnail {
+            let (w, h) = emu.framebuffer_size();
+            let mut rgb = vec![0; w * h * 3];
+            match emu.copy_framebuffer_rgb888(&mut rgb) {
+                Ok(()) => Some(Thumbnail {
+                    width: u32::try_from(w)?,
+                    height: u32::try_from(h)?,
+                    rgb,
+                }),
+                Err(RetroRsError::NoFramebufferError) => None,
+                Err(err) => return Err(err),
+            }
+        } else {
+            None
+        };
+        Ok(Savestate {
+            core_name: emu.get_library_name(),
+            core_version: emu.get_library_version(),
+            rom_sha1: emu.rom_sha1().to_owned(),
+            frame_count: emu.frame_count(),
+            thumbnail,
+            state,
+        })
+    }
+    /// Checks that `emu` is running the same core, core version and ROM this state was saved from.
+    /// # Errors
+    /// [`RetroRsError::SavestateMismatchError`]: The core name, core version or ROM hash differs
+    pub fn check(&self, emu: &Emulator) -> Result<(), RetroRsError> {
+        let core_name = emu.get_library_name();
+        let core_version = emu.get_library_version();
+        if core_name != self.core_name || core_version != self.core_version {
+            return Err(RetroRsError::SavestateMismatchError(format!(
+                "state was saved by core {} {}, not {core_name} {core_version}",
+                self.core_name, self.core_version
+            )));
+        }
+        if emu.rom_sha1() != self.rom_sha1 {
+            return Err(RetroRsError::SavestateMismatchError(format!(
+                "state was saved with ROM {}, not {}",
+                self.rom_sha1,
+                emu.rom_sha1()
+            )));
+        }
+        Ok(())
+    }
+    /// Checks `emu` against the state, loads it, and sets `emu`'s frame count to the one it was saved at.
+    /// # Errors
+    /// [`RetroRsError::SavestateMismatchError`]: See [`Savestate::check`]
+    /// [`RetroRsError::StateLoadError`]: The core couldn't load the state
+    pub fn restore(&self, emu: &mut Emulator) -> Result<(), RetroRsError> {
+        self.check(emu)?;
+        if !emu.load(&self.state) {
+            return Err(RetroRsError::StateLoadError);
+        }
+        emu.set_frame_count(self.frame_count);
+        Ok(())
+    }
+
+    /// # Errors
+    /// [`RetroRsError::SavestateIOError`]: Writing or compressing failed
+    /// [`RetroRsError::SavestateFormatError`]: `compression` needs a feature which isn't enabled
+    pub fn write(&self, mut w: impl Write, compression: Compression) -> Result<(), RetroRsError> {
+        let mut buf = Vec::with_capacity(64);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        for s in [&self.core_name, &self.core_version, &self.rom_sha1] {
+            put_string(&mut buf, s)?;
+        }
+        buf.extend_from_slice(&self.frame_count.to_le_bytes());
+        buf.push(compression.tag());
+        let thumb_len = self.thumbnail.as_ref().map_or(0, |t| t.rgb.len());
+        let mut body = Vec::with_capacity(8 + thumb_len + self.state.len());
+        match &self.thumbnail {
+            Some(thumb) => {
+                body.extend_from_slice(&thumb.width.to_le_bytes());
+                body.extend_from_slice(&thumb.height.to_le_bytes());
+                body.extend_from_slice(&thumb.rgb);
+            }
+            None => body.extend_from_slice(&[0; 8]),
+        }
+        body.extend_from_slice(&self.state);
+        buf.extend_from_slice(&compression.compress(body)?);
+        w.write_all(&buf).map_err(RetroRsError::SavestateIOError)
+    }
+    /// # Errors
+    /// [`RetroRsError::SavestateIOError`]: Reading failed
+    /// [`RetroRsError::SavestateFormatError`]: The data is not a savestate in a supported version of the format,
+    /// or is compressed in a way which needs a feature that isn't enabled
+    pub fn read(mut r: impl Read) -> Result<Self, RetroRsError> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)
+            .map_err(RetroRsError::SavestateIOError)?;
+        let mut data = ByteReader::new(&bytes, RetroRsError::SavestateFormatError);
+        if data.take(MAGIC.len())? != MAGIC {
+            return Err(data.error("not a retro-rs savestate"));
+        }
+        let version = u32::from_le_bytes(data.take_array()?);
+        if version != VERSION {
+            return Err(data.error(format!("unsupported version {version}")));
+        }
+        let core_name = data.take_string()?;
+        let core_version = data.take_string()?;
+        let rom_sha1 = data.take_string()?;
+        let frame_count = u64::from_le_bytes(data.take_array()?);
+        let compression = Compression::from_tag(data.take_array::<1>()?[0])?;
+        let body = compression.decompress(data.rest())?;
+        let mut body = ByteReader::new(&body, RetroRsError::SavestateFormatError);
+        let width = u32::from_le_bytes(body.take_array()?);
+        let height = u32::from_le_bytes(body.take_array()?);
+        let thumbnail = if width == 0 {
+            None
+        } else {
+            // The size is untrusted, so it mustn't overflow
+            let len = u64::from(width)
+                .checked_mul(u64::from(height))
+                .and_then(|pixels| pixels.checked_mul(3))
+                .ok_or_else(|| body.error("thumbnail size overflows"))?;
+            let len = usize::try_from(len)?;
+            Some(Thumbnail {
+                width,
+                height,
+                rgb: body.take(len)?.to_vec(),
+            })
+        };
+        Ok(Savestate {
+            core_name,
+            core_version,
+            rom_sha1,
+            frame_count,
+            thumbnail,
+            state: body.rest().to_vec(),
+        })
+    }
+    /// # Errors
+    /// See [`Savestate::write`]
+    pub fn save_file(&self, path: &Path, compression: Compression) -> Result<(), RetroRsError> {
+        let file = std::fs::File::create(path).map_err(RetroRsError::SavestateIOError)?;
+        self.write(std::io::BufWriter::new(file), compression)
+    }
+    /// # Errors
+    /// See [`Savestate::read`]
+    pub fn load_file(path: &Path) -> Result<Self, RetroRsError> {
+        let file = std::fs::File::open(path).map_err(RetroRsError::SavestateIOError)?;
+        Self::read(std::io::BufReader::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn savestate_round_trip() {
+        let state = Savestate {
+            core_name: "FCEUmm".to_owned(),
+            core_version: "(SVN) 1234".to_owned(),
+            rom_sha1: "ea343f4e445a9050d4b4fbac2c77d0693b1d0922".to_owned(),
+            frame_count: 600,
+            thumbnail: Some(Thumbnail {
+                width: 2,
+                height: 1,
+                rgb: vec![255, 0, 0, 0, 0, 255],
+            }),
+            state: vec![7; 300],
+        };
+        let mut kinds = vec![Compression::None];
+        if cfg!(feature = "use_deflate") {
+            kinds.push(Compression::Deflate);
+        }
+        if cfg!(feature = "use_zstd") {
+            kinds.push(Compression::Zstd);
+        }
+        for compression in kinds {
+            let mut bytes = Vec::new();
+            state.write(&mut bytes, compression).unwrap();
+            assert_eq!(Savestate::read(bytes.as_slice()).unwrap(), state);
+        }
+        let mut bytes = Vec::new();
+        state.write(&mut bytes, Compression::None).unwrap();
+        bytes[0] = b'X';
+        assert!(matches!(
+            Savestate::read(bytes.as_slice()),
+            Err(RetroRsError::SavestateFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_thumbnail_size() {
+        let state = Savestate {
+            core_name: String::new(),
+            core_version: String::new(),
+            rom_sha1: String::new(),
+            frame_count: 0,
+            thumbnail: Some(Thumbnail {
+                width: 1,
+                height: 1,
+                rgb: vec![0; 3],
+            }),
+            state: Vec::new(),
+        };
+        let mut bytes = Vec::new();
+        state.write(&mut bytes, Compression::None).unwrap();
+        // The body is the thumbnail's width, height and pixels
+        let size_at = bytes.len() - 11;
+        bytes[size_at..size_at + 8].fill(0xFF);
+        assert!(matches!(
+            Savestate::read(bytes.as_slice()),
+            Err(RetroRsError::SavestateFormatError(why)) if why.contains("overflows")
+        ));
+    }
+}
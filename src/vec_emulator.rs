@@ -0,0 +1,451 @@
+use crate::buttons::Buttons;
+use crate::emulator::Emulator;
+use crate::error::RetroRsError;
+use crate::pixels::rgb888_to_rgb332;
+use crate::remote::RemoteEmulator;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+/// The pixel layout of the observations written by [`VecEmulator::step`] and [`VecEmulator::observe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObservationFormat {
+    /// Three bytes per pixel, as from [`Emulator::copy_framebuffer_rgb888`]
+    Rgb888,
+    /// One byte per pixel, as from [`Emulator::copy_framebuffer_rgb332`]
+    Rgb332,
+}
+
+impl ObservationFormat {
+    #[must_use]
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            ObservationFormat::Rgb888 => 3,
+            ObservationFormat::Rgb332 => 1,
+        }
+    }
+}
+
+/// A batch of emulators stepped in lockstep, each on its own thread or in its own worker process.
+///
+/// Observations of every emulator are written one after another into a single buffer, so a batch
+/// of `n` frames of `w`x`h` pixels takes `n * w * h * format.bytes_per_pixel()` bytes.
+pub struct VecEmulator {
+    members: Members,
+    frame_skip: usize,
+    max_pool: bool,
+}
+
+enum Members {
+    Threads(Vec<EmulatorThread>),
+    Workers(Vec<RemoteEmulator>),
+}
+
+impl VecEmulator {
+    /// Starts `count` threads which each create an emulator with `make`, given the emulator's index.
+    /// `make` typically calls [`Emulator::builder`] with the same core and ROM every time.
+    /// # Errors
+    /// [`RetroRsError::EmulatorThreadSpawnError`]: A thread couldn't be started
+    /// [`RetroRsError::EmulatorThreadExitedError`]: `make` panicked
+    /// Others: The first error returned by `make`
+    pub fn with_threads<F>(count: usize, make: F) -> Result<Self, RetroRsError>
+    where
+        F: Fn(usize) -> Result<Emulator, RetroRsError> + Send + Sync + 'static,
+    {
+        let make = Arc::new(make);
+        let mut threads = Vec::with_capacity(count);
+        let mut ready = Vec::with_capacity(count);
+        for index in 0..count {
+            let make = Arc::clone(&make);
+            let (thread, created) = EmulatorThread::spawn(move || make(index))?;
+            threads.push(thread);
+            ready.push(created);
+        }
+        for created in ready {
+            created
+                .recv()
+                .map_err(|_| RetroRsError::EmulatorThreadExitedError)??;
+        }
+        Ok(Self::new(Members::Threads(threads)))
+    }
+    /// Starts `count` worker processes with `spawn`, given the emulator's index,
+    /// e.g. by calling [`RemoteEmulator::spawn`] with the same core and ROM every time.
+    /// # Errors
+    /// The first error returned by `spawn`
+    pub fn with_workers<F>(count: usize, mut spawn: F) -> Result<Self, RetroRsError>
+    where
+        F: FnMut(usize) -> Result<RemoteEmulator, RetroRsError>,
+    {
+        let workers = (0..count).map(&mut spawn).collect::<Result<_, _>>()?;
+        Ok(Self::new(Members::Workers(workers)))
+    }
+    fn new(members: Members) -> Self {
+        VecEmulator {
+            members,
+            frame_skip: 1,
+            max_pool: false,
+        }
+    }
+    #[must_use]
+    pub fn len(&self) -> usize {
+        match &self.members {
+            Members::Threads(threads) => threads.len(),
+            Members::Workers(workers) => workers.len(),
+        }
+    }
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Makes each [`VecEmulator::step`] run `frames` frames with the same inputs.
+    /// With `max_pool`, each observation is the channel-wise maximum of the last two frames,
+    /// which hides sprites that flicker on alternate frames.
+    /// # Panics
+    /// If `frames` is 0, or `max_pool` is set and `frames` is 1, since each step must run both
+    /// frames it pools
+    pub fn set_frame_skip(&mut self, frames: usize, max_pool: bool) {
+        assert!(frames > 0, "frame skip must be at least one frame");
+        assert!(
+            frames > 1 || !max_pool,
+            "max pooling needs a frame skip of at least two frames"
+        );
+        self.frame_skip = frames;
+        self.max_pool = max_pool;
+    }
+    /// The framebuffer size of the first emulator, which all the emulators are expected to share.
+    /// # Errors
+    /// [`RetroRsError::NoFramebufferError`]: There are no emulators
+    /// Others: The emulator's thread or worker is gone, see [`VecEmulator::step`]
+    pub fn framebuffer_size(&mut self) -> Result<(usize, usize), RetroRsError> {
+        if self.is_empty() {
+            return Err(RetroRsError::NoFramebufferError);
+        }
+        self.call(0, (), |member, ()| member.framebuffer_size())
+    }
+    /// Bytes taken by one emulator's observation in `format`, which is 0 until a frame has been run.
+    /// # Errors
+    /// See [`VecEmulator::framebuffer_size`]
+    pub fn observation_size(&mut self, format: ObservationFormat) -> Result<usize, RetroRsError> {
+        let (w, h) = self.framebuffer_size()?;
+        Ok(w * h * format.bytes_per_pixel())
+    }
+    /// Runs every emulator for the frame skip with its own inputs, in parallel,
+    /// then writes their observations one after another into `out`.
+    /// # Panics
+    /// If `inputs` doesn't have one entry per emulator
+    /// # Errors
+    /// [`RetroRsError::ObservationSizeError`]: `out` isn't exactly the size of the observations
+    /// [`RetroRsError::EmulatorThreadExitedError`]: An emulator's thread panicked, e.g. in its core
+    /// Others: The first error from any emulator, e.g. [`RetroRsError::RemoteWorkerExitedError`] if a worker crashed
+    pub fn step(
+        &mut self,
+        inputs: &[[Buttons; 2]],
+        format: ObservationFormat,
+        out: &mut [u8],
+    ) -> Result<(), RetroRsError> {
+        assert_eq!(inputs.len(), self.len(), "need inputs for every emulator");
+        let (frames, max_pool) = (self.frame_skip, self.max_pool);
+        let args = inputs
+            .iter()
+            .map(|&inputs| (inputs, frames, max_pool, format))
+            .collect();
+        let observations = self.broadcast(args, |member, (inputs, frames, max_pool, format)| {
+            let mut previous = None;
+            for frame in 0..frames {
+                member.run(inputs)?;
+                if max_pool && frame + 2 == frames {
+                    previous = Some(member.rgb888()?);
+                }
+            }
+            Ok(observation(member.rgb888()?, previous.as_deref(), format))
+        })?;
+        write_observations(&observations, out)
+    }
+    /// Writes every emulator's current observation into `out` as in [`VecEmulator::step`],
+    /// without running any frames, e.g. after [`VecEmulator::reset`].
+    /// # Errors
+    /// See [`VecEmulator::step`]
+    pub fn observe(
+        &mut self,
+        format: ObservationFormat,
+        out: &mut [u8],
+    ) -> Result<(), RetroRsError> {
+        let args = vec![format; self.len()];
+        let observations = self.broadcast(args, |member, format| {
+            Ok(observation(member.rgb888()?, None, format))
+        })?;
+        write_observations(&observations, out)
+    }
+    /// Resets emulator `index` by loading `state`, as produced by [`Emulator::save`] or
+    /// [`VecEmulator::save`], or by resetting its core if `state` is `None`.
+    /// # Panics
+    /// If `index` is out of bounds
+    /// # Errors
+    /// [`RetroRsError::StateLoadError`]: The core couldn't load the state
+    /// Others: The emulator's thread or worker is gone, see [`VecEmulator::step`]
+    pub fn reset(&mut self, index: usize, state: Option<&[u8]>) -> Result<(), RetroRsError> {
+        self.call(index, state.map(<[u8]>::to_vec), |member, state| {
+            member.restore(state.as_deref())
+        })
+    }
+    /// The serialized state of emulator `index`.
+    /// # Panics
+    /// If `index` is out of bounds
+    /// # Errors
+    /// [`RetroRsError::StateSaveError`]: The core couldn't save its state
+    /// Others: The emulator's thread or worker is gone, see [`VecEmulator::step`]
+    pub fn save(&mut self, index: usize) -> Result<Vec<u8>, RetroRsError> {
+        self.call(index, (), |member, ()| member.save())
+    }
+
+    // Calls `f` on every emulator in parallel with its own argument
+    fn broadcast<A, R>(&mut self, args: Vec<A>, f: MemberFn<A, R>) -> Result<Vec<R>, RetroRsError>
+    where
+        A: Send + 'static,
+        R: Send + 'static,
+    {
+        match &mut self.members {
+            Members::Threads(threads) => {
+                let replies: Vec<_> = threads
+                    .iter()
+                    .zip(args)
+                    .map(|(thread, arg)| thread.send(arg, f))
+                    .collect();
+                replies
+                    .into_iter()
+                    .map(|reply| {
+                        reply?
+                            .recv()
+                            .map_err(|_| RetroRsError::EmulatorThreadExitedError)?
+                    })
+                    .collect()
+            }
+            Members::Workers(workers) => thread::scope(|scope| {
+                let handles: Vec<_> = workers
+                    .iter_mut()
+                    .zip(args)
+                    .map(|(worker, arg)| scope.spawn(move || f(worker, arg)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or(Err(RetroRsError::EmulatorThreadExitedError))
+                    })
+                    .collect()
+            }),
+        }
+    }
+    fn call<A, R>(&mut self, index: usize, arg: A, f: MemberFn<A, R>) -> Result<R, RetroRsError>
+    where
+        A: Send + 'static,
+        R: Send + 'static,
+    {
+        match &mut self.members {
+            Members::Threads(threads) => threads[index]
+                .send(arg, f)?
+                .recv()
+                .map_err(|_| RetroRsError::EmulatorThreadExitedError)?,
+            Members::Workers(workers) => f(&mut workers[index], arg),
+        }
+    }
+}
+
+type MemberFn<A, R> = fn(&mut dyn Member, A) -> Result<R, RetroRsError>;
+
+// What a batch needs from an emulator, wherever it runs
+trait Member {
+    fn run(&mut self, inputs: [Buttons; 2]) -> Result<(), RetroRsError>;
+    fn framebuffer_size(&mut self) -> Result<(usize, usize), RetroRsError>;
+    fn rgb888(&mut self) -> Result<Vec<u8>, RetroRsError> {
+        let (w, h) = self.framebuffer_size()?;
+        let mut frame = vec![0; w * h * 3];
+        self.copy_rgb888(&mut frame)?;
+        Ok(frame)
+    }
+    fn copy_rgb888(&mut self, frame: &mut [u8]) -> Result<(), RetroRsError>;
+    fn restore(&mut self, state: Option<&[u8]>) -> Result<(), RetroRsError>;
+    fn save(&mut self) -> Result<Vec<u8>, RetroRsError>;
+}
+
+impl Member for Emulator {
+    fn run(&mut self, inputs: [Buttons; 2]) -> Result<(), RetroRsError> {
+        Emulator::run(self, inputs);
+        Ok(())
+    }
+    fn framebuffer_size(&mut self) -> Result<(usize, usize), RetroRsError> {
+        Ok(Emulator::framebuffer_size(self))
+    }
+    fn copy_rgb888(&mut self, frame: &mut [u8]) -> Result<(), RetroRsError> {
+        self.copy_framebuffer_rgb888(frame)
+    }
+    fn restore(&mut self, state: Option<&[u8]>) -> Result<(), RetroRsError> {
+        match state {
+            Some(state) if !self.load(state) => Err(RetroRsError::StateLoadError),
+            Some(_) => Ok(()),
+            None => {
+                self.reset();
+                Ok(())
+            }
+        }
+    }
+    fn save(&mut self) -> Result<Vec<u8>, RetroRsError> {
+        let mut state = vec![0; self.save_size()];
+        if Emulator::save(self, &mut state) {
+            Ok(state)
+        } else {
+            Err(RetroRsError::StateSaveError)
+        }
+    }
+}
+
+impl Member for RemoteEmulator {
+    fn run(&mut self, inputs: [Buttons; 2]) -> Result<(), RetroRsError> {
+        RemoteEmulator::run(self, inputs)
+    }
+    fn framebuffer_size(&mut self) -> Result<(usize, usize), RetroRsError> {
+        RemoteEmulator::framebuffer_size(self)
+    }
+    fn copy_rgb888(&mut self, frame: &mut [u8]) -> Result<(), RetroRsError> {
+        self.copy_framebuffer_rgb888(frame)
+    }
+    fn restore(&mut self, state: Option<&[u8]>) -> Result<(), RetroRsError> {
+        match state {
+            Some(state) => self.load(state),
+            None => self.reset(),
+        }
+    }
+    fn save(&mut self) -> Result<Vec<u8>, RetroRsError> {
+        RemoteEmulator::save(self)
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Emulator) + Send>;
+
+// A thread owning one emulator, since emulators can't move between threads
+struct EmulatorThread {
+    jobs: Option<Sender<Job>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EmulatorThread {
+    fn spawn<F>(make: F) -> Result<(Self, mpsc::Receiver<Result<(), RetroRsError>>), RetroRsError>
+    where
+        F: FnOnce() -> Result<Emulator, RetroRsError> + Send + 'static,
+    {
+        let (jobs, job_rx) = mpsc::channel::<Job>();
+        let (created, created_rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("retro-rs emulator".to_owned())
+            .spawn(move || {
+                let mut emu = match make() {
+                    Ok(emu) => emu,
+                    Err(err) => {
+                        let _ = created.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = created.send(Ok(()));
+                while let Ok(job) = job_rx.recv() {
+                    job(&mut emu);
+                }
+            })
+            .map_err(RetroRsError::EmulatorThreadSpawnError)?;
+        let thread = EmulatorThread {
+            jobs: Some(jobs),
+            handle: Some(handle),
+        };
+        Ok((thread, created_rx))
+    }
+    fn send<A, R>(
+        &self,
+        arg: A,
+        f: MemberFn<A, R>,
+    ) -> Result<mpsc::Receiver<Result<R, RetroRsError>>, RetroRsError>
+    where
+        A: Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, reply_rx) = mpsc::channel();
+        let job: Job = Box::new(move |emu| {
+            let _ = reply.send(f(emu, arg));
+        });
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .ok_or(RetroRsError::EmulatorThreadExitedError)?;
+        Ok(reply_rx)
+    }
+}
+
+impl Drop for EmulatorThread {
+    fn drop(&mut self) {
+        // Closing the job queue ends the thread, which drops its emulator
+        self.jobs = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+pub(crate) fn observation(
+    mut frame: Vec<u8>,
+    previous: Option<&[u8]>,
+    format: ObservationFormat,
+) -> Vec<u8> {
+    if let Some(previous) = previous {
+        for (px, prev) in frame.iter_mut().zip(previous) {
+            *px = (*px).max(*prev);
+        }
+    }
+    match format {
+        ObservationFormat::Rgb888 => frame,
+        ObservationFormat::Rgb332 => frame
+            .chunks_exact(3)
+            .map(|rgb| rgb888_to_rgb332(rgb[0], rgb[1], rgb[2]))
+            .collect(),
+    }
+}
+
+fn write_observations(observations: &[Vec<u8>], out: &mut [u8]) -> Result<(), RetroRsError> {
+    let size = observations.iter().map(Vec::len).sum();
+    if out.len() != size {
+        return Err(RetroRsError::ObservationSizeError(size, out.len()));
+    }
+    for (dst, obs) in out.iter_mut().zip(observations.iter().flatten()) {
+        *dst = *obs;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_pooled_observations() {
+        let older = [10, 200, 0, 255, 255, 255];
+        let newer = vec![20, 100, 0, 0, 0, 0];
+        let pooled = observation(newer.clone(), Some(&older), ObservationFormat::Rgb888);
+        assert_eq!(pooled, [20, 200, 0, 255, 255, 255]);
+        let pooled = observation(newer, Some(&older), ObservationFormat::Rgb332);
+        assert_eq!(pooled, [rgb888_to_rgb332(20, 200, 0), 0xff]);
+        let mut out = [0; 4];
+        write_observations(&[vec![1, 2], vec![3, 4]], &mut out).unwrap();
+        assert_eq!(out, [1, 2, 3, 4]);
+        // Observations are packed, so a buffer of any other size is a mistake
+        assert!(matches!(
+            write_observations(&[vec![1, 2], vec![3, 4]], &mut [0; 5]),
+            Err(RetroRsError::ObservationSizeError(4, 5))
+        ));
+        assert!(write_observations(&[vec![1, 2], vec![3]], &mut out).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "max pooling needs a frame skip of at least two frames")]
+    fn max_pooling_needs_two_frames() {
+        let mut batch = VecEmulator::with_workers(0, |_| unreachable!()).unwrap();
+        batch.set_frame_skip(1, true);
+    }
+}
@@ -0,0 +1,83 @@
+//! Runs the `retro-rs-worker` binary, which only integration tests can find.
+use retro_rs::{RemoteEmulator, RetroRsError};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const WORKER: &str = env!("CARGO_BIN_EXE_retro-rs-worker");
+
+#[test]
+fn missing_core_is_a_core_error() {
+    // Any file will do as the ROM, since the core fails to load first
+    let result = RemoteEmulator::spawn(
+        Path::new(WORKER),
+        Path::new("cores/no_such_core_libretro"),
+        Path::new("Cargo.toml"),
+    );
+    let Err(RetroRsError::RemoteCoreError(msg)) = result else {
+        panic!("expected a core error");
+    };
+    assert!(msg.contains("no_such_core_libretro"), "{msg}");
+}
+
+#[cfg(unix)]
+#[test]
+fn killed_worker_is_an_error() {
+    use std::os::unix::process::ExitStatusExt;
+    // A worker which is killed before it can reply, as if its core crashed
+    let mut command = Command::new("sh");
+    command.args(["-c", "kill -9 $$"]);
+    let result = RemoteEmulator::spawn_command(
+        command,
+        Path::new("cores/fceumm_libretro"),
+        Path::new("roms/mario.nes"),
+        &[],
+    );
+    let Err(RetroRsError::RemoteWorkerExitedError(status)) = result else {
+        panic!("expected the worker to have exited");
+    };
+    assert_eq!(status.signal(), Some(9));
+}
+
+// Sends one request in the worker's framing and returns the reply's status byte and the rest
+fn request(worker: &mut std::process::Child, body: &[u8]) -> (u8, Vec<u8>) {
+    let stdin = worker.stdin.as_mut().unwrap();
+    stdin
+        .write_all(&u32::try_from(body.len()).unwrap().to_le_bytes())
+        .unwrap();
+    stdin.write_all(body).unwrap();
+    stdin.flush().unwrap();
+    let stdout = worker.stdout.as_mut().unwrap();
+    let mut len = [0; 4];
+    stdout.read_exact(&mut len).unwrap();
+    let mut reply = vec![0; u32::from_le_bytes(len) as usize];
+    stdout.read_exact(&mut reply).unwrap();
+    let rest = reply.split_off(1);
+    (reply[0], rest)
+}
+
+#[test]
+fn unknown_ram_region_is_an_error() {
+    // RemoteEmulator only asks for known regions, so speak the protocol directly: create an
+    // emulator, then ask for RAM region 9
+    let mut worker = Command::new(WORKER)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut create = vec![0];
+    for path in [
+        "../../.config/retroarch/cores/fceumm_libretro",
+        "roms/mario.nes",
+    ] {
+        create.extend_from_slice(&u32::try_from(path.len()).unwrap().to_le_bytes());
+        create.extend_from_slice(path.as_bytes());
+    }
+    create.extend_from_slice(&0_u32.to_le_bytes());
+    assert_eq!(request(&mut worker, &create).0, 0);
+    let (status, msg) = request(&mut worker, &[8, 9]);
+    worker.kill().unwrap();
+    worker.wait().unwrap();
+    assert_eq!(status, 1);
+    assert!(String::from_utf8_lossy(&msg).contains("unknown RAM region 9"));
+}